@@ -0,0 +1,236 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use lua::{LuaHookEvent, LuaHookKind, LuaRef, LuaStackFrame};
+
+
+/// A line of a chunk that execution pauses at.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Breakpoint {
+    /// Name of the chunk as shown in error messages, such as "stdin:3" or the path of a file.
+    pub chunk: String,
+    pub line: u32,
+}
+
+
+/// How execution carries on after it was paused.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StepMode {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Pause at the next line, even if it is in a function called from the paused line.
+    Into,
+
+    /// Pause at the next line of the paused function, or of the function it returns to.
+    Over,
+
+    /// Pause at the next line of the function that the paused function returns to.
+    Out,
+}
+
+
+/// The function execution paused in, along with its variables as they were when it paused.
+pub struct PausedFrame {
+    pub frame: LuaStackFrame,
+    pub locals: Vec<(String, LuaRef)>,
+    pub upvalues: Vec<(String, LuaRef)>,
+}
+
+
+/// Decides where chunks entered into the console pause, from a hook called on every line.
+/// Pausing suspends the thread the chunk runs in, so the console can take input while the chunk
+/// is paused, and resume the thread to carry on.
+pub struct Debugger {
+    state: Rc<RefCell<DebuggerState>>,
+}
+
+
+/// State shared between the debugger and its hook.
+struct DebuggerState {
+    breakpoints: Vec<Breakpoint>,
+    step_mode: StepMode,
+
+    // Depth of the call stack when execution last paused, for stepping over and out.
+    paused_depth: usize,
+
+    // Line execution is paused at, and the line to skip once it is resumed. Lua reports the
+    // paused line again when resuming if it has more instructions left to run.
+    paused_line: Option<PausedLine>,
+    skipped_line: Option<PausedLine>,
+
+    // Frame that execution paused in, until the console takes it.
+    paused_frame: Option<PausedFrame>,
+}
+
+
+/// The chunk and line execution paused at, and the depth of the call stack at the time.
+type PausedLine = (String, Option<u32>, usize);
+
+
+impl Breakpoint {
+    /// Parses a breakpoint given as the chunk name and line separated by a colon, such as
+    /// "stdin:3:2" for line 2 of the third input.
+    pub fn parse(location: &str) -> Option<Breakpoint> {
+        let separator = location.rfind(':')?;
+        let chunk = location[.. separator].trim();
+        let line = location[separator + 1 ..].trim().parse().ok()?;
+        if chunk.is_empty() || line == 0 {
+            return None;
+        }
+
+        Some(Breakpoint{
+            chunk: String::from(chunk),
+            line,
+        })
+    }
+}
+
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.chunk, self.line)
+    }
+}
+
+
+impl PausedFrame {
+    /// Returns the chunk and line execution paused at, such as "stdin:3:2".
+    pub fn location(&self) -> String {
+        match self.frame.current_line {
+            Some(line) => format!("{}:{}", self.frame.source, line),
+            None => self.frame.source.clone(),
+        }
+    }
+}
+
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger{
+            state: Rc::new(RefCell::new(DebuggerState{
+                breakpoints: Vec::new(),
+                step_mode: StepMode::Continue,
+                paused_depth: 0,
+                paused_line: None,
+                skipped_line: None,
+                paused_frame: None,
+            })),
+        }
+    }
+
+    /// Returns true if execution could pause, so the hook is needed. The hook is called on
+    /// every line, so it is best removed while it is not needed.
+    pub fn is_active(&self) -> bool {
+        let state = self.state.borrow();
+        !state.breakpoints.is_empty() || state.step_mode != StepMode::Continue
+    }
+
+    /// Returns the hook to call on every line of Lua code, which pauses execution where the
+    /// debugger says to.
+    pub fn hook(&self) -> impl FnMut(&LuaHookEvent) {
+        let state = self.state.clone();
+        move |event| {
+            if event.kind == LuaHookKind::Line {
+                state.borrow_mut().on_line(event);
+            }
+        }
+    }
+
+    /// Adds a breakpoint. Returns false if there already was one at the same line.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let mut state = self.state.borrow_mut();
+        if state.breakpoints.contains(&breakpoint) {
+            return false;
+        }
+
+        state.breakpoints.push(breakpoint);
+        true
+    }
+
+    /// Removes a breakpoint. Returns false if there was none at that line.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let mut state = self.state.borrow_mut();
+        let count = state.breakpoints.len();
+        state.breakpoints.retain(|existing| existing != breakpoint);
+        state.breakpoints.len() != count
+    }
+
+    /// Returns every breakpoint, in the order they were added.
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.state.borrow().breakpoints.clone()
+    }
+
+    /// Sets how execution carries on once the paused chunk is resumed.
+    pub fn step(&mut self, step_mode: StepMode) {
+        self.state.borrow_mut().step_mode = step_mode;
+    }
+
+    /// Prepares for the paused chunk to be resumed.
+    pub fn resume(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.skipped_line = state.paused_line.take();
+    }
+
+    /// Takes the frame that execution paused in, if the chunk last resumed was paused rather
+    /// than yielding.
+    pub fn take_paused_frame(&mut self) -> Option<PausedFrame> {
+        self.state.borrow_mut().paused_frame.take()
+    }
+
+    /// Stops any step in progress, such as once the stepped chunk has finished.
+    pub fn stop_stepping(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.step_mode = StepMode::Continue;
+        state.paused_line = None;
+        state.skipped_line = None;
+    }
+}
+
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+
+impl DebuggerState {
+    /// Pauses execution if the line is where the current step ends, or has a breakpoint.
+    fn on_line(&mut self, event: &LuaHookEvent) {
+        if let Some(skipped_line) = self.skipped_line.take() {
+            if skipped_line == (event.frame.source.clone(), event.frame.current_line, event.stack_depth()) {
+                return;
+            }
+        }
+
+        let depth = match self.step_mode {
+            StepMode::Over | StepMode::Out => event.stack_depth(),
+            _ => 0,
+        };
+
+        let step_ends = match self.step_mode {
+            StepMode::Continue => false,
+            StepMode::Into => true,
+            StepMode::Over => depth <= self.paused_depth,
+            StepMode::Out => depth < self.paused_depth,
+        };
+        let at_breakpoint = self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.chunk == event.frame.source && Some(breakpoint.line) == event.frame.current_line
+        });
+
+        // Lines that cannot be paused at, such as in coroutines started by the chunk, are run
+        // through without ending the step.
+        if (step_ends || at_breakpoint) && event.suspend() {
+            self.step_mode = StepMode::Continue;
+            self.paused_depth = event.stack_depth();
+            self.paused_line = Some((event.frame.source.clone(), event.frame.current_line, self.paused_depth));
+            self.paused_frame = Some(PausedFrame{
+                frame: event.frame.clone(),
+                locals: event.locals(),
+                upvalues: event.upvalues(),
+            });
+        }
+    }
+}
@@ -1,5 +1,9 @@
 extern crate libc;
+#[cfg(feature = "serde")]
+extern crate serde;
 extern crate termion;
 
+pub mod debugger;
 pub mod lua;
+pub mod profiler;
 pub mod repl;
@@ -0,0 +1,278 @@
+use std::ffi::CString;
+
+use lua::{check_global_name, redirect_libraries, LuaChunkMode, LuaError, LuaExecutionLimits, LuaState, LuaTable};
+use lua::convert::ToLua;
+use lua::ffi::*;
+use lua::load::restrict_load_to_text;
+
+
+/// The standard libraries that can be opened in a Lua state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LuaLibrary {
+    Base,
+    Coroutine,
+    Debug,
+    Io,
+    Math,
+    Os,
+    Package,
+    String,
+    Table,
+    Utf8,
+}
+
+
+/// Builds a module table for a Lua state.
+type ModuleBuilder = Box<dyn FnOnce(&LuaState) -> Result<LuaTable, LuaError>>;
+
+
+/// Configures and creates a Lua state, choosing which standard libraries are available to the
+/// chunks it executes.
+pub struct LuaStateBuilder {
+    libraries: Vec<LuaLibrary>,
+    removed_functions: Vec<String>,
+    modules: Vec<(String, ModuleBuilder)>,
+    memory_limit: Option<usize>,
+    execution_limits: LuaExecutionLimits,
+    chunk_mode: LuaChunkMode,
+
+    // Whether Lua code may only load source code, whatever the chunk mode.
+    text_only_load: bool,
+}
+
+
+/// Every standard library, in the order luaL_openlibs opens them.
+const ALL_LIBRARIES: [LuaLibrary; 10] = [
+    LuaLibrary::Base,
+    LuaLibrary::Package,
+    LuaLibrary::Coroutine,
+    LuaLibrary::Table,
+    LuaLibrary::Io,
+    LuaLibrary::Os,
+    LuaLibrary::String,
+    LuaLibrary::Math,
+    LuaLibrary::Utf8,
+    LuaLibrary::Debug,
+];
+
+
+/// Libraries that cannot reach the file system, other processes or the internals of the runtime.
+const SANDBOXED_LIBRARIES: [LuaLibrary; 7] = [
+    LuaLibrary::Base,
+    LuaLibrary::Coroutine,
+    LuaLibrary::Table,
+    LuaLibrary::Os,
+    LuaLibrary::String,
+    LuaLibrary::Math,
+    LuaLibrary::Utf8,
+];
+
+
+/// Functions left out of the sandboxed libraries, since they access files, the environment or
+/// the process itself. string.dump is left out as well, since loading crafted bytecode can
+/// escape the sandbox.
+const SANDBOXED_REMOVED_FUNCTIONS: [&str; 10] = [
+    "dofile",
+    "loadfile",
+    "os.execute",
+    "os.exit",
+    "os.getenv",
+    "os.remove",
+    "os.rename",
+    "os.setlocale",
+    "os.tmpname",
+    "string.dump",
+];
+
+
+impl LuaLibrary {
+    /// Returns the name the library is registered under.
+    pub fn name(self) -> &'static str {
+        match self {
+            LuaLibrary::Base => "_G",
+            LuaLibrary::Coroutine => "coroutine",
+            LuaLibrary::Debug => "debug",
+            LuaLibrary::Io => "io",
+            LuaLibrary::Math => "math",
+            LuaLibrary::Os => "os",
+            LuaLibrary::Package => "package",
+            LuaLibrary::String => "string",
+            LuaLibrary::Table => "table",
+            LuaLibrary::Utf8 => "utf8",
+        }
+    }
+
+    /// Returns the C function that opens the library.
+    fn open_function(self) -> lua_CFunction {
+        match self {
+            LuaLibrary::Base => luaopen_base,
+            LuaLibrary::Coroutine => luaopen_coroutine,
+            LuaLibrary::Debug => luaopen_debug,
+            LuaLibrary::Io => luaopen_io,
+            LuaLibrary::Math => luaopen_math,
+            LuaLibrary::Os => luaopen_os,
+            LuaLibrary::Package => luaopen_package,
+            LuaLibrary::String => luaopen_string,
+            LuaLibrary::Table => luaopen_table,
+            LuaLibrary::Utf8 => luaopen_utf8,
+        }
+    }
+}
+
+
+impl LuaStateBuilder {
+    /// Creates a builder for a state with every standard library opened, the same as
+    /// LuaState::new.
+    pub fn new() -> LuaStateBuilder {
+        LuaStateBuilder{
+            libraries: ALL_LIBRARIES.to_vec(),
+            removed_functions: Vec::new(),
+            modules: Vec::new(),
+            memory_limit: None,
+            execution_limits: LuaExecutionLimits::default(),
+            chunk_mode: LuaChunkMode::Text,
+            text_only_load: false,
+        }
+    }
+
+    /// Creates a builder for a locked down state that can be handed to semi-trusted users. Only
+    /// libraries without access to files, other processes or the debug interface are opened,
+    /// and the remaining functions that access the file system or process are removed. Lua code
+    /// can only load source code, even if the chunk mode allows bytecode for the Rust API.
+    pub fn sandboxed() -> LuaStateBuilder {
+        let mut builder = LuaStateBuilder::new().libraries(&SANDBOXED_LIBRARIES);
+        builder.text_only_load = true;
+        SANDBOXED_REMOVED_FUNCTIONS.iter()
+            .fold(builder, |builder, path| builder.remove_function(path))
+    }
+
+    /// Opens exactly the given standard libraries instead of all of them.
+    pub fn libraries(mut self, libraries: &[LuaLibrary]) -> LuaStateBuilder {
+        self.libraries = libraries.to_vec();
+        self
+    }
+
+    /// Opens the given standard library in addition to those already chosen.
+    pub fn library(mut self, library: LuaLibrary) -> LuaStateBuilder {
+        if !self.libraries.contains(&library) {
+            self.libraries.push(library);
+        }
+        self
+    }
+
+    /// Removes the function at the given path, such as "io.popen" or "dofile", once the
+    /// libraries have been opened. Paths into libraries that are not opened are ignored.
+    pub fn remove_function(mut self, path: &str) -> LuaStateBuilder {
+        self.removed_functions.push(String::from(path));
+        self
+    }
+
+    /// Adds a module built in Rust. The module's table is stored as a global with the given
+    /// name, and is also returned by "require" if the package library is opened. Modules are
+    /// built in the order they were added, after the libraries are opened.
+    pub fn module<F>(mut self, name: &str, build: F) -> LuaStateBuilder
+        where F: 'static + FnOnce(&LuaState) -> Result<LuaTable, LuaError>
+    {
+        self.modules.push((String::from(name), Box::new(build)));
+        self
+    }
+
+    /// Limits how much memory the state may use while executing chunks.
+    pub fn memory_limit(mut self, limit: usize) -> LuaStateBuilder {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Limits how long every chunk executed by the state may run.
+    pub fn execution_limits(mut self, limits: LuaExecutionLimits) -> LuaStateBuilder {
+        self.execution_limits = limits;
+        self
+    }
+
+    /// Chooses which kinds of chunks execute_file and execute_bytes accept. Only source code is
+    /// accepted unless precompiled bytecode is explicitly allowed.
+    pub fn chunk_mode(mut self, mode: LuaChunkMode) -> LuaStateBuilder {
+        self.chunk_mode = mode;
+        self
+    }
+
+    /// Creates the configured state. Fails if one of the modules could not be built or has an
+    /// invalid name.
+    pub fn build(self) -> Result<LuaState, LuaError> {
+        let mut lua_state = LuaState::create(self.memory_limit);
+        lua_state.set_execution_limits(self.execution_limits);
+        lua_state.set_chunk_mode(self.chunk_mode);
+
+        for library in self.libraries {
+            unsafe{ open_library(lua_state.state, library) };
+        }
+
+        for path in &self.removed_functions {
+            remove_function(&lua_state, path)?;
+        }
+        unsafe {
+            redirect_libraries(lua_state.state);
+            if self.text_only_load {
+                restrict_load_to_text(lua_state.state);
+            }
+        }
+
+        for (name, build) in self.modules {
+            check_global_name(&name)?;
+            let module = build(&lua_state)?;
+            lua_state.globals().raw_set(name.as_str(), &module)?;
+            unsafe {
+                module.to_lua(lua_state.state);
+                set_loaded_module(lua_state.state, &name);
+            }
+        }
+
+        Ok(lua_state)
+    }
+}
+
+
+impl Default for LuaStateBuilder {
+    fn default() -> LuaStateBuilder {
+        LuaStateBuilder::new()
+    }
+}
+
+
+/// Opens the given library, storing it as a global and as a loaded module.
+unsafe fn open_library(L: *mut lua_State, library: LuaLibrary) {
+    let name = CString::new(library.name()).unwrap();
+    luaL_requiref(L, name.as_ptr(), library.open_function(), 1);
+    lua_pop(L, 1); // Pop the library table left by luaL_requiref
+}
+
+
+/// Removes the value at the given dotted path from the globals table. Nothing is removed if
+/// one of the tables along the path does not exist.
+fn remove_function(lua_state: &LuaState, path: &str) -> Result<(), LuaError> {
+    let mut names: Vec<&str> = path.split('.').collect();
+    let function_name = names.pop().unwrap();
+
+    let mut table = lua_state.globals();
+    for name in names {
+        match table.raw_get::<_, Option<LuaTable>>(name)? {
+            Some(subtable) => table = subtable,
+            None => return Ok(()),
+        }
+    }
+
+    table.raw_set(function_name, None::<bool>)
+}
+
+
+/// Pops the module on top of the stack and stores it in the table of loaded modules, where
+/// "require" looks for it.
+unsafe fn set_loaded_module(L: *mut lua_State, name: &str) {
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    lua_insert(L, -2); // Place the loaded table under the module
+
+    let name = CString::new(name).unwrap();
+    lua_setfield(L, -2, name.as_ptr());
+    lua_pop(L, 1); // Pop the loaded table
+}
@@ -0,0 +1,329 @@
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_int};
+
+use libc;
+
+use lua::ffi::*;
+use lua::string::LuaString;
+use lua::value::{read_value, LuaValue};
+
+
+/// Describes why a value on the Lua stack could not be converted into a Rust value.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LuaConversionError {
+    pub message: String,
+}
+
+
+/// Values that can be read from the Lua stack.
+pub trait FromLua: Sized {
+    /// Reads the value at the given stack index without removing it from the stack.
+    ///
+    /// # Safety
+    /// The state must be valid and the index must be an acceptable index into its stack.
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<Self, LuaConversionError>;
+}
+
+
+/// Values that can be pushed onto the Lua stack.
+pub trait ToLua {
+    /// Pushes the value onto the top of the stack.
+    ///
+    /// # Safety
+    /// The state must be valid and must have room on its stack for the value.
+    unsafe fn to_lua(self, L: *mut lua_State);
+}
+
+
+/// A sequence of values read from consecutive stack slots, such as the arguments passed to a
+/// function.
+pub trait FromLuaMulti: Sized {
+    /// Reads the given number of values starting at the stack index "first". On failure, the
+    /// one-based position of the value that could not be converted is returned with the error.
+    ///
+    /// # Safety
+    /// The state must be valid and every index read must be an acceptable index into its stack.
+    unsafe fn from_lua_multi(L: *mut lua_State, first: c_int, count: c_int)
+        -> Result<Self, (c_int, LuaConversionError)>;
+}
+
+
+/// A sequence of values pushed onto the stack, such as the results returned from a function.
+pub trait ToLuaMulti {
+    /// Pushes all of the values onto the stack and returns how many values were pushed.
+    ///
+    /// # Safety
+    /// The state must be valid.
+    unsafe fn to_lua_multi(self, L: *mut lua_State) -> c_int;
+}
+
+
+/// Any number of values of the same type, used to accept or return a variable number of values.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LuaVariadic<T>(pub Vec<T>);
+
+
+impl LuaConversionError {
+    /// Creates an error describing that the value at the given stack index is not of the
+    /// expected type.
+    ///
+    /// # Safety
+    /// The state must be valid and the index must be an acceptable index into its stack.
+    pub unsafe fn type_mismatch(L: *mut lua_State, idx: c_int, expected: &str) -> LuaConversionError {
+        let found = CStr::from_ptr(lua_typename(L, lua_type(L, idx)));
+        LuaConversionError{
+            message: format!("{} expected, got {}", expected, found.to_string_lossy()),
+        }
+    }
+}
+
+
+impl fmt::Display for LuaConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+
+impl FromLua for LuaValue {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaValue, LuaConversionError> {
+        Ok(read_value(L, idx))
+    }
+}
+
+
+impl FromLua for bool {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<bool, LuaConversionError> {
+        if lua_type(L, idx) == LUA_TBOOLEAN {
+            Ok(lua_toboolean(L, idx) != 0)
+        } else {
+            Err(LuaConversionError::type_mismatch(L, idx, "boolean"))
+        }
+    }
+}
+
+
+impl FromLua for i64 {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<i64, LuaConversionError> {
+        let mut is_integer = 0;
+        let value = lua_tointegerx(L, idx, &mut is_integer);
+
+        if is_integer != 0 {
+            Ok(value)
+        } else if lua_type(L, idx) == LUA_TNUMBER {
+            Err(LuaConversionError{ message: String::from("number has no integer representation") })
+        } else {
+            Err(LuaConversionError::type_mismatch(L, idx, "number"))
+        }
+    }
+}
+
+
+impl FromLua for f64 {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<f64, LuaConversionError> {
+        let mut is_number = 0;
+        let value = lua_tonumberx(L, idx, &mut is_number);
+
+        if is_number != 0 {
+            Ok(value)
+        } else {
+            Err(LuaConversionError::type_mismatch(L, idx, "number"))
+        }
+    }
+}
+
+
+impl FromLua for f32 {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<f32, LuaConversionError> {
+        f64::from_lua(L, idx).map(|value| value as f32)
+    }
+}
+
+
+impl FromLua for String {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<String, LuaConversionError> {
+        let value = LuaString::from_lua(L, idx)?;
+        String::from_utf8(value.into_bytes())
+            .map_err(|_| LuaConversionError{ message: String::from("string is not valid UTF-8") })
+    }
+}
+
+
+impl<T: FromLua> FromLua for Option<T> {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<Option<T>, LuaConversionError> {
+        if lua_type(L, idx) <= LUA_TNIL {
+            Ok(None)
+        } else {
+            T::from_lua(L, idx).map(Some)
+        }
+    }
+}
+
+
+impl ToLua for bool {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushboolean(L, self as c_int);
+    }
+}
+
+
+impl ToLua for i64 {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushinteger(L, self);
+    }
+}
+
+
+impl ToLua for f64 {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushnumber(L, self);
+    }
+}
+
+
+impl ToLua for f32 {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushnumber(L, self as f64);
+    }
+}
+
+
+impl ToLua for &str {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushlstring(L, self.as_ptr() as *const c_char, self.len() as libc::size_t);
+    }
+}
+
+
+impl ToLua for String {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.as_str().to_lua(L);
+    }
+}
+
+
+impl<T: ToLua> ToLua for Option<T> {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        match self {
+            Some(value) => value.to_lua(L),
+            None => lua_pushnil(L),
+        }
+    }
+}
+
+
+/// Implements the conversions for integer types other than lua_Integer itself. Values are
+/// range checked when read from Lua.
+macro_rules! impl_integer {
+    ($($int:ty),*) => {
+        $(
+            impl FromLua for $int {
+                unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<$int, LuaConversionError> {
+                    let value = i64::from_lua(L, idx)?;
+                    <$int>::try_from(value)
+                        .map_err(|_| LuaConversionError{ message: String::from("number out of range") })
+                }
+            }
+
+            impl ToLua for $int {
+                unsafe fn to_lua(self, L: *mut lua_State) {
+                    lua_pushinteger(L, self as lua_Integer);
+                }
+            }
+        )*
+    }
+}
+
+impl_integer!(i8, i16, i32, isize, u8, u16, u32, usize);
+
+
+impl<T: FromLua> FromLuaMulti for T {
+    unsafe fn from_lua_multi(L: *mut lua_State, first: c_int, _count: c_int)
+        -> Result<T, (c_int, LuaConversionError)>
+    {
+        T::from_lua(L, first).map_err(|error| (1, error))
+    }
+}
+
+
+impl<T: ToLua> ToLuaMulti for T {
+    unsafe fn to_lua_multi(self, L: *mut lua_State) -> c_int {
+        self.to_lua(L);
+        1
+    }
+}
+
+
+impl<T: FromLua> FromLuaMulti for LuaVariadic<T> {
+    unsafe fn from_lua_multi(L: *mut lua_State, first: c_int, count: c_int)
+        -> Result<LuaVariadic<T>, (c_int, LuaConversionError)>
+    {
+        let mut values = Vec::with_capacity(count as usize);
+        for position in 1 .. count + 1 {
+            let value = T::from_lua(L, first + position - 1).map_err(|error| (position, error))?;
+            values.push(value);
+        }
+
+        Ok(LuaVariadic(values))
+    }
+}
+
+
+impl<T: ToLua> ToLuaMulti for LuaVariadic<T> {
+    unsafe fn to_lua_multi(self, L: *mut lua_State) -> c_int {
+        let num_values = self.0.len() as c_int;
+        assert!(lua_checkstack(L, num_values) != 0, "Not enough stack space for {} values", num_values);
+
+        for value in self.0 {
+            value.to_lua(L);
+        }
+
+        num_values
+    }
+}
+
+
+/// Implements the multiple value conversions for tuples, where each element of the tuple is
+/// one value on the stack.
+macro_rules! impl_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: FromLua),*> FromLuaMulti for ($($name,)*) {
+            #[allow(unused_variables, unused_mut)]
+            unsafe fn from_lua_multi(L: *mut lua_State, first: c_int, _count: c_int)
+                -> Result<($($name,)*), (c_int, LuaConversionError)>
+            {
+                let mut position = 0;
+                Ok(($({
+                    position += 1;
+                    $name::from_lua(L, first + position - 1).map_err(|error| (position, error))?
+                },)*))
+            }
+        }
+
+        impl<$($name: ToLua),*> ToLuaMulti for ($($name,)*) {
+            #[allow(unused_variables, unused_mut)]
+            unsafe fn to_lua_multi(self, L: *mut lua_State) -> c_int {
+                let ($($name,)*) = self;
+                let mut num_values = 0;
+                $(
+                    $name.to_lua(L);
+                    num_values += 1;
+                )*
+                num_values
+            }
+        }
+    }
+}
+
+impl_tuple!();
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+
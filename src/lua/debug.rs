@@ -0,0 +1,117 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_int};
+
+use lua::ffi::*;
+
+
+/// The kind of function running in a stack frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LuaFunctionKind {
+    /// A function written in Lua.
+    Lua,
+
+    /// A function written in C or Rust.
+    C,
+
+    /// The main function of a chunk.
+    Main,
+}
+
+
+/// One level of the call stack of a running Lua chunk.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LuaStackFrame {
+    /// Name of the function, if Lua could work out how it was called.
+    pub function_name: Option<String>,
+
+    /// Printable name of the chunk the function was defined in, or "[C]" for C functions.
+    pub source: String,
+
+    /// Line being executed, if the function is written in Lua.
+    pub current_line: Option<u32>,
+
+    /// Line the function was defined on, if it is written in Lua.
+    pub line_defined: Option<u32>,
+
+    /// Kind of function running in the frame.
+    pub what: LuaFunctionKind,
+}
+
+
+impl fmt::Display for LuaStackFrame {
+    /// Describes the frame the same way luaL_traceback does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.source)?;
+        if let Some(line) = self.current_line {
+            write!(f, "{}:", line)?;
+        }
+
+        match (self.what, &self.function_name, self.line_defined) {
+            (LuaFunctionKind::Main, _, _) => write!(f, " in main chunk"),
+            (_, Some(name), _) => write!(f, " in function '{}'", name),
+            (LuaFunctionKind::Lua, None, Some(line)) => write!(f, " in function <{}:{}>", self.source, line),
+            _ => write!(f, " in ?"),
+        }
+    }
+}
+
+
+/// Reads every frame of the call stack, starting at the given level where level 0 is the
+/// currently running function.
+///
+/// # Safety
+/// The state must be valid.
+pub unsafe fn read_stack_frames(L: *mut lua_State, first_level: c_int) -> Vec<LuaStackFrame> {
+    let mut frames = Vec::new();
+    let mut ar: lua_Debug = mem::zeroed();
+    let options = b"Slnt\0";
+
+    let mut level = first_level;
+    while lua_getstack(L, level, &mut ar) != 0 {
+        lua_getinfo(L, options.as_ptr() as *const c_char, &mut ar);
+        frames.push(read_frame(&ar));
+        level += 1;
+    }
+
+    frames
+}
+
+
+/// Converts the activation record filled in by lua_getinfo into a stack frame.
+pub unsafe fn read_frame(ar: &lua_Debug) -> LuaStackFrame {
+    let what = match optional_string(ar.what).as_deref() {
+        Some("main") => LuaFunctionKind::Main,
+        Some("Lua") => LuaFunctionKind::Lua,
+        _ => LuaFunctionKind::C,
+    };
+
+    LuaStackFrame{
+        function_name: optional_string(ar.name),
+        source: CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy().into_owned(),
+        current_line: optional_line(ar.currentline),
+        line_defined: optional_line(ar.linedefined),
+        what,
+    }
+}
+
+
+/// Converts a string that lua_getinfo may have left null.
+unsafe fn optional_string(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+    }
+}
+
+
+/// Converts a line number, which lua_getinfo sets to -1 when there is no line information.
+fn optional_line(line: c_int) -> Option<u32> {
+    if line > 0 {
+        Some(line as u32)
+    } else {
+        None
+    }
+}
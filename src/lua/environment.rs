@@ -0,0 +1,216 @@
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::path::Path;
+
+use lua::{chunk_name, compile_chunk, execute_function, get_execution_error, load_string, read_file};
+use lua::{LuaError, LuaIO, LuaRcode};
+use lua::ffi::*;
+use lua::output::call_original;
+use lua::registry::RegistryRef;
+use lua::table::LuaTable;
+use lua::thread::LuaThread;
+use lua::value::LuaValue;
+
+
+/// A separate set of global variables for running chunks in, such as for one of several console
+/// sessions sharing a state. Globals set by chunks run in the environment are kept in its own
+/// table, while globals it does not have, like the standard libraries, are looked up in the
+/// global table of the state. Chunks loaded with "load", "loadfile" or "dofile" from the
+/// environment run in it too, unless given an environment of their own. Tables reached through
+/// the globals, such as the standard libraries, and modules loaded with "require" are shared by
+/// every environment. Creating an environment is much cheaper than creating a new state.
+#[derive(Clone)]
+pub struct LuaEnvironment {
+    reference: RegistryRef,
+}
+
+
+impl LuaEnvironment {
+    /// Creates a new, empty environment that falls back to the global table of the given state.
+    pub(super) unsafe fn create(L: *mut lua_State) -> LuaEnvironment {
+        lua_pushglobaltable(L);
+        LuaEnvironment::create_over(L)
+    }
+
+    /// Pops the table on top of the stack and creates a new, empty environment that falls back
+    /// to it.
+    unsafe fn create_over(L: *mut lua_State) -> LuaEnvironment {
+        lua_newtable(L);
+
+        // Let _G refer to the environment, so that chunks setting globals through it do not
+        // reach the shared global table.
+        let global_name = CString::new("_G").unwrap();
+        lua_pushvalue(L, -1);
+        lua_setfield(L, -2, global_name.as_ptr());
+
+        // Chunks loaded by chunks run in the environment would otherwise run in the shared
+        // global table.
+        set_loader(L, "load", "load", load_into_environment);
+        set_loader(L, "loadfile", "loadfile", load_file_into_environment);
+        set_loader(L, "dofile", "loadfile", do_file_in_environment);
+
+        lua_newtable(L);
+        let index_name = CString::new("__index").unwrap();
+        lua_rotate(L, -3, -1); // Move the fallback table to the top
+        lua_setfield(L, -2, index_name.as_ptr());
+
+        // Hide the metatable from chunks, which could otherwise reach the fallback table
+        // through it and set globals seen by other environments.
+        let metatable_name = CString::new("__metatable").unwrap();
+        lua_pushboolean(L, 0);
+        lua_setfield(L, -2, metatable_name.as_ptr());
+        lua_setmetatable(L, -2);
+
+        LuaEnvironment{
+            reference: RegistryRef::pop_from(L),
+        }
+    }
+
+    /// Creates a new, empty environment that falls back to this one, so chunks run in it see
+    /// the globals of this environment but set globals of their own.
+    pub fn create_environment(&self) -> LuaEnvironment {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            LuaEnvironment::create_over(L)
+        }
+    }
+
+    /// Returns a handle to the table holding the environment's own global variables.
+    pub fn globals(&self) -> LuaTable {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            LuaTable::pop_from(L)
+        }
+    }
+
+    /// Executes the given Lua chunk in the environment, and returns any values left on the
+    /// stack.
+    pub fn execute_chunk(&self, chunk: &str, io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        self.execute(io, |L| compile_chunk(L, chunk, None))
+    }
+
+    /// Executes the given Lua chunk in the environment like execute_chunk, but names the chunk
+    /// so that errors and tracebacks refer to it by the given name.
+    pub fn execute_named_chunk(&self, name: &str, chunk: &str, io: &mut dyn LuaIO)
+        -> Result<Vec<LuaValue>, LuaError>
+    {
+        let chunk_name = chunk_name("=", name)?;
+        self.execute(io, |L| compile_chunk(L, chunk, Some(&chunk_name)))
+    }
+
+    /// Executes the Lua file at the given path in the environment. Behaves the same as
+    /// LuaState::execute_file otherwise.
+    pub fn execute_file<P: AsRef<Path>>(&self, path: P, io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        let (source, chunk_name) = read_file(path.as_ref())?;
+        let mode = self.reference.owner().chunk_mode.get();
+        self.execute(io, |L| load_string(L, &source, Some(&chunk_name), mode))
+    }
+
+    /// Compiles the given Lua chunk into a new thread that runs in the environment, without
+    /// running it. Behaves the same as LuaState::create_thread otherwise.
+    pub fn create_thread(&self, name: &str, chunk: &str) -> Result<LuaThread, LuaError> {
+        let chunk_name = chunk_name("=", name)?;
+        let L = self.reference.state();
+        unsafe {
+            let rcode = self.load(L, |L| compile_chunk(L, chunk, Some(&chunk_name)));
+            if rcode != LuaRcode::Ok {
+                let error = get_execution_error(L, rcode, Vec::new());
+                lua_pop(L, 1); // Remove the error message from the stack
+                return Err(error);
+            }
+
+            Ok(LuaThread::create(L))
+        }
+    }
+
+    /// Compiles a chunk with the given function and executes it in the environment, returning
+    /// the values left on the stack.
+    fn execute<F>(&self, io: &mut dyn LuaIO, compile: F) -> Result<Vec<LuaValue>, LuaError>
+        where F: FnOnce(*mut lua_State) -> LuaRcode
+    {
+        let L = self.reference.state();
+        unsafe {
+            execute_function(L, self.reference.owner(), io, |L| self.load(L, compile))
+        }
+    }
+
+    /// Compiles a chunk with the given function and makes the environment the chunk's _ENV,
+    /// which is always the first up value of a compiled chunk.
+    unsafe fn load<F>(&self, L: *mut lua_State, compile: F) -> LuaRcode
+        where F: FnOnce(*mut lua_State) -> LuaRcode
+    {
+        let rcode = compile(L);
+        if rcode == LuaRcode::Ok {
+            self.reference.push(L);
+            if lua_setupvalue(L, -2, 1).is_null() {
+                lua_pop(L, 1); // The chunk has no up values, so leave it as it is
+            }
+        }
+
+        rcode
+    }
+}
+
+
+/// Sets the function with the given name in the environment on top of the stack to the given
+/// loader, if the global table has a function with that name. The loader's up values are the
+/// global function it loads chunks with and the environment.
+unsafe fn set_loader(L: *mut lua_State, name: &str, load_name: &str, loader: lua_CFunction) {
+    let top = lua_gettop(L);
+    let name = CString::new(name).unwrap();
+    let load_name = CString::new(load_name).unwrap();
+    if lua_getglobal(L, name.as_ptr()) == LUA_TFUNCTION && lua_getglobal(L, load_name.as_ptr()) == LUA_TFUNCTION {
+        lua_pushvalue(L, top);
+        lua_pushcclosure(L, loader, 2);
+        lua_setfield(L, top, name.as_ptr());
+    }
+    lua_settop(L, top);
+}
+
+
+/// Replaces "load" in an environment, so that chunks are loaded into the environment unless
+/// the caller gives one.
+unsafe extern "C" fn load_into_environment(L: *mut lua_State) -> c_int {
+    default_to_environment(L, 4);
+    call_original(L)
+}
+
+
+/// Replaces "loadfile" in an environment, so that chunks are loaded into the environment unless
+/// the caller gives one.
+unsafe extern "C" fn load_file_into_environment(L: *mut lua_State) -> c_int {
+    default_to_environment(L, 3);
+    call_original(L)
+}
+
+
+/// Replaces "dofile" in an environment. Loads the file into the environment with the global
+/// "loadfile", then runs it and returns all its results.
+unsafe extern "C" fn do_file_in_environment(L: *mut lua_State) -> c_int {
+    lua_settop(L, 1);
+    lua_pushvalue(L, lua_upvalueindex(1));
+    lua_pushvalue(L, 1);
+    lua_pushnil(L);
+    lua_pushvalue(L, lua_upvalueindex(2));
+    lua_call(L, 3, 2);
+    if lua_type(L, -2) == LUA_TNIL {
+        return lua_error(L); // Raise the message returned along with nil
+    }
+
+    lua_pop(L, 1);
+    lua_call(L, 0, LUA_MULTRET);
+    lua_gettop(L) - 1
+}
+
+
+/// Passes the environment, kept as the second up value, as the argument at the given index if
+/// the caller left it out. Passing nil is not the same as leaving it out.
+unsafe fn default_to_environment(L: *mut lua_State, env_arg: c_int) {
+    if lua_gettop(L) < env_arg {
+        lua_settop(L, env_arg);
+        lua_pushvalue(L, lua_upvalueindex(2));
+        lua_replace(L, env_arg);
+    }
+}
@@ -0,0 +1,84 @@
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::ptr;
+
+use lua::{LuaError, LuaErrorStatus};
+use lua::ffi::*;
+use lua::function::raise_error;
+use lua::hook::install_hook;
+use lua::output::call_original;
+use lua::registry::{get_owner, StateOwner};
+
+
+/// Exit codes os.exit uses for true and false, the same as EXIT_SUCCESS and EXIT_FAILURE.
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+
+
+/// Replaces "os.exit", so that instead of ending the process, calling it while a chunk executes
+/// stops the chunk and has it end with an Exit error. The process is left to the embedding
+/// application, which can clean up before exiting itself. Does nothing if the os library is not
+/// opened or os.exit has been removed.
+pub unsafe fn capture_exit(L: *mut lua_State) {
+    let top = lua_gettop(L);
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    let os_name = CString::new("os").unwrap();
+    let exit_name = CString::new("exit").unwrap();
+
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    if lua_getfield(L, -1, os_name.as_ptr()) == LUA_TTABLE
+        && lua_getfield(L, -1, exit_name.as_ptr()) == LUA_TFUNCTION
+    {
+        lua_pushcclosure(L, exit, 1);
+        lua_setfield(L, -2, exit_name.as_ptr());
+    }
+
+    lua_settop(L, top);
+}
+
+
+/// Returns the Exit error for the chunk that just finished executing if it called os.exit, even
+/// if the error raised to stop it was caught. The request is cleared, so the state can go on to
+/// execute further chunks.
+pub unsafe fn take_exit_request(owner: &StateOwner) -> Option<LuaError> {
+    let code = owner.exit_code.take()?;
+
+    // Stop raising the error on every instruction
+    install_hook(owner.L);
+    Some(LuaError::new(LuaErrorStatus::Exit(code), exit_message(code)))
+}
+
+
+/// Returns the message of the error that stops the running chunk if it called os.exit.
+pub fn exit_request_message(owner: &StateOwner) -> Option<String> {
+    owner.exit_code.get().map(exit_message)
+}
+
+
+/// Replacement for "os.exit". The up value is the original function, which is called if no
+/// chunk is executing. Otherwise records the exit code and raises an error to stop the chunk.
+/// The hook raises the error again on every instruction from then on, so that the chunk stops
+/// even if it catches the error.
+unsafe extern "C" fn exit(L: *mut lua_State) -> c_int {
+    if get_owner(L).map_or(ptr::null_mut(), |owner| owner.io.get()).is_null() {
+        return call_original(L);
+    }
+
+    let code = match lua_type(L, 1) {
+        LUA_TBOOLEAN if lua_toboolean(L, 1) != 0 => EXIT_SUCCESS,
+        LUA_TBOOLEAN => EXIT_FAILURE,
+        _ => luaL_optinteger(L, 1, EXIT_SUCCESS as lua_Integer) as i32,
+    };
+
+    if let Some(owner) = get_owner(L) {
+        owner.exit_code.set(Some(code));
+    }
+    install_hook(L);
+    raise_error(L, exit_message(code))
+}
+
+
+/// Returns the message of the error raised to stop a chunk that called os.exit.
+fn exit_message(code: i32) -> String {
+    format!("os.exit called with code {}", code)
+}
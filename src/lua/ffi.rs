@@ -1,30 +1,89 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
+#![allow(dead_code)]
 /// Contains all FFI function declarations from the Lua API that are used by the rest of the library.
 /// Many common Lua API functions are actually implemented as macros which are not available through the
 /// FFI mechanism. Those macros are implemented here as normal Rust functions using the FFI Lua functions
 /// exactly how they are implemented in the Lua header files.
 
-use std::os::raw::{c_char, c_int, c_void, c_longlong};
+use std::os::raw::{c_char, c_double, c_int, c_longlong, c_uchar, c_void};
+use std::mem;
 use std::ptr;
 
 use libc;
 
 const LUAI_MAXSTACK: c_int = 1000000;
+pub const LUA_EXTRASPACE: usize = mem::size_of::<*mut c_void>();
+pub const LUA_IDSIZE: usize = 60;
+pub const LUA_LOADED_TABLE: &str = "_LOADED";
+
+pub const LUA_MINSTACK: c_int = 20;
 pub const LUA_MULTRET: c_int = -1;
 pub const LUA_OK: c_int = 0;
-pub const LUA_ERRYIELD: c_int = 1;
+pub const LUA_YIELD: c_int = 1;
 pub const LUA_REGISTRYINDEX: c_int = (-LUAI_MAXSTACK) - 1000;
 pub const LUA_RIDX_GLOBALS: c_int = 2;
 
+pub const LUA_HOOKCALL: c_int = 0;
+pub const LUA_HOOKRET: c_int = 1;
+pub const LUA_HOOKLINE: c_int = 2;
+pub const LUA_HOOKCOUNT: c_int = 3;
+pub const LUA_HOOKTAILCALL: c_int = 4;
+
+pub const LUA_MASKCALL: c_int = 1 << LUA_HOOKCALL;
+pub const LUA_MASKRET: c_int = 1 << LUA_HOOKRET;
+pub const LUA_MASKLINE: c_int = 1 << LUA_HOOKLINE;
+pub const LUA_MASKCOUNT: c_int = 1 << LUA_HOOKCOUNT;
+
+pub const LUA_NOREF: c_int = -2;
+pub const LUA_REFNIL: c_int = -1;
+
+pub const LUA_TNONE: c_int = -1;
+pub const LUA_TNIL: c_int = 0;
+pub const LUA_TBOOLEAN: c_int = 1;
+pub const LUA_TLIGHTUSERDATA: c_int = 2;
+pub const LUA_TNUMBER: c_int = 3;
+pub const LUA_TSTRING: c_int = 4;
+pub const LUA_TTABLE: c_int = 5;
+pub const LUA_TFUNCTION: c_int = 6;
+pub const LUA_TUSERDATA: c_int = 7;
+pub const LUA_TTHREAD: c_int = 8;
+
+pub type lua_Alloc = unsafe extern "C" fn(ud: *mut c_void, ptr: *mut c_void, osize: libc::size_t, nsize: libc::size_t) -> *mut c_void;
 pub type lua_CFunction = unsafe extern "C" fn(L: *mut lua_State) -> c_int;
+pub type lua_Hook = Option<unsafe extern "C" fn(L: *mut lua_State, ar: *mut lua_Debug)>;
 pub type lua_Integer = c_longlong;
 pub type lua_KContext = *mut c_void;
 pub type lua_KFunction = *mut c_void;
+pub type lua_Number = c_double;
 pub type lua_State = *mut c_void;
+pub type lua_Writer = unsafe extern "C" fn(L: *mut lua_State, p: *const c_void, sz: libc::size_t, ud: *mut c_void) -> c_int;
+
+/// Activation record used by the debug interface.
+#[repr(C)]
+pub struct lua_Debug {
+    pub event: c_int,
+    pub name: *const c_char,
+    pub namewhat: *const c_char,
+    pub what: *const c_char,
+    pub source: *const c_char,
+    pub currentline: c_int,
+    pub linedefined: c_int,
+    pub lastlinedefined: c_int,
+    pub nups: c_uchar,
+    pub nparams: c_uchar,
+    pub isvararg: c_char,
+    pub istailcall: c_char,
+    pub short_src: [c_char; LUA_IDSIZE],
+    i_ci: *mut c_void,
+}
 
 #[link(name = "lua5.3")]
 extern "C" {
+    pub fn lua_absindex(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn lua_atpanic(L: *mut lua_State, panicf: lua_CFunction) -> lua_CFunction;
+
     pub fn lua_callk
         (
         L: *mut lua_State,
@@ -34,12 +93,54 @@ extern "C" {
         k: lua_KFunction
         );
 
+    pub fn lua_checkstack(L: *mut lua_State, n: c_int) -> c_int;
+
     pub fn lua_close(L: *mut lua_State);
 
+    pub fn lua_concat(L: *mut lua_State, n: c_int);
+
+    pub fn lua_copy(L: *mut lua_State, fromidx: c_int, toidx: c_int);
+
+    pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
+
+    pub fn lua_dump(L: *mut lua_State, writer: lua_Writer, data: *mut c_void, strip: c_int) -> c_int;
+
+    pub fn lua_error(L: *mut lua_State) -> c_int;
+
+    pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const c_char) -> c_int;
+
     pub fn lua_getglobal(L: *mut lua_State, name: *const c_char) -> c_int;
 
+    pub fn lua_gethookcount(L: *mut lua_State) -> c_int;
+
+    pub fn lua_gethookmask(L: *mut lua_State) -> c_int;
+
+    pub fn lua_getinfo(L: *mut lua_State, what: *const c_char, ar: *mut lua_Debug) -> c_int;
+
+    pub fn lua_getlocal(L: *mut lua_State, ar: *const lua_Debug, n: c_int) -> *const c_char;
+
+    pub fn lua_getstack(L: *mut lua_State, level: c_int, ar: *mut lua_Debug) -> c_int;
+
+    pub fn lua_gettable(L: *mut lua_State, idx: c_int) -> c_int;
+
     pub fn lua_gettop(L: *mut lua_State) -> c_int;
 
+    pub fn lua_getupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const c_char;
+
+    pub fn lua_isinteger(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn lua_isyieldable(L: *mut lua_State) -> c_int;
+
+    pub fn lua_len(L: *mut lua_State, idx: c_int);
+
+    pub fn lua_newstate(f: lua_Alloc, ud: *mut c_void) -> *mut lua_State;
+
+    pub fn lua_newthread(L: *mut lua_State) -> *mut lua_State;
+
+    pub fn lua_newuserdata(L: *mut lua_State, sz: libc::size_t) -> *mut c_void;
+
+    pub fn lua_next(L: *mut lua_State, idx: c_int) -> c_int;
+
     pub fn lua_pcallk
         (
         L: *mut lua_State,
@@ -50,26 +151,92 @@ extern "C" {
         k: lua_KFunction
         ) -> c_int;
 
+    pub fn lua_pushboolean(L: *mut lua_State, b: c_int);
+
     pub fn lua_pushcclosure(L: *mut lua_State, f: lua_CFunction, n: c_int);
 
+    pub fn lua_pushinteger(L: *mut lua_State, n: lua_Integer);
+
     pub fn lua_pushlightuserdata(L: *mut lua_State, p: *mut c_void);
 
+    pub fn lua_pushlstring(L: *mut lua_State, s: *const c_char, len: libc::size_t) -> *const c_char;
+
     pub fn lua_pushnil(L: *mut lua_State);
 
+    pub fn lua_pushnumber(L: *mut lua_State, n: lua_Number);
+
     pub fn lua_pushvalue(L: *mut lua_State, idx: c_int);
 
+    pub fn lua_rawequal(L: *mut lua_State, idx1: c_int, idx2: c_int) -> c_int;
+
+    pub fn lua_rawget(L: *mut lua_State, idx: c_int) -> c_int;
+
     pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: lua_Integer) -> c_int;
 
+    pub fn lua_rawlen(L: *mut lua_State, idx: c_int) -> libc::size_t;
+
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: lua_Integer);
+
+    pub fn lua_rawgetp(L: *mut lua_State, idx: c_int, p: *const c_void) -> c_int;
+
+    pub fn lua_rawset(L: *mut lua_State, idx: c_int);
+
+    pub fn lua_rawsetp(L: *mut lua_State, idx: c_int, p: *const c_void);
+
+    pub fn lua_resume(L: *mut lua_State, from: *mut lua_State, narg: c_int) -> c_int;
+
     pub fn lua_rotate(L: *mut lua_State, idx: c_int, n: c_int);
 
     pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const c_char);
 
+    pub fn lua_sethook(L: *mut lua_State, f: lua_Hook, mask: c_int, count: c_int);
+
+    pub fn lua_setmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+
+    pub fn lua_settable(L: *mut lua_State, idx: c_int);
+
     pub fn lua_settop(L: *mut lua_State, idx: c_int);
 
+    pub fn lua_setupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const c_char;
+
+    pub fn lua_status(L: *mut lua_State) -> c_int;
+
+    pub fn lua_stringtonumber(L: *mut lua_State, s: *const c_char) -> libc::size_t;
+
+    pub fn lua_toboolean(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn lua_tointegerx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Integer;
+
     pub fn lua_tolstring(L: *mut lua_State, idx: c_int, len: *mut usize) -> *const c_char;
 
+    pub fn lua_tonumberx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Number;
+
+    pub fn lua_topointer(L: *mut lua_State, idx: c_int) -> *const c_void;
+
+    pub fn lua_tothread(L: *mut lua_State, idx: c_int) -> *mut lua_State;
+
     pub fn lua_touserdata(L: *mut lua_State, idx: c_int) -> *mut c_void;
 
+    pub fn lua_type(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn lua_typename(L: *mut lua_State, tp: c_int) -> *const c_char;
+
+    pub fn lua_xmove(from: *mut lua_State, to: *mut lua_State, n: c_int);
+
+    pub fn lua_yieldk(L: *mut lua_State, nresults: c_int, ctx: lua_KContext, k: lua_KFunction) -> c_int;
+
+    pub fn luaL_argerror(L: *mut lua_State, arg: c_int, extramsg: *const c_char) -> c_int;
+
+    pub fn luaL_callmeta(L: *mut lua_State, obj: c_int, e: *const c_char) -> c_int;
+
+    pub fn luaL_checklstring(L: *mut lua_State, arg: c_int, l: *mut libc::size_t) -> *const c_char;
+
+    pub fn luaL_checktype(L: *mut lua_State, arg: c_int, t: c_int);
+
+    pub fn luaL_getsubtable(L: *mut lua_State, idx: c_int, fname: *const c_char) -> c_int;
+
+    pub fn luaL_len(L: *mut lua_State, idx: c_int) -> lua_Integer;
+
     pub fn luaL_loadbufferx
         (
         L: *mut lua_State,
@@ -79,21 +246,69 @@ extern "C" {
         mode: *const c_char
         ) -> c_int;
 
+    pub fn luaL_newmetatable(L: *mut lua_State, tname: *const c_char) -> c_int;
+
     pub fn luaL_newstate() -> *mut lua_State;
 
+    pub fn luaL_optinteger(L: *mut lua_State, arg: c_int, def: lua_Integer) -> lua_Integer;
+
     pub fn luaL_openlibs(L: *mut lua_State);
 
+    pub fn luaL_ref(L: *mut lua_State, t: c_int) -> c_int;
+
+    pub fn luaL_requiref(L: *mut lua_State, modname: *const c_char, openf: lua_CFunction, glb: c_int);
+
+    pub fn luaL_testudata(L: *mut lua_State, ud: c_int, tname: *const c_char) -> *mut c_void;
+
+    pub fn luaL_tolstring(L: *mut lua_State, idx: c_int, len: *mut libc::size_t) -> *const c_char;
+
     pub fn luaL_traceback(L: *mut lua_State, L1: *mut lua_State, msg: *const c_char, level: c_int);
+
+    pub fn luaL_unref(L: *mut lua_State, t: c_int, r: c_int);
+
+    pub fn luaL_where(L: *mut lua_State, lvl: c_int);
+
+    pub fn luaopen_base(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_coroutine(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_debug(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_io(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_math(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_os(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_package(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_string(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_table(L: *mut lua_State) -> c_int;
+
+    pub fn luaopen_utf8(L: *mut lua_State) -> c_int;
 }
 
 pub unsafe fn lua_call(L: *mut lua_State, n: c_int, r: c_int) {
     lua_callk(L, n, r, ptr::null_mut(), ptr::null_mut());
 }
 
+pub unsafe fn lua_getextraspace(L: *mut lua_State) -> *mut c_void {
+    (L as *mut u8).offset(-(LUA_EXTRASPACE as isize)) as *mut c_void
+}
+
 pub unsafe fn lua_insert(L: *mut lua_State, idx: c_int) {
     lua_rotate(L, idx, 1);
 }
 
+pub unsafe fn lua_isnoneornil(L: *mut lua_State, n: c_int) -> bool {
+    lua_type(L, n) <= 0
+}
+
+pub unsafe fn lua_newtable(L: *mut lua_State) {
+    lua_createtable(L, 0, 0);
+}
+
 pub unsafe fn lua_pcall(L: *mut lua_State, nargs: c_int, nresults: c_int, errfunc: c_int) -> c_int {
     lua_pcallk(L, nargs, nresults, errfunc, ptr::null_mut(), ptr::null_mut())
 }
@@ -115,6 +330,19 @@ pub unsafe fn lua_remove(L: *mut lua_State, idx: c_int) {
     lua_pop(L, 1);
 }
 
+pub unsafe fn lua_replace(L: *mut lua_State, idx: c_int) {
+    lua_copy(L, -1, idx);
+    lua_pop(L, 1);
+}
+
+pub unsafe fn lua_tointeger(L: *mut lua_State, i: c_int) -> lua_Integer {
+    lua_tointegerx(L, i, ptr::null_mut())
+}
+
+pub unsafe fn lua_tonumber(L: *mut lua_State, i: c_int) -> lua_Number {
+    lua_tonumberx(L, i, ptr::null_mut())
+}
+
 pub unsafe fn lua_tostring(L: *mut lua_State, i: c_int) -> *const c_char {
     lua_tolstring(L, i, ptr::null_mut())
 }
@@ -123,6 +351,10 @@ pub fn lua_upvalueindex(i: c_int) -> c_int {
     LUA_REGISTRYINDEX - i
 }
 
+pub unsafe fn lua_yield(L: *mut lua_State, n: c_int) -> c_int {
+    lua_yieldk(L, n, ptr::null_mut(), ptr::null_mut())
+}
+
 pub unsafe fn luaL_loadbuffer
     (
     L: *mut lua_State,
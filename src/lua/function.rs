@@ -0,0 +1,172 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use libc;
+
+use lua::convert::{FromLuaMulti, LuaConversionError, ToLuaMulti};
+use lua::ffi::*;
+use lua::userdata::{check_userdata, push_userdata, LuaMetaMethod, LuaUserData, LuaUserDataMethods};
+
+
+/// A type erased Rust function that can be called from Lua. The function reads its own arguments
+/// from the stack and returns either the number of results it pushed or an error message to
+/// raise in Lua.
+pub type RustFunction = Box<dyn Fn(*mut lua_State) -> Result<c_int, String>>;
+
+
+/// Name of the metatable shared by all userdata objects holding a Rust function.
+const RUST_FUNCTION_METATABLE: &str = "lua_console.RustFunction";
+
+
+/// Error object raised in Lua in place of a panic caught in a Rust callback. Lua code sees the
+/// panic message when it converts the error to a string.
+struct RustPanic {
+    message: String,
+}
+
+
+/// Pushes the given closure onto the stack as a Lua function. The name is only used to describe
+/// the function in error messages. The closure is kept in a RefCell since a Lua function may end
+/// up calling itself, in which case the inner call is rejected instead of aliasing the outer call.
+pub unsafe fn push_function<A, R, F>(L: *mut lua_State, name: &str, function: F)
+    where A: FromLuaMulti,
+          R: ToLuaMulti,
+          F: 'static + FnMut(A) -> Result<R, String>
+{
+    let name = String::from(name);
+    let function = RefCell::new(function);
+    push_rust_function(L, Box::new(move |L| {
+        let num_args = lua_gettop(L);
+        let args = A::from_lua_multi(L, 1, num_args)
+            .map_err(|(position, error)| argument_error(position, &name, error))?;
+
+        // The borrow must end before the results are pushed, since running out of memory while
+        // pushing them raises an error that skips destructors.
+        let results = {
+            let mut function = function.try_borrow_mut()
+                .map_err(|_| format!("cannot call '{}' from within itself", name))?;
+            (*function)(args)?
+        };
+        Ok(results.to_lua_multi(L))
+    }));
+}
+
+
+/// Pushes the given type erased Rust function onto the stack as a Lua function.
+pub unsafe fn push_rust_function(L: *mut lua_State, function: RustFunction) {
+    // The function is stored in a full userdata object so that Lua's garbage collector decides
+    // when it is dropped. The userdata is then captured as an up value by the C function that
+    // Lua actually calls.
+    let userdata = lua_newuserdata(L, mem::size_of::<RustFunction>() as libc::size_t) as *mut RustFunction;
+    ptr::write(userdata, function);
+
+    let metatable_name = CString::new(RUST_FUNCTION_METATABLE).unwrap();
+    if luaL_newmetatable(L, metatable_name.as_ptr()) != 0 {
+        lua_pushcfunction(L, drop_rust_function);
+        let gc_name = CString::new("__gc").unwrap();
+        lua_setfield(L, -2, gc_name.as_ptr());
+    }
+    lua_setmetatable(L, -2);
+
+    lua_pushcclosure(L, call_rust_function, 1);
+}
+
+
+/// Describes an argument passed to the named function that could not be converted.
+pub fn argument_error(position: c_int, name: &str, error: LuaConversionError) -> String {
+    format!("bad argument #{} to '{}' ({})", position, name, error)
+}
+
+
+/// Raises a Lua error with the given message. Since lua_error never returns, the message is
+/// released before control is handed back to Lua.
+pub unsafe fn raise_error(L: *mut lua_State, message: String) -> c_int {
+    lua_pushlstring(L, message.as_ptr() as *const c_char, message.len() as libc::size_t);
+    drop(message);
+    lua_error(L)
+}
+
+
+/// Runs Rust code called back from Lua. Unwinding into the Lua C code that made the call is
+/// undefined behaviour, so a panic is caught and raised as a Lua error instead. The code must
+/// not raise Lua errors itself, since those would skip over the point where panics are caught.
+pub unsafe fn catch_panic<R, F: FnOnce() -> R>(L: *mut lua_State, body: F) -> R {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(payload) => {
+            raise_panic(L, payload);
+            unreachable!()
+        },
+    }
+}
+
+
+/// Raises a Lua error carrying the message of the given panic payload.
+pub unsafe fn raise_panic(L: *mut lua_State, payload: Box<dyn Any + Send>) -> c_int {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        String::from(*message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    };
+    drop(payload);
+
+    push_userdata(L, RustPanic{ message });
+    lua_error(L)
+}
+
+
+/// Returns the message of the error object at the given stack index if it was raised for a
+/// panic in a Rust callback.
+pub unsafe fn read_panic(L: *mut lua_State, idx: c_int) -> Option<String> {
+    check_userdata::<RustPanic>(L, idx)
+        .ok()
+        .map(|cell| (*cell).borrow().to_string())
+}
+
+
+impl fmt::Display for RustPanic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "panic: {}", self.message)
+    }
+}
+
+
+impl LuaUserData for RustPanic {
+    fn type_name() -> &'static str {
+        "RustPanic"
+    }
+
+    fn add_methods(methods: &mut LuaUserDataMethods<RustPanic>) {
+        methods.add_meta_method(LuaMetaMethod::ToString, |panic, ()| Ok(panic.to_string()));
+    }
+}
+
+
+/// C function invoked by Lua whenever a function created with push_rust_function is called.
+unsafe extern "C" fn call_rust_function(L: *mut lua_State) -> c_int {
+    let result = catch_panic(L, || {
+        let function = &*(lua_touserdata(L, lua_upvalueindex(1)) as *const RustFunction);
+        function(L)
+    });
+
+    match result {
+        Ok(num_results) => num_results,
+        Err(message) => raise_error(L, message),
+    }
+}
+
+
+/// Garbage collection metamethod that drops the Rust closure held in a userdata object.
+unsafe extern "C" fn drop_rust_function(L: *mut lua_State) -> c_int {
+    let function = lua_touserdata(L, 1) as *mut RustFunction;
+    catch_panic(L, || ptr::drop_in_place(function));
+    0
+}
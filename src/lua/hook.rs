@@ -0,0 +1,426 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use lua::exit::exit_request_message;
+use lua::ffi::*;
+use lua::debug::{read_frame, read_stack_frames, LuaStackFrame};
+use lua::function::{raise_error, raise_panic};
+use lua::limits::{budget_check_interval, charge_budget};
+use lua::output::call_original;
+use lua::reference::LuaRef;
+use lua::registry::get_owner;
+
+
+/// Events a hook set from Rust is called for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct LuaHookMask {
+    /// Call the hook whenever a function is called.
+    pub calls: bool,
+
+    /// Call the hook whenever a function returns.
+    pub returns: bool,
+
+    /// Call the hook whenever Lua code is about to start a new line.
+    pub lines: bool,
+
+    /// Call the hook after every given number of virtual machine instructions.
+    pub count: Option<u32>,
+}
+
+
+/// The kind of event a hook is called for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LuaHookKind {
+    /// A function is being called.
+    Call,
+
+    /// A function is being called as a tail call, so it will return straight to the caller of
+    /// the function it replaces.
+    TailCall,
+
+    /// A function is about to return.
+    Return,
+
+    /// Lua code is about to start a new line.
+    Line,
+
+    /// The given number of instructions has been executed.
+    Count,
+}
+
+
+/// An event that a hook is called for, with the function that was running when it happened.
+/// The running function can be inspected further while the hook is handling the event.
+#[derive(Debug)]
+pub struct LuaHookEvent {
+    /// Kind of event.
+    pub kind: LuaHookKind,
+
+    /// Function running when the event happened, with the line it was on.
+    pub frame: LuaStackFrame,
+
+    L: *mut lua_State,
+    ar: *mut lua_Debug,
+    suspendable: bool,
+    suspension: Cell<bool>,
+    interruption: RefCell<Option<String>>,
+}
+
+
+/// Hook set from Rust, called with every event it asked for.
+pub type LuaHook = Box<dyn FnMut(&LuaHookEvent)>;
+
+
+/// What the dispatcher does to the running chunk once it is done with an event.
+enum HookAction {
+    Raise(String),
+    Panic(Box<dyn Any + Send>),
+    Suspend,
+}
+
+
+impl LuaHookMask {
+    /// Converts the mask into the one given to lua_sethook, without its count.
+    fn to_raw_mask(self) -> c_int {
+        let mut mask = 0;
+        if self.calls {
+            mask |= LUA_MASKCALL;
+        }
+        if self.returns {
+            mask |= LUA_MASKRET;
+        }
+        if self.lines {
+            mask |= LUA_MASKLINE;
+        }
+        mask
+    }
+}
+
+
+impl LuaHookEvent {
+    /// Stops the running chunk with a runtime error carrying the given message once the hook
+    /// returns. Like any other error, it can be caught by pcall.
+    pub fn interrupt(&self, message: &str) {
+        *self.interruption.borrow_mut() = Some(String::from(message));
+    }
+
+    /// Suspends the thread being resumed from Rust once the hook returns, so that its resume
+    /// returns as if it had yielded no values. Resuming the thread again continues where it
+    /// left off, and any values passed to it are discarded. Only line and count events can
+    /// suspend, and not while the event happens in a coroutine started from Lua or inside a
+    /// function called from C, such as a metamethod. Returns false if the thread cannot be
+    /// suspended.
+    pub fn suspend(&self) -> bool {
+        self.suspension.set(self.suspendable);
+        self.suspendable
+    }
+
+    /// Returns the number of functions on the call stack of the running thread, including the
+    /// running function.
+    pub fn stack_depth(&self) -> usize {
+        let mut ar: lua_Debug = unsafe{ mem::zeroed() };
+        let mut depth = 0;
+        while unsafe{ lua_getstack(self.L, depth as c_int, &mut ar) } != 0 {
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Reads every frame of the call stack of the running thread, starting with the running
+    /// function.
+    pub fn stack(&self) -> Vec<LuaStackFrame> {
+        unsafe{ read_stack_frames(self.L, 0) }
+    }
+
+    /// Reads the local variables of the running function that are in scope, in the order they
+    /// were declared, as handles that can still be used once the hook returns. Temporary values
+    /// Lua keeps in locals of its own, whose names start with a parenthesis, are left out.
+    pub fn locals(&self) -> Vec<(String, LuaRef)> {
+        let mut locals = Vec::new();
+        unsafe {
+            lua_checkstack(self.L, 1);
+            let mut n = 1;
+            loop {
+                let name = lua_getlocal(self.L, self.ar, n);
+                if name.is_null() {
+                    break;
+                }
+                read_variable(self.L, name, &mut locals);
+                n += 1;
+            }
+        }
+        locals
+    }
+
+    /// Reads the up values of the running function, which are the local variables of enclosing
+    /// functions that it uses, as handles like the locals. Up values of C functions have no
+    /// names, and are left out.
+    pub fn upvalues(&self) -> Vec<(String, LuaRef)> {
+        let mut upvalues = Vec::new();
+        unsafe {
+            lua_checkstack(self.L, 2);
+            lua_getinfo(self.L, b"f\0".as_ptr() as *const c_char, self.ar);
+            let function = lua_gettop(self.L);
+            let mut n = 1;
+            loop {
+                let name = lua_getupvalue(self.L, function, n);
+                if name.is_null() {
+                    break;
+                }
+                read_variable(self.L, name, &mut upvalues);
+                n += 1;
+            }
+            lua_pop(self.L, 1); // Pop the function
+        }
+        upvalues
+    }
+}
+
+
+/// Installs the dispatcher as the hook of the given state or thread, asking Lua for the events
+/// wanted by the hook set from Rust and for counts needed to enforce execution limits. Removes
+/// the hook if neither needs any events, or if the state is being closed.
+pub unsafe fn install_hook(L: *mut lua_State) {
+    let owner = match get_owner(L) {
+        Some(owner) => owner,
+        None => {
+            lua_sethook(L, None, 0, 0);
+            return;
+        },
+    };
+    let mask = owner.hook_mask.get();
+
+    let hook_count = mask.count.map(|_| owner.hook_countdown.get().min(c_int::MAX as u32) as c_int);
+    let count = match (hook_count, budget_check_interval(L)) {
+        (Some(hook_count), Some(budget_count)) => Some(hook_count.min(budget_count)),
+        (hook_count, budget_count) => hook_count.or(budget_count),
+    };
+
+    // Once os.exit has been called, the chunk is stopped again on every instruction
+    let count = if owner.exit_code.get().is_some() { Some(1) } else { count };
+
+    let mut raw_mask = mask.to_raw_mask();
+    if count.is_some() {
+        raw_mask |= LUA_MASKCOUNT;
+    }
+
+    if raw_mask == 0 {
+        lua_sethook(L, None, 0, 0);
+    } else {
+        lua_sethook(L, Some(dispatch_hook), raw_mask, count.unwrap_or(0));
+    }
+}
+
+
+/// Wraps "coroutine.resume" and "coroutine.wrap", so that the hook of a coroutine is brought up
+/// to date whenever it is resumed, and that of the resuming thread once it returns. Lua only
+/// copies the hook into a coroutine as it is created, so coroutines created before a hook was
+/// set or execution limits were enforced would otherwise run without them. Does nothing if the
+/// coroutine library is not opened.
+pub unsafe fn capture_coroutines(L: *mut lua_State) {
+    let top = lua_gettop(L);
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    let coroutine_name = CString::new("coroutine").unwrap();
+    let resume_name = CString::new("resume").unwrap();
+    let wrap_name = CString::new("wrap").unwrap();
+
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    if lua_getfield(L, -1, coroutine_name.as_ptr()) == LUA_TTABLE
+        && lua_getfield(L, -1, resume_name.as_ptr()) == LUA_TFUNCTION
+    {
+        let coroutine_table = lua_gettop(L) - 1;
+        lua_pushvalue(L, -1);
+        lua_pushcclosure(L, resume_coroutine, 1);
+        lua_setfield(L, coroutine_table, resume_name.as_ptr());
+
+        // Wrapped coroutines are resumed with the original function
+        lua_pushcclosure(L, wrap_coroutine, 1);
+        lua_setfield(L, coroutine_table, wrap_name.as_ptr());
+    }
+
+    lua_settop(L, top);
+}
+
+
+/// Replaces "coroutine.resume". The up value is the original function.
+unsafe extern "C" fn resume_coroutine(L: *mut lua_State) -> c_int {
+    let coroutine = lua_tothread(L, 1);
+    if !coroutine.is_null() {
+        install_hook(coroutine);
+    }
+
+    let num_results = call_original(L);
+    install_hook(L);
+    num_results
+}
+
+
+/// Replaces "coroutine.wrap", returning a function that resumes a new coroutine running the
+/// given function. The up value is the original "coroutine.resume".
+unsafe extern "C" fn wrap_coroutine(L: *mut lua_State) -> c_int {
+    luaL_checktype(L, 1, LUA_TFUNCTION);
+    let coroutine = lua_newthread(L);
+    lua_pushvalue(L, 1);
+    lua_xmove(L, coroutine, 1);
+
+    lua_pushvalue(L, lua_upvalueindex(1));
+    lua_insert(L, -2); // Place resume below the coroutine
+    lua_pushcclosure(L, resume_wrapped_coroutine, 2);
+    1
+}
+
+
+/// Function returned by the replacement of "coroutine.wrap", which resumes its coroutine and
+/// returns the values it yields, or raises the error it raised. The up values are the original
+/// "coroutine.resume" and the coroutine.
+unsafe extern "C" fn resume_wrapped_coroutine(L: *mut lua_State) -> c_int {
+    install_hook(lua_tothread(L, lua_upvalueindex(2)));
+
+    lua_pushvalue(L, lua_upvalueindex(1));
+    lua_pushvalue(L, lua_upvalueindex(2));
+    lua_rotate(L, 1, 2); // Place resume and the coroutine below the arguments
+    lua_call(L, lua_gettop(L) - 1, LUA_MULTRET);
+    install_hook(L);
+
+    if lua_toboolean(L, 1) == 0 {
+        // Add the position of the caller to the error, the same way the original function does
+        if lua_type(L, -1) == LUA_TSTRING {
+            luaL_where(L, 1);
+            lua_insert(L, -2);
+            lua_concat(L, 2);
+        }
+        return lua_error(L);
+    }
+
+    lua_gettop(L) - 1 // Return everything but the status
+}
+
+
+/// Hook installed on every state and thread while there is a hook set from Rust or execution
+/// limits to enforce. Lua only allows a single hook, so both are served from here.
+unsafe extern "C" fn dispatch_hook(L: *mut lua_State, ar: *mut lua_Debug) {
+    // Raising an error skips destructors, so it is only done once everything is dropped.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| handle_event(L, ar)));
+    match result {
+        Ok(None) => {},
+        Ok(Some(HookAction::Raise(message))) => {
+            raise_error(L, message);
+        },
+        Ok(Some(HookAction::Panic(payload))) | Err(payload) => {
+            raise_panic(L, payload);
+        },
+        Ok(Some(HookAction::Suspend)) => {
+            // A hook yields by calling lua_yield and then returning, after which Lua suspends
+            // the thread.
+            lua_yield(L, 0);
+        },
+    }
+}
+
+
+/// Passes the event to the hook set from Rust if it asked for it, and charges counted
+/// instructions to the budget of the running chunk. Returns what to do to the running chunk.
+/// Events are ignored while the state is being closed.
+unsafe fn handle_event(L: *mut lua_State, ar: *mut lua_Debug) -> Option<HookAction> {
+    let owner = match get_owner(L) {
+        Some(owner) => owner,
+        None => {
+            lua_sethook(L, None, 0, 0);
+            return None;
+        },
+    };
+    let mask = owner.hook_mask.get();
+
+    let (kind, wanted) = match (*ar).event {
+        LUA_HOOKCALL => (LuaHookKind::Call, mask.calls),
+        LUA_HOOKTAILCALL => (LuaHookKind::TailCall, mask.calls),
+        LUA_HOOKRET => (LuaHookKind::Return, mask.returns),
+        LUA_HOOKLINE => (LuaHookKind::Line, mask.lines),
+        _ => (LuaHookKind::Count, false),
+    };
+
+    let mut error = None;
+    if kind == LuaHookKind::Count {
+        let executed = lua_gethookcount(L).max(0) as u32;
+        let mut count_reached = false;
+        if let Some(count) = mask.count {
+            let remaining = owner.hook_countdown.get().saturating_sub(executed);
+            count_reached = remaining == 0;
+            owner.hook_countdown.set(if count_reached { count.max(1) } else { remaining });
+        }
+
+        if count_reached {
+            error = call_hook(L, ar, kind);
+        }
+        if let Some(message) = charge_budget(L, executed as u64).or_else(|| exit_request_message(&owner)) {
+            // Stopping the chunk takes priority over suspending it
+            error = match error {
+                None | Some(HookAction::Suspend) => Some(HookAction::Raise(message)),
+                error => error,
+            };
+        }
+    } else if wanted {
+        error = call_hook(L, ar, kind);
+    }
+
+    // The count has to be set again after every count event, as it depends on how far both the
+    // hook and the budget are from their next count. Threads created before the hook changed may
+    // also still be asking for events that are no longer wanted.
+    if kind == LuaHookKind::Count || lua_gethookmask(L) & !LUA_MASKCOUNT != mask.to_raw_mask() {
+        install_hook(L);
+    }
+
+    error
+}
+
+
+/// Calls the hook set from Rust with the given event. The hook is not called again for events
+/// caused by code it runs itself.
+unsafe fn call_hook(L: *mut lua_State, ar: *mut lua_Debug, kind: LuaHookKind) -> Option<HookAction> {
+    let owner = get_owner(L)?;
+    let mut current_hook = match owner.hook.try_borrow_mut() {
+        Ok(current_hook) => current_hook,
+        Err(_) => return None,
+    };
+    let hook = current_hook.as_mut()?;
+
+    lua_getinfo(L, b"Slnt\0".as_ptr() as *const c_char, ar);
+    let suspendable = (kind == LuaHookKind::Line || kind == LuaHookKind::Count)
+        && ptr::eq(L, owner.resumed_thread.get())
+        && lua_isyieldable(L) != 0;
+    let event = LuaHookEvent{
+        kind,
+        frame: read_frame(&*ar),
+        L,
+        ar,
+        suspendable,
+        suspension: Cell::new(false),
+        interruption: RefCell::new(None),
+    };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| hook(&event))) {
+        Ok(()) => match event.interruption.into_inner() {
+            Some(message) => Some(HookAction::Raise(message)),
+            None if event.suspension.get() => Some(HookAction::Suspend),
+            None => None,
+        },
+        Err(payload) => Some(HookAction::Panic(payload)),
+    }
+}
+
+
+/// Pops the value of the variable with the given name and adds a handle to it to the list of
+/// variables, unless it is one of Lua's temporary values or has no name.
+unsafe fn read_variable(L: *mut lua_State, name: *const c_char, variables: &mut Vec<(String, LuaRef)>) {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    if !name.is_empty() && !name.starts_with('(') {
+        variables.push((name, LuaRef::pop_from(L)));
+    } else {
+        lua_pop(L, 1);
+    }
+}
@@ -0,0 +1,341 @@
+use std::ffi::CString;
+use std::io::{BufRead, Read};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str;
+
+use libc;
+
+use lua::LuaIOBox;
+use lua::ffi::*;
+use lua::function::raise_panic;
+use lua::output::{call_original, FILE_HANDLE_METATABLE};
+use lua::registry::get_owner;
+
+
+/// The ways Lua can ask to read standard input, following the formats of "io.read".
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LuaReadFormat {
+    /// The next line, without its line break.
+    Line,
+
+    /// The next line, keeping its line break.
+    LineWithBreak,
+
+    /// Everything up to the end of the input.
+    All,
+
+    /// A number. The input is read as a line, which Lua then converts to a number.
+    Number,
+
+    /// Up to the given number of bytes.
+    Bytes(usize),
+}
+
+
+/// Registry key under which the io library keeps the default input file.
+const IO_INPUT: &str = "_IO_input";
+
+
+/// Most formats that can be given to "io.lines", the same limit as the io library's.
+const MAX_LINES_FORMATS: c_int = 250;
+
+
+impl LuaReadFormat {
+    /// Reads from the given reader in this format. Returns None if the reader is already at the
+    /// end of its input, except when reading everything, which always succeeds. Reading zero
+    /// bytes reads nothing, but still returns None at the end of the input.
+    pub fn read_from<R: BufRead>(self, reader: &mut R) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let result = match self {
+            LuaReadFormat::Line | LuaReadFormat::LineWithBreak | LuaReadFormat::Number => {
+                reader.read_until(b'\n', &mut bytes)
+            },
+            LuaReadFormat::All => return reader.read_to_end(&mut bytes).ok().map(|_| bytes),
+            LuaReadFormat::Bytes(0) => {
+                return reader.fill_buf().ok().filter(|buffer| !buffer.is_empty()).map(|_| bytes);
+            },
+            LuaReadFormat::Bytes(count) => reader.take(count as u64).read_to_end(&mut bytes),
+        };
+
+        match result {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                let keep_break = !matches!(self, LuaReadFormat::Line | LuaReadFormat::Number);
+                if !keep_break && bytes.last() == Some(&b'\n') {
+                    bytes.pop();
+                }
+                Some(bytes)
+            },
+        }
+    }
+}
+
+
+/// Wraps "io.read", "io.lines" and the read and lines methods of file handles, so that while a
+/// chunk executes, what it reads from standard input comes from its IO receiver. Reads from
+/// other files, and all reads made while no chunk is executing, are passed on to the original
+/// functions. Does nothing if the io library is not opened.
+pub unsafe fn capture_input(L: *mut lua_State) {
+    let top = lua_gettop(L);
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    let io_name = CString::new("io").unwrap();
+    let stdin_name = CString::new("stdin").unwrap();
+    let metatable_name = CString::new(FILE_HANDLE_METATABLE).unwrap();
+
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    if lua_getfield(L, -1, io_name.as_ptr()) != LUA_TTABLE {
+        lua_settop(L, top);
+        return;
+    }
+
+    let io_table = lua_gettop(L);
+    lua_getfield(L, io_table, stdin_name.as_ptr());
+    let stdin = lua_gettop(L);
+    wrap_function(L, io_table, "read", read_default_input, stdin);
+    wrap_function(L, io_table, "lines", lines_default_input, stdin);
+
+    if lua_getfield(L, LUA_REGISTRYINDEX, metatable_name.as_ptr()) == LUA_TTABLE {
+        let metatable = lua_gettop(L);
+        wrap_function(L, metatable, "read", read_file, stdin);
+        wrap_function(L, metatable, "lines", lines_file, stdin);
+    }
+
+    lua_settop(L, top);
+}
+
+
+/// Replaces the function with the given name in the table at the given index with a closure
+/// whose up values are the original function and the standard input file.
+unsafe fn wrap_function(L: *mut lua_State, table: c_int, name: &str, wrapper: lua_CFunction, stdin: c_int) {
+    let name = CString::new(name).unwrap();
+    if lua_getfield(L, table, name.as_ptr()) == LUA_TFUNCTION {
+        lua_pushvalue(L, stdin);
+        lua_pushcclosure(L, wrapper, 2);
+        lua_setfield(L, table, name.as_ptr());
+    } else {
+        lua_pop(L, 1);
+    }
+}
+
+
+/// Replaces "io.read". Reads from the IO receiver if the default input file is still standard
+/// input.
+unsafe extern "C" fn read_default_input(L: *mut lua_State) -> c_int {
+    if is_default_input_stdin(L) {
+        if let Some(num_results) = read_from_receiver(L, 1) {
+            return num_results;
+        }
+    }
+
+    call_original(L)
+}
+
+
+/// Replaces the read method of file handles.
+unsafe extern "C" fn read_file(L: *mut lua_State) -> c_int {
+    if lua_rawequal(L, 1, lua_upvalueindex(2)) != 0 {
+        if let Some(num_results) = read_from_receiver(L, 2) {
+            return num_results;
+        }
+    }
+
+    call_original(L)
+}
+
+
+/// Replaces "io.lines". Lines of the default input file are read with its read method, so they
+/// come from the IO receiver if the default input file is standard input.
+unsafe extern "C" fn lines_default_input(L: *mut lua_State) -> c_int {
+    if lua_isnoneornil(L, 1) && is_default_input_stdin(L) {
+        push_lines_iterator(L, lua_upvalueindex(2), 2);
+        return 1;
+    }
+
+    call_original(L)
+}
+
+
+/// Replaces the lines method of file handles. Lines of standard input are read with its read
+/// method, so they come from the IO receiver.
+unsafe extern "C" fn lines_file(L: *mut lua_State) -> c_int {
+    if lua_rawequal(L, 1, lua_upvalueindex(2)) != 0 {
+        push_lines_iterator(L, 1, 2);
+        return 1;
+    }
+
+    call_original(L)
+}
+
+
+/// Returns true if the io library's default input file is standard input, kept as the second
+/// up value of the running function.
+unsafe fn is_default_input_stdin(L: *mut lua_State) -> bool {
+    let io_input = CString::new(IO_INPUT).unwrap();
+    lua_getfield(L, LUA_REGISTRYINDEX, io_input.as_ptr());
+    let is_stdin = lua_rawequal(L, -1, lua_upvalueindex(2)) != 0;
+    lua_pop(L, 1);
+    is_stdin
+}
+
+
+/// Pushes an iterator that calls the read method of the file at the given index with the
+/// formats on the stack starting at the given index. Unlike the io library's iterator, the file
+/// is never closed.
+unsafe fn push_lines_iterator(L: *mut lua_State, file: c_int, first_format: c_int) {
+    let num_formats = (lua_gettop(L) - first_format + 1).max(0);
+    if num_formats > MAX_LINES_FORMATS {
+        luaL_argerror(L, first_format + MAX_LINES_FORMATS, b"too many arguments\0".as_ptr() as *const c_char);
+    }
+
+    lua_pushinteger(L, num_formats as lua_Integer);
+    lua_pushvalue(L, file);
+    for format in first_format .. first_format + num_formats {
+        lua_pushvalue(L, format);
+    }
+    lua_pushcclosure(L, read_next_line, num_formats + 2);
+}
+
+
+/// Iterator returned by the lines wrappers. The up values are the number of formats, the file
+/// and the formats.
+unsafe extern "C" fn read_next_line(L: *mut lua_State) -> c_int {
+    let num_formats = lua_tointeger(L, lua_upvalueindex(1)) as c_int;
+    lua_settop(L, 0);
+    lua_checkstack(L, num_formats + 2);
+
+    lua_getfield(L, lua_upvalueindex(2), b"read\0".as_ptr() as *const c_char);
+    lua_pushvalue(L, lua_upvalueindex(2));
+    for i in 0 .. num_formats {
+        lua_pushvalue(L, lua_upvalueindex(3 + i));
+    }
+    lua_call(L, num_formats + 1, LUA_MULTRET);
+    lua_gettop(L)
+}
+
+
+/// Reads from the IO receiver of the executing chunk in each of the formats on the stack
+/// starting at the given index, or a line if there are none, and pushes the results. Reading
+/// stops at the first format that could not be read, with nil pushed for it. Returns the number
+/// of results, or None without checking the formats if no chunk is executing, or if the state
+/// is being closed.
+unsafe fn read_from_receiver(L: *mut lua_State, first_format: c_int) -> Option<c_int> {
+    let raw_io_ptr = get_owner(L).map_or(ptr::null_mut(), |owner| owner.io.get());
+    if raw_io_ptr.is_null() {
+        return None;
+    }
+
+    // Raise errors for invalid formats before anything is allocated, since raising an error
+    // skips destructors.
+    let num_args = lua_gettop(L);
+    for arg in first_format ..= num_args {
+        if read_format(L, arg).is_none() {
+            luaL_argerror(L, arg, b"invalid format\0".as_ptr() as *const c_char);
+        }
+    }
+
+    // Everything is read before any of it is pushed, so that a panic in the IO receiver is only
+    // raised once the formats and the input read so far are dropped. Reading stops at the first
+    // format that could not be read, including input read as a number that is not one.
+    let io_box = &mut *(raw_io_ptr as *mut LuaIOBox);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let formats: Vec<LuaReadFormat> = if num_args < first_format {
+            vec![LuaReadFormat::Line]
+        } else {
+            (first_format ..= num_args).filter_map(|arg| read_format(L, arg)).collect()
+        };
+
+        let mut inputs = Vec::new();
+        for format in formats {
+            let input = io_box.io.on_read(format)
+                .filter(|input| format != LuaReadFormat::Number || is_number(L, input));
+            let at_end = input.is_none();
+            inputs.push((format, input));
+            if at_end {
+                break;
+            }
+        }
+        inputs
+    }));
+
+    let inputs = match result {
+        Ok(inputs) => inputs,
+        Err(payload) => {
+            raise_panic(L, payload);
+            unreachable!()
+        },
+    };
+
+    lua_checkstack(L, inputs.len() as c_int);
+    let num_results = inputs.len() as c_int;
+    for (format, input) in inputs {
+        push_input(L, format, input);
+    }
+
+    Some(num_results)
+}
+
+
+/// Reads the format at the given index, given as a count of bytes or as a string such as "l"
+/// or "*l".
+unsafe fn read_format(L: *mut lua_State, idx: c_int) -> Option<LuaReadFormat> {
+    if lua_type(L, idx) == LUA_TNUMBER {
+        let mut is_integer = 0;
+        let count = lua_tointegerx(L, idx, &mut is_integer);
+        return if is_integer != 0 {
+            Some(LuaReadFormat::Bytes(count.max(0) as usize))
+        } else {
+            None
+        };
+    }
+
+    if lua_type(L, idx) != LUA_TSTRING {
+        return None;
+    }
+
+    let mut len = 0;
+    let data = lua_tolstring(L, idx, &mut len);
+    let format = ::std::slice::from_raw_parts(data as *const u8, len);
+    let format = if format.first() == Some(&b'*') { &format[1..] } else { format };
+    match format.first() {
+        Some(b'l') => Some(LuaReadFormat::Line),
+        Some(b'L') => Some(LuaReadFormat::LineWithBreak),
+        Some(b'a') => Some(LuaReadFormat::All),
+        Some(b'n') => Some(LuaReadFormat::Number),
+        _ => None,
+    }
+}
+
+
+/// Pushes the input read in the given format, or nil if nothing could be read. Input read as a
+/// number must already be known to be one.
+unsafe fn push_input(L: *mut lua_State, format: LuaReadFormat, input: Option<Vec<u8>>) {
+    match input {
+        Some(ref input) if format == LuaReadFormat::Number => {
+            push_number(L, input);
+        },
+        Some(input) => {
+            lua_pushlstring(L, input.as_ptr() as *const c_char, input.len() as libc::size_t);
+        },
+        None => lua_pushnil(L),
+    }
+}
+
+
+/// Returns true if the given input, read as a number, is one.
+unsafe fn is_number(L: *mut lua_State, input: &[u8]) -> bool {
+    let is_number = push_number(L, input);
+    if is_number {
+        lua_pop(L, 1);
+    }
+    is_number
+}
+
+
+/// Pushes the number in the given input, read as a number. Returns false without pushing
+/// anything if it is not a number.
+unsafe fn push_number(L: *mut lua_State, input: &[u8]) -> bool {
+    let text = str::from_utf8(input).ok().and_then(|text| CString::new(text.trim()).ok());
+    matches!(text, Some(ref text) if lua_stringtonumber(L, text.as_ptr()) != 0)
+}
@@ -0,0 +1,185 @@
+use std::os::raw::{c_int, c_void};
+use std::time::{Duration, Instant};
+
+use lua::ffi::*;
+use lua::hook::install_hook;
+
+
+/// Limits on how much work a single chunk may do before it is stopped. Limits are only checked
+/// while Lua code is running, so a chunk blocked inside a C function is not interrupted.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct LuaExecutionLimits {
+    /// Maximum number of virtual machine instructions the chunk may execute.
+    pub max_instructions: Option<u64>,
+
+    /// Maximum amount of time the chunk may run for.
+    pub timeout: Option<Duration>,
+}
+
+
+/// Number of instructions executed between checks of the deadline.
+const CHECK_INTERVAL: u64 = 1000;
+
+
+/// Address used as the registry key under which the budget of the running chunk is stored.
+static BUDGET_KEY: u8 = 0;
+
+
+/// Tracks the work done by the chunk currently executing against its limits.
+struct ExecutionBudget {
+    max_instructions: Option<u64>,
+    deadline: Option<Instant>,
+    instructions_executed: u64,
+    exceeded: bool,
+}
+
+
+/// Handle to provide RAII semantics for installing the hook that enforces execution limits for
+/// the duration of a single chunk. A chunk executed from within another, such as by a Rust
+/// function calling a LuaRef, counts against the budget of the outer chunk, which is left in
+/// place once the inner chunk finishes.
+pub struct BudgetRegistrationHandle {
+    budget: *mut ExecutionBudget,
+    L: *mut lua_State,
+
+    // Whether this handle stored its budget in the registry, and whether the budget belongs to
+    // the chunk this one was executed from.
+    hooked: bool,
+    shared: bool,
+}
+
+
+impl LuaExecutionLimits {
+    /// Returns true if no limit has been set.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_instructions.is_none() && self.timeout.is_none()
+    }
+}
+
+
+impl BudgetRegistrationHandle {
+    /// Starts enforcing the given limits on the state. The budget starts counting immediately.
+    pub fn new(L: *mut lua_State, limits: LuaExecutionLimits) -> BudgetRegistrationHandle {
+        if let Some(budget) = unsafe{ current_budget(L) } {
+            // The thread being resumed may not have the hook installed yet
+            unsafe{ install_hook(L) };
+            return BudgetRegistrationHandle{
+                budget,
+                L,
+                hooked: false,
+                shared: true,
+            };
+        }
+
+        let budget = Box::into_raw(Box::new(ExecutionBudget{
+            max_instructions: limits.max_instructions,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            instructions_executed: 0,
+            exceeded: false,
+        }));
+
+        let hooked = !limits.is_unlimited();
+        unsafe {
+            if hooked {
+                lua_pushlightuserdata(L, budget as *mut c_void);
+                lua_rawsetp(L, LUA_REGISTRYINDEX, budget_key());
+            }
+
+            // Also brings the hook set from Rust up to date on threads created before it was set
+            install_hook(L);
+        }
+
+        BudgetRegistrationHandle{
+            budget,
+            L,
+            hooked,
+            shared: false,
+        }
+    }
+
+    /// Returns true if the chunk ran past one of its limits.
+    pub fn exceeded(&self) -> bool {
+        unsafe{ (*self.budget).exceeded }
+    }
+}
+
+
+impl Drop for BudgetRegistrationHandle {
+    fn drop(&mut self) {
+        // Stop counting instructions before releasing the budget, so the state can go on to
+        // execute further chunks. The registry is left alone if nothing was stored in it, since
+        // even storing nil can grow it after the memory limit is no longer enforced.
+        if self.hooked {
+            unsafe {
+                lua_pushnil(self.L);
+                lua_rawsetp(self.L, LUA_REGISTRYINDEX, budget_key());
+                install_hook(self.L);
+            }
+        }
+        if !self.shared {
+            let _budget = unsafe{ Box::from_raw(self.budget) };
+        }
+    }
+}
+
+
+/// Returns the light userdata key under which the budget is stored in the registry.
+fn budget_key() -> *const c_void {
+    &BUDGET_KEY as *const u8 as *const c_void
+}
+
+
+/// Returns how many instructions may run before the budget has to be checked again. Once the
+/// budget is exceeded it is checked on every instruction.
+fn next_check_interval(budget: &ExecutionBudget) -> c_int {
+    if budget.exceeded {
+        return 1;
+    }
+
+    let remaining = budget.max_instructions
+        .map(|max| max.saturating_sub(budget.instructions_executed))
+        .unwrap_or(CHECK_INTERVAL);
+    remaining.clamp(1, CHECK_INTERVAL) as c_int
+}
+
+
+/// Returns the budget of the chunk running on the given state, if it has execution limits.
+unsafe fn current_budget<'a>(L: *mut lua_State) -> Option<&'a mut ExecutionBudget> {
+    lua_rawgetp(L, LUA_REGISTRYINDEX, budget_key());
+    let budget = lua_touserdata(L, -1) as *mut ExecutionBudget;
+    lua_pop(L, 1);
+
+    // Threads may outlive the chunk that created them, and be resumed when no budget is set.
+    budget.as_mut()
+}
+
+
+/// Returns how many instructions may run before the budget of the running chunk has to be
+/// checked again, or None if the chunk has no execution limits.
+pub unsafe fn budget_check_interval(L: *mut lua_State) -> Option<c_int> {
+    current_budget(L).map(|budget| next_check_interval(budget))
+}
+
+
+/// Charges the given number of executed instructions to the budget of the running chunk.
+/// Returns the message of the error that should stop the chunk if it ran past its limits.
+pub unsafe fn charge_budget(L: *mut lua_State, instructions: u64) -> Option<String> {
+    let budget = current_budget(L)?;
+    if budget.exceeded {
+        // The chunk caught the previous error with pcall. Keep raising the error on every
+        // instruction so that it cannot keep running for long.
+        return Some(String::from("execution limit exceeded"));
+    }
+
+    budget.instructions_executed += instructions;
+    let message = if matches!(budget.max_instructions, Some(max) if budget.instructions_executed >= max) {
+        String::from("instruction limit exceeded")
+    } else if matches!(budget.deadline, Some(deadline) if Instant::now() >= deadline) {
+        String::from("time limit exceeded")
+    } else {
+        return None;
+    };
+
+    budget.exceeded = true;
+    Some(message)
+}
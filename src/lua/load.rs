@@ -0,0 +1,61 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+
+use lua::LuaChunkMode;
+use lua::ffi::*;
+use lua::output::call_original;
+use lua::registry::get_owner;
+
+
+/// Wraps "load" and "loadfile", so that Lua code can only load precompiled bytecode if the
+/// chunk mode of the state allows it. Otherwise the chunk is loaded as source code, whatever
+/// mode the caller asked for. Functions that are not opened are left alone.
+pub unsafe fn capture_load(L: *mut lua_State) {
+    wrap_load_function(L, "load", 3, false);
+    wrap_load_function(L, "loadfile", 2, false);
+}
+
+
+/// Wraps "load" and "loadfile" so that they only ever load source code, whatever the chunk mode
+/// of the state.
+pub unsafe fn restrict_load_to_text(L: *mut lua_State) {
+    wrap_load_function(L, "load", 3, true);
+    wrap_load_function(L, "loadfile", 2, true);
+}
+
+
+/// Replaces the global function with the given name, which takes the mode of the chunk to load
+/// as the argument at the given index, with a closure whose up values are the original function,
+/// the index and whether only source code may be loaded.
+unsafe fn wrap_load_function(L: *mut lua_State, name: &str, mode_arg: c_int, text_only: bool) {
+    let name = CString::new(name).unwrap();
+    lua_pushglobaltable(L);
+    if lua_getfield(L, -1, name.as_ptr()) == LUA_TFUNCTION {
+        lua_pushinteger(L, mode_arg as lua_Integer);
+        lua_pushboolean(L, text_only as c_int);
+        lua_pushcclosure(L, load_chunk, 3);
+        lua_setfield(L, -2, name.as_ptr());
+    } else {
+        lua_pop(L, 1);
+    }
+    lua_pop(L, 1); // Pop the global table
+}
+
+
+/// Replaces "load" and "loadfile". Calls the original function with the mode set to source
+/// code only, unless binary chunks are allowed.
+unsafe extern "C" fn load_chunk(L: *mut lua_State) -> c_int {
+    let text_only = lua_toboolean(L, lua_upvalueindex(3)) != 0
+        || get_owner(L).map_or(LuaChunkMode::Text, |owner| owner.chunk_mode.get()) == LuaChunkMode::Text;
+
+    if text_only {
+        // Arguments after the mode are left as they are, since passing the environment as nil is
+        // not the same as leaving it out.
+        let mode_arg = lua_tointeger(L, lua_upvalueindex(2)) as c_int;
+        lua_settop(L, lua_gettop(L).max(mode_arg));
+        lua_pushlstring(L, b"t".as_ptr() as *const c_char, 1);
+        lua_replace(L, mode_arg);
+    }
+
+    call_original(L)
+}
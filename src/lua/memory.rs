@@ -0,0 +1,125 @@
+use std::cell::Cell;
+use std::os::raw::c_void;
+use std::ptr;
+
+use libc;
+
+
+/// A snapshot of the memory used by a Lua state.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LuaMemoryUsage {
+    /// Number of bytes currently allocated by the state.
+    pub used: usize,
+
+    /// Largest number of bytes the state has had allocated at once.
+    pub peak: usize,
+
+    /// Number of bytes the state may allocate while executing a chunk, if limited.
+    pub limit: Option<usize>,
+}
+
+
+/// Accounts for every allocation made by a Lua state. Lua calls back into the tracker through a
+/// raw pointer while Rust may be reading it, so all of its state is kept in cells.
+pub struct MemoryTracker {
+    used: Cell<usize>,
+    peak: Cell<usize>,
+    limit: Option<usize>,
+    enforcing: Cell<bool>,
+}
+
+
+/// Handle to provide RAII semantics for enforcing the memory limit while a chunk executes.
+/// Allocations made directly by the Rust API are not refused, since Lua can only report running
+/// out of memory in protected mode. Handles nest, so that the limit is still enforced on a chunk
+/// once a chunk executed from within it has finished.
+pub struct LimitEnforcementHandle<'a> {
+    tracker: &'a MemoryTracker,
+    was_enforcing: bool,
+}
+
+
+impl MemoryTracker {
+    /// Creates a tracker that refuses to grow the state beyond the given number of bytes.
+    pub fn new(limit: Option<usize>) -> MemoryTracker {
+        MemoryTracker{
+            used: Cell::new(0),
+            peak: Cell::new(0),
+            limit,
+            enforcing: Cell::new(false),
+        }
+    }
+
+    /// Returns the current memory usage.
+    pub fn usage(&self) -> LuaMemoryUsage {
+        LuaMemoryUsage{
+            used: self.used.get(),
+            peak: self.peak.get(),
+            limit: self.limit,
+        }
+    }
+
+    /// Starts enforcing the limit until the returned handle is dropped.
+    pub fn enforce_limit(&self) -> LimitEnforcementHandle<'_> {
+        LimitEnforcementHandle{
+            tracker: self,
+            was_enforcing: self.enforcing.replace(true),
+        }
+    }
+
+    /// Returns true if growing a block from the old size to the new size is allowed.
+    fn allows_growth(&self, old_size: usize, new_size: usize) -> bool {
+        match self.limit {
+            Some(limit) if self.enforcing.get() && new_size > old_size => {
+                self.used.get() - old_size + new_size <= limit
+            },
+            _ => true,
+        }
+    }
+
+    /// Records that a block has changed size.
+    fn record_resize(&self, old_size: usize, new_size: usize) {
+        let used = self.used.get() - old_size + new_size;
+        self.used.set(used);
+        if used > self.peak.get() {
+            self.peak.set(used);
+        }
+    }
+}
+
+
+impl<'a> Drop for LimitEnforcementHandle<'a> {
+    fn drop(&mut self) {
+        self.tracker.enforcing.set(self.was_enforcing);
+    }
+}
+
+
+/// Allocation function used by every Lua state, which must be passed a pointer to the state's
+/// MemoryTracker as its user data. Follows the contract of lua_Alloc: a new size of zero frees
+/// the block, and returning null refuses the allocation.
+pub unsafe extern "C" fn allocate(ud: *mut c_void, block: *mut c_void, osize: libc::size_t, nsize: libc::size_t)
+    -> *mut c_void
+{
+    let tracker = &*(ud as *const MemoryTracker);
+
+    // When there is no block, the old size encodes the type of object being allocated instead.
+    let old_size = if block.is_null() { 0 } else { osize };
+
+    if nsize == 0 {
+        libc::free(block);
+        tracker.record_resize(old_size, 0);
+        return ptr::null_mut();
+    }
+
+    if !tracker.allows_growth(old_size, nsize) {
+        return ptr::null_mut();
+    }
+
+    let new_block = libc::realloc(block, nsize);
+    if !new_block.is_null() {
+        tracker.record_resize(old_size, nsize);
+    }
+
+    new_block
+}
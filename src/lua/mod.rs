@@ -1,13 +1,72 @@
 #![allow(non_snake_case)]
+mod builder;
+mod convert;
+mod debug;
+mod environment;
+mod exit;
 mod ffi;
-
+mod function;
+mod hook;
+mod input;
+mod limits;
+mod load;
+mod memory;
+mod output;
+mod reference;
+mod registry;
+mod searcher;
+#[cfg(feature = "serde")]
+mod serialize;
+mod string;
+mod table;
+mod thread;
+mod userdata;
+mod value;
+
+use std::cell::{Cell, RefCell};
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
 use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
 use std::ptr;
+use std::rc::Rc;
 
 use libc;
 
 use lua::ffi::*;
+use lua::debug::read_stack_frames;
+use lua::function::{catch_panic, push_function, push_rust_function, read_panic};
+use lua::hook::{capture_coroutines, install_hook};
+use lua::limits::BudgetRegistrationHandle;
+use lua::load::capture_load;
+use lua::exit::{capture_exit, take_exit_request};
+use lua::input::capture_input;
+use lua::memory::{allocate, MemoryTracker};
+use lua::output::capture_output;
+use lua::registry::{get_owner, set_owner, StateOwner};
+use lua::searcher::add_module_loader;
+use lua::string::read_string;
+use lua::userdata::push_userdata;
+use lua::value::{read_value, render_value};
+
+pub use lua::builder::{LuaLibrary, LuaStateBuilder};
+pub use lua::debug::{LuaFunctionKind, LuaStackFrame};
+pub use lua::environment::LuaEnvironment;
+pub use lua::hook::{LuaHookEvent, LuaHookKind, LuaHookMask};
+pub use lua::input::LuaReadFormat;
+pub use lua::convert::{FromLua, FromLuaMulti, LuaConversionError, LuaVariadic, ToLua, ToLuaMulti};
+pub use lua::limits::LuaExecutionLimits;
+pub use lua::memory::LuaMemoryUsage;
+pub use lua::reference::LuaRef;
+#[cfg(feature = "serde")]
+pub use lua::serialize::{LuaSerde, LuaSerialized};
+pub use lua::string::LuaString;
+pub use lua::table::LuaTable;
+pub use lua::thread::{LuaResume, LuaThread};
+pub use lua::userdata::{LuaMetaMethod, LuaUserData, LuaUserDataMethods, LuaUserDataRef};
+pub use lua::value::{LuaObject, LuaValue};
 
 
 /// Status codes returned by the Lua virtual machine.
@@ -31,20 +90,77 @@ pub enum LuaErrorStatus {
     RuntimeError,
     SyntaxError,
     InternalError,
+    ConversionError,
+    FileError,
+    Timeout,
+    OutOfMemory,
+    Panic,
+
+    /// The chunk called os.exit with the given exit code.
+    Exit(i32),
+}
+
+
+/// Kinds of chunks that may be loaded from files and byte buffers. Binary chunks hold
+/// precompiled bytecode, which Lua does not verify. Loading malformed bytecode can crash the
+/// process, so binary chunks are only loaded if the state is configured to allow them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LuaChunkMode {
+    /// Only Lua source code is loaded. This is the default.
+    Text,
+
+    /// Only precompiled bytecode is loaded.
+    Binary,
+
+    /// Both source code and precompiled bytecode are loaded.
+    TextOrBinary,
+}
+
+
+/// Standard streams that Lua code can write to through the io library.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LuaStream {
+    Stdout,
+    Stderr,
 }
 
 
 /// Trait used to respond to output generated by an executing Lua chunk.
 pub trait LuaIO {
     /// Invoked whenever "print" is called in Lua with all arguments converted to strings.
-    fn on_print(&mut self, values: Vec<String>);
+    fn on_print(&mut self, values: Vec<LuaString>);
+
+    /// Invoked whenever Lua writes to standard output or standard error through the io library,
+    /// such as with "io.write" or "io.stderr:write". By default the bytes are written to the
+    /// same stream of the process, as they would be without an IO receiver.
+    fn on_write(&mut self, stream: LuaStream, bytes: &[u8]) {
+        let _ = match stream {
+            LuaStream::Stdout => io::stdout().write_all(bytes),
+            LuaStream::Stderr => io::stderr().write_all(bytes),
+        };
+    }
+
+    /// Invoked whenever Lua reads from standard input through the io library, such as with
+    /// "io.read" or "io.lines", once for each format read. Returns None at the end of the
+    /// input. By default the input is read from the standard input of the process.
+    fn on_read(&mut self, format: LuaReadFormat) -> Option<Vec<u8>> {
+        format.read_from(&mut io::stdin().lock())
+    }
 }
 
 
 /// Provides a safe handle to the lua_State structure used in the
-/// Lua C API.
+/// Lua C API. Dropping it closes the state, after which handles to its values, such as tables
+/// and functions, can no longer be used.
 pub struct LuaState {
     state: *mut lua_State,
+
+    // Handles into the state share the owner, so that they can tell once the state is closed.
+    owner: Rc<StateOwner>,
+
+    // Whether dropping this LuaState closes the state. Only the LuaState that created the state
+    // closes it, not the ones handed to Rust code called from Lua.
+    closes_state: bool,
 }
 
 
@@ -52,78 +168,459 @@ pub struct LuaState {
 #[derive(PartialEq, Debug)]
 pub struct LuaError {
     pub status: LuaErrorStatus,
+
+    /// The error message as raised, without a traceback. Bytes that are not valid UTF-8 are
+    /// written as "\xNN" escapes.
     pub message: String,
+
+    /// Printable name of the chunk the error was raised in, if known.
+    pub chunk_name: Option<String>,
+
+    /// Line the error was raised on, if known.
+    pub line: Option<u32>,
+
+    /// The call stack at the point the error was raised, innermost frame first. Only errors
+    /// raised while executing a chunk have a traceback.
+    pub traceback: Vec<LuaStackFrame>,
 }
 
 
 /// Container to hold a reference to a LuaIO trait object. In order to redirect all output
 /// written to standard out by an executing Lua script to an IO receiver, the standard Lua print
-/// function is redefined in Rust. In order to send output values to the IO receiver, the Rust
-/// print function must somehow have access to the particualr IO receiver for the Lua state that
-/// is executing. This is done by storing a raw pointer to the reveiver as a light userdata object
-/// as an up-value to the custom print function. Since trait objects are "fat" pointers, they cannot
-/// be cast between raw C pointers. Therefore, the IO receiver is placed into a container which we
-/// can then access in the print function as one of the function's up values.
+/// function is redefined in Rust, and the io library's read and write functions are wrapped. In
+/// order to send output to the IO receiver, these functions must somehow have access to the particular IO
+/// receiver for the Lua state that is executing. This is done by storing a raw pointer to the
+/// receiver in the owner of the state while a chunk executes. Since trait objects are "fat"
+/// pointers, they cannot be cast between raw C pointers. Therefore, the IO receiver is placed
+/// into a container which the functions can then find through the owner.
 struct LuaIOBox<'a> {
-    io: &'a mut LuaIO,
+    io: &'a mut dyn LuaIO,
 }
 
 /// Handle to provide RAII semantics for managing the registration and unregistration of IO
-/// receivers with the Lua run time.
+/// receivers with the Lua run time. Registrations nest, so that a chunk executed from within
+/// another sends its output to its own receiver, and the outer receiver is restored after.
 struct IORegistrationHandle<'a> {
     io: *mut LuaIOBox<'a>,
+    previous_io: *mut c_void,
     L: *mut lua_State,
 }
 
 
+/// Registry key of the print function replaced while chunks execute.
+const ORIGINAL_PRINT: &str = "lua_console.OriginalPrint";
+
+
+/// Registry key of the print function that sends values to the IO receiver.
+const CONSOLE_PRINT: &str = "lua_console.ConsolePrint";
+
+
 impl LuaState {
     /// Creates and configures a new Lua state that can be used to execute
     /// Lua chunks.
     pub fn new() -> LuaState {
-        let state = unsafe{ luaL_newstate() };
-        unsafe{ luaL_openlibs(state) };
+        let lua_state = LuaState::create(None);
+        unsafe {
+            luaL_openlibs(lua_state.state);
+            redirect_libraries(lua_state.state);
+        }
+        lua_state
+    }
+
+    /// Creates a new Lua state whose chunks may not grow it beyond the given number of bytes.
+    /// Chunks that try to are stopped with an OutOfMemory error.
+    pub fn with_memory_limit(limit: usize) -> LuaState {
+        let lua_state = LuaState::create(Some(limit));
+        unsafe {
+            luaL_openlibs(lua_state.state);
+            redirect_libraries(lua_state.state);
+        }
+        lua_state
+    }
+
+    /// Creates a Lua state that allocates its memory through a tracker with the given limit. No
+    /// libraries are opened in the state.
+    fn create(memory_limit: Option<usize>) -> LuaState {
+        let memory = Box::new(MemoryTracker::new(memory_limit));
+        let state = unsafe {
+            lua_newstate(allocate, &*memory as *const MemoryTracker as *mut c_void)
+        };
+        assert!(!state.is_null(), "Not enough memory to create a Lua state");
+
+        unsafe {
+            lua_atpanic(state, panic_handler);
+        }
+
+        let owner = Rc::new(StateOwner{
+            L: state,
+            memory,
+            limits: Cell::new(LuaExecutionLimits::default()),
+            chunk_mode: Cell::new(LuaChunkMode::Text),
+            io: Cell::new(ptr::null_mut()),
+            exit_code: Cell::new(None),
+            hook: RefCell::new(None),
+            hook_mask: Cell::new(LuaHookMask::default()),
+            hook_countdown: Cell::new(0),
+            resumed_thread: Cell::new(ptr::null_mut()),
+            closing: Cell::new(false),
+        });
+        unsafe{ set_owner(state, &owner) };
 
         LuaState{
             state,
+            owner,
+            closes_state: true,
+        }
+    }
+
+    /// Sets the limits enforced on every chunk executed and every thread resumed from now on.
+    /// A chunk that exceeds them is stopped with a Timeout error.
+    pub fn set_execution_limits(&mut self, limits: LuaExecutionLimits) {
+        self.owner.limits.set(limits);
+    }
+
+    /// Returns the limits enforced on executing chunks.
+    pub fn execution_limits(&self) -> LuaExecutionLimits {
+        self.owner.limits.get()
+    }
+
+    /// Sets a hook that is called with the given events while chunks and threads execute,
+    /// replacing any hook set before. The hook is not called for events caused by code it runs
+    /// itself, and it applies alongside the execution limits of the state.
+    pub fn set_hook<F>(&mut self, mask: LuaHookMask, hook: F)
+        where F: 'static + FnMut(&LuaHookEvent)
+    {
+        *self.owner.hook.borrow_mut() = Some(Box::new(hook));
+        self.owner.hook_mask.set(mask);
+        self.owner.hook_countdown.set(mask.count.unwrap_or(0).max(1));
+        unsafe{ install_hook(self.state) };
+    }
+
+    /// Removes the hook set with set_hook.
+    pub fn remove_hook(&mut self) {
+        self.owner.hook_mask.set(LuaHookMask::default());
+        unsafe{ install_hook(self.state) };
+        *self.owner.hook.borrow_mut() = None;
+    }
+
+    /// Sets which kinds of chunks execute_file and execute_bytes accept. Unless precompiled
+    /// bytecode is allowed, "load" and "loadfile" only load source code as well.
+    pub fn set_chunk_mode(&mut self, mode: LuaChunkMode) {
+        self.owner.chunk_mode.set(mode);
+    }
+
+    /// Returns which kinds of chunks execute_file and execute_bytes accept.
+    pub fn chunk_mode(&self) -> LuaChunkMode {
+        self.owner.chunk_mode.get()
+    }
+
+    /// Returns how much memory the state is using.
+    pub fn memory_usage(&self) -> LuaMemoryUsage {
+        self.owner.memory.usage()
+    }
+
+    /// Creates a new, empty table.
+    pub fn create_table(&self) -> LuaTable {
+        unsafe{ LuaTable::create(self.state) }
+    }
+
+    /// Creates a new environment whose chunks have their own global variables, falling back to
+    /// this state's globals for any they have not set themselves.
+    pub fn create_environment(&self) -> LuaEnvironment {
+        unsafe{ LuaEnvironment::create(self.state) }
+    }
+
+    /// Executes the given Lua chunk, and returns any values left on the stack. Tables,
+    /// functions and other objects are only returned as their identity and string form.
+    pub fn execute_chunk(&self, chunk: &str, io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        self.execute(io, |L| compile_chunk(L, chunk, None))
+    }
+
+    /// Executes the given Lua chunk like execute_chunk, but returns handles to the values left
+    /// on the stack, so that tables and functions it returns can still be used after it ends.
+    pub fn execute_chunk_refs(&self, chunk: &str, io: &mut dyn LuaIO) -> Result<Vec<LuaRef>, LuaError> {
+        self.execute(io, |L| compile_chunk(L, chunk, None))
+    }
+
+    /// Executes the given Lua chunk like execute_chunk, but names the chunk so that errors and
+    /// tracebacks refer to it by the given name, such as "stdin:3".
+    pub fn execute_named_chunk(&self, name: &str, chunk: &str, io: &mut dyn LuaIO)
+        -> Result<Vec<LuaValue>, LuaError>
+    {
+        let chunk_name = chunk_name("=", name)?;
+        self.execute(io, |L| compile_chunk(L, chunk, Some(&chunk_name)))
+    }
+
+    /// Executes the Lua file at the given path and returns the values it returns. Errors and
+    /// tracebacks refer to the file by its path. The file may hold precompiled bytecode if the
+    /// chunk mode allows it.
+    pub fn execute_file<P: AsRef<Path>>(&self, path: P, io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        let (source, chunk_name) = read_file(path.as_ref())?;
+        self.execute(io, |L| load_string(L, &source, Some(&chunk_name), self.chunk_mode()))
+    }
+
+    /// Executes a chunk of source code or precompiled bytecode, as allowed by the chunk mode,
+    /// and returns the values it returns. Errors and tracebacks refer to the chunk by the given
+    /// name.
+    pub fn execute_bytes(&self, name: &str, chunk: &[u8], io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        let chunk_name = chunk_name("=", name)?;
+        self.execute(io, |L| load_string(L, chunk, Some(&chunk_name), self.chunk_mode()))
+    }
+
+    /// Compiles the given source code into bytecode that can be executed with execute_bytes or
+    /// saved to a file. Stripping the bytecode leaves out debug information such as line numbers
+    /// and local variable names, which makes it smaller.
+    pub fn compile(&self, source: &str, name: &str, strip: bool) -> Result<Vec<u8>, LuaError> {
+        let chunk_name = chunk_name("=", name)?;
+        let mut bytecode = Vec::new();
+        unsafe {
+            let rcode = load_string(self.state, source.as_bytes(), Some(&chunk_name), LuaChunkMode::Text);
+            if rcode != LuaRcode::Ok {
+                let error = get_execution_error(self.state, rcode, Vec::new());
+                lua_pop(self.state, 1); // Remove the error message from the stack
+                return Err(error);
+            }
+
+            let bytecode_ptr = &mut bytecode as *mut Vec<u8> as *mut c_void;
+            let result = lua_dump(self.state, write_bytecode, bytecode_ptr, strip as c_int);
+            lua_pop(self.state, 1); // Remove the compiled function from the stack
+
+            if result != 0 {
+                return Err(LuaError::new(LuaErrorStatus::InternalError, String::from("unable to dump bytecode")));
+            }
+        }
+
+        Ok(bytecode)
+    }
+
+    /// Compiles the given Lua chunk into a new thread without running it. Unlike a chunk run by
+    /// execute_chunk, the chunk may yield when the thread is resumed. Errors and tracebacks
+    /// refer to the chunk by the given name.
+    pub fn create_thread(&self, name: &str, chunk: &str) -> Result<LuaThread, LuaError> {
+        let chunk_name = chunk_name("=", name)?;
+        unsafe {
+            let rcode = compile_chunk(self.state, chunk, Some(&chunk_name));
+            if rcode != LuaRcode::Ok {
+                let error = get_execution_error(self.state, rcode, Vec::new());
+                lua_pop(self.state, 1); // Remove the error message from the stack
+                return Err(error);
+            }
+
+            Ok(LuaThread::create(self.state))
+        }
+    }
+
+    /// Compiles a chunk with the given function and executes it, returning the values left on
+    /// the stack.
+    fn execute<V, F>(&self, io: &mut dyn LuaIO, compile: F) -> Result<Vec<V>, LuaError>
+        where V: FromLua,
+              F: FnOnce(*mut lua_State) -> LuaRcode
+    {
+        unsafe{ execute_function(self.state, &self.owner, io, compile) }
+    }
+
+    /// Registers a Rust closure as a global Lua function with the given name. The closure's
+    /// arguments are converted from the values passed by Lua and its results are pushed back as
+    /// the function's return values. Arguments that cannot be converted and errors returned by
+    /// the closure are raised as Lua errors.
+    pub fn register_function<A, R, F>(&self, name: &str, function: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + FnMut(A) -> Result<R, String>
+    {
+        unsafe {
+            lua_pushglobaltable(self.state);
+            name.to_lua(self.state);
+            push_function(self.state, name, function);
+            lua_rawset(self.state, -3);
+
+            lua_pop(self.state, 1); // Pop the global table from the stack
         }
     }
 
-    /// Executes the given Lua chunk, and returns any values left on the stack converted to
-    /// their string representations.
-    pub fn execute_chunk(&self, chunk: &str, io: &mut LuaIO) -> Result<Vec<String>, LuaError> {
-        let _io_handle = IORegistrationHandle::new(self.state, io);
-        
-        let initial_stack = unsafe{ lua_gettop(self.state) };
-        let mut rcode = compile_chunk(self.state, chunk);
+    /// Registers a Rust closure as a function in the global table with the given name, creating
+    /// the table if it does not exist yet. Otherwise behaves the same as register_function.
+    pub fn register_table_function<A, R, F>(&self, table: &str, name: &str, function: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + FnMut(A) -> Result<R, String>
+    {
+        unsafe {
+            lua_pushglobaltable(self.state);
+            push_subtable(self.state, table);
+
+            let qualified_name = format!("{}.{}", table, name);
+            name.to_lua(self.state);
+            push_function(self.state, &qualified_name, function);
+            lua_rawset(self.state, -3);
 
-        if rcode == LuaRcode::Ok {
-            rcode = unsafe{ execute_compiled_chunk(self.state) };
+            lua_pop(self.state, 2); // Pop the table and the global table from the stack
         }
+    }
 
-        let num_stack_values = unsafe{ lua_gettop(self.state) } - initial_stack;
+    /// Adds a module written in Lua that "require" finds by the given name without searching
+    /// the file system. The source is compiled straight away, so syntax errors are returned here
+    /// instead of when the module is required. Passing the source from include_str! embeds the
+    /// module into the binary at compile time. Requires the package library.
+    pub fn add_module_source(&self, name: &str, source: &str) -> Result<(), LuaError> {
+        let chunk_name = chunk_name("=", name)?;
+        unsafe {
+            let rcode = load_string(self.state, source.as_bytes(), Some(&chunk_name), LuaChunkMode::Text);
+            if rcode != LuaRcode::Ok {
+                let error = get_execution_error(self.state, rcode, Vec::new());
+                lua_pop(self.state, 1); // Remove the error message from the stack
+                return Err(error);
+            }
+
+            add_module_loader(self.state, name)
+        }
+    }
 
-        let exctn_result = if rcode == LuaRcode::Ok {
-            let stack_values = unsafe{ dump_stack(self.state, num_stack_values) };
+    /// Adds a module built in Rust that "require" finds by the given name. Unlike the modules
+    /// added by LuaStateBuilder::module, the module is only built once a chunk requires it, and
+    /// is not stored as a global. Errors returned while building it are raised in Lua by
+    /// require. Requires the package library.
+    pub fn add_module_opener<F>(&self, name: &str, open: F) -> Result<(), LuaError>
+        where F: 'static + FnMut(&LuaState) -> Result<LuaTable, LuaError>
+    {
+        let open = RefCell::new(open);
+        unsafe {
+            push_rust_function(self.state, Box::new(move |L| {
+                let mut open = open.try_borrow_mut()
+                    .map_err(|_| String::from("module requires itself while it is being opened"))?;
+
+                let owner = get_owner(L).ok_or_else(|| String::from("Lua state is being closed"))?;
+                let lua_state = LuaState{ state: owner.L, owner, closes_state: false };
+                let module = (*open)(&lua_state).map_err(|error| error.message)?;
+                module.to_lua(L);
+                Ok(1)
+            }));
+
+            add_module_loader(self.state, name)
+        }
+    }
 
-            // Remove all of the returned values and the compiled chunk from the stack.
-            unsafe{ lua_pop(self.state, num_stack_values + 1) };
-            Ok(stack_values)
-        } else {
-            let error = unsafe{ get_execution_error(self.state, rcode) };
-            unsafe{ lua_pop(self.state, num_stack_values) };
-            Err(error)
-        };
+    /// Moves the given value into Lua as userdata and returns a handle to it. Lua code and the
+    /// handle both refer to the same value.
+    pub fn create_userdata<T: LuaUserData>(&self, value: T) -> LuaUserDataRef<T> {
+        unsafe {
+            push_userdata(self.state, value);
+            LuaUserDataRef::pop_from(self.state)
+        }
+    }
 
-        exctn_result
+    /// Sets the global variable with the given name to the given value. Any "__newindex"
+    /// metamethod on the global table is invoked.
+    pub fn set_global<V: ToLua>(&self, name: &str, value: V) -> Result<(), LuaError> {
+        check_global_name(name)?;
+        unsafe {
+            name.to_lua(self.state);
+            value.to_lua(self.state);
+            protected_call(self.state, set_global_value, 2, 0)
+        }
+    }
+
+    /// Retrieves the value of the global variable with the given name. Any "__index" metamethod
+    /// on the global table is invoked.
+    pub fn get_global<V: FromLua>(&self, name: &str) -> Result<V, LuaError> {
+        check_global_name(name)?;
+        unsafe {
+            name.to_lua(self.state);
+            protected_call(self.state, get_global_value, 1, 1)?;
+            pop_value(self.state)
+        }
+    }
+
+    /// Converts a Rust value into a Lua value with serde. Structs and maps become tables with
+    /// a key for each field or entry, sequences and tuples become tables with the keys 1 to n,
+    /// and unit values and None become nil. Unit variants become their name as a string, and
+    /// other variants a table holding their contents under their name. The result can be
+    /// passed anywhere a Lua value is expected, and read back with LuaSerde.
+    #[cfg(feature = "serde")]
+    pub fn serialize<T: ::serde::Serialize + ?Sized>(&self, value: &T) -> Result<LuaSerialized, LuaError> {
+        unsafe{ LuaSerialized::create(self.state, value).map_err(LuaError::from) }
+    }
+
+    /// Returns a handle to the table holding all global variables.
+    pub fn globals(&self) -> LuaTable {
+        unsafe {
+            lua_pushglobaltable(self.state);
+            LuaTable::pop_from(self.state)
+        }
     }
 }
 
 
 impl Drop for LuaState {
     fn drop(&mut self) {
-        unsafe {
-            lua_close(self.state);
+        if self.closes_state {
+            self.owner.close();
+        }
+    }
+}
+
+
+impl LuaError {
+    /// Creates an error with the given message and no location or traceback.
+    pub fn new(status: LuaErrorStatus, message: String) -> LuaError {
+        LuaError{
+            status,
+            message,
+            chunk_name: None,
+            line: None,
+            traceback: Vec::new(),
+        }
+    }
+
+    /// Creates an error from the message and traceback collected when an error was raised. The
+    /// location is taken from the position Lua added in front of the message, or otherwise
+    /// from the innermost Lua function in the traceback.
+    fn with_traceback(status: LuaErrorStatus, message: String, traceback: Vec<LuaStackFrame>) -> LuaError {
+        let location = parse_location(&message)
+            .filter(|(chunk_name, _)| {
+                traceback.is_empty() || traceback.iter().any(|frame| &frame.source == chunk_name)
+            })
+            .or_else(|| {
+                traceback.iter()
+                    .find(|frame| frame.current_line.is_some())
+                    .map(|frame| (frame.source.clone(), frame.current_line.unwrap()))
+            });
+
+        let (chunk_name, line) = match location {
+            Some((chunk_name, line)) => (Some(chunk_name), Some(line)),
+            None => (None, None),
+        };
+
+        LuaError{
+            status,
+            message,
+            chunk_name,
+            line,
+            traceback,
+        }
+    }
+}
+
+
+impl fmt::Display for LuaError {
+    /// Displays the message followed by the traceback, the same way luaL_traceback does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.traceback.is_empty() {
+            write!(f, "\nstack traceback:")?;
+            for frame in &self.traceback {
+                write!(f, "\n\t{}", frame)?;
+            }
         }
+
+        Ok(())
+    }
+}
+
+
+impl From<LuaConversionError> for LuaError {
+    fn from(error: LuaConversionError) -> LuaError {
+        LuaError::new(LuaErrorStatus::ConversionError, error.message)
     }
 }
 
@@ -146,12 +643,19 @@ impl LuaRcode {
 
 
 impl<'a> IORegistrationHandle<'a> {
-    fn new(L: *mut lua_State, io: &'a mut LuaIO) -> IORegistrationHandle<'a> {
+    fn new(L: *mut lua_State, io: &'a mut dyn LuaIO) -> IORegistrationHandle<'a> {
         let io_ptr = Box::into_raw(Box::new(LuaIOBox{ io }));
-        unsafe{ register_print(L, io_ptr as *mut c_void); }
+        let owner = unsafe{ get_owner(L) }.expect("chunk executed while its state is being closed");
+        let previous_io = owner.io.replace(io_ptr as *mut c_void);
+
+        // Only the outermost registration replaces print, the nested ones share it
+        if previous_io.is_null() {
+            unsafe{ register_print(L); }
+        }
 
         IORegistrationHandle{
             io: io_ptr,
+            previous_io,
             L,
         }
     }
@@ -160,47 +664,155 @@ impl<'a> IORegistrationHandle<'a> {
 
 impl<'a> Drop for IORegistrationHandle<'a> {
     fn drop(&mut self) {
-        // For safety, unregister the print function which holds a raw reference to the
-        // IO receiver trait object.
-        unsafe{ unregister_print(self.L); }
+        // For safety, unregister the IO receiver before it is freed, so that functions still
+        // reachable from Lua cannot use it afterwards.
+        if let Some(owner) = unsafe{ get_owner(self.L) } {
+            owner.io.set(self.previous_io);
+        }
+        if self.previous_io.is_null() {
+            unsafe{ unregister_print(self.L); }
+        }
         let _io_container = unsafe{ Box::from_raw(self.io) };
     }
 }
 
 
+/// Replaces the standard library functions that would reach past the IO receiver to the
+/// process itself: reading and writing the standard streams, and os.exit. Also wraps the
+/// functions that resume coroutines, so that hooks reach every coroutine, and the functions that
+/// load chunks, so that Lua code is held to the chunk mode. Must be called once the libraries
+/// have been opened.
+unsafe fn redirect_libraries(L: *mut lua_State) {
+    capture_input(L);
+    capture_output(L);
+    capture_exit(L);
+    capture_coroutines(L);
+    capture_load(L);
+}
+
+
 /// Compiles the given chunk making it available to be executed as a no argument function
-/// on top of the stack.
-fn compile_chunk(L: *mut lua_State, chunk: &str) -> LuaRcode {
-    let mut rcode = try_add_return(L, chunk);
+/// on top of the stack. Without a name, Lua names the chunk after its source.
+fn compile_chunk(L: *mut lua_State, chunk: &str, name: Option<&CStr>) -> LuaRcode {
+    let mut rcode = try_add_return(L, chunk, name);
     if rcode != LuaRcode::Ok {
-        rcode = load_string(L, chunk);
+        rcode = load_string(L, chunk.as_bytes(), name, LuaChunkMode::Text);
     }
 
     rcode
 }
 
 
-/// Executes a chunk that has been compiled and is on the top of the stack.
-unsafe fn execute_compiled_chunk(L: *mut lua_State) -> LuaRcode {
-    let base = lua_gettop(L);
-    lua_pushcfunction(L, message_handler);
+/// Builds the name of a chunk from the given name and the prefix telling Lua how to display
+/// it: "=" to display the name as is, or "@" to display it as a file name.
+fn chunk_name(prefix: &str, name: &str) -> Result<CString, LuaError> {
+    CString::new(format!("{}{}", prefix, name)).map_err(|_| {
+        LuaError::new(LuaErrorStatus::ConversionError, format!("chunk name {:?} contains a nul byte", name))
+    })
+}
+
+
+/// Reads the Lua file at the given path, returning its contents along with the chunk name that
+/// refers to the file by its path.
+fn read_file(path: &Path) -> Result<(Vec<u8>, CString), LuaError> {
+    let mut source = fs::read(path).map_err(|error| {
+        LuaError::new(LuaErrorStatus::FileError, format!("cannot open {}: {}", path.display(), error))
+    })?;
+    skip_comment_line(&mut source);
+
+    let chunk_name = chunk_name("@", &path.display().to_string())?;
+    Ok((source, chunk_name))
+}
+
+
+/// Blanks out the first line of a source file if it is a comment starting with "#", such as a
+/// Unix shebang line, the same way the standalone Lua interpreter does. The line break is kept
+/// so that line numbers in errors still match the file.
+fn skip_comment_line(source: &mut Vec<u8>) {
+    if source.first() == Some(&b'#') {
+        let line_end = source.iter().position(|&byte| byte == b'\n').unwrap_or(source.len());
+        source.drain(.. line_end);
+    }
+}
+
+
+/// Pushes a function and its arguments with the given closure, then calls the function with
+/// print sent to the IO receiver and the limits of the state enforced. Returns the values the
+/// function returned, converted to the given type.
+unsafe fn execute_function<V, F>(L: *mut lua_State, owner: &StateOwner, io: &mut dyn LuaIO, prepare: F)
+    -> Result<Vec<V>, LuaError>
+    where V: FromLua,
+          F: FnOnce(*mut lua_State) -> LuaRcode
+{
+    let _io_handle = IORegistrationHandle::new(L, io);
+    let budget_handle = BudgetRegistrationHandle::new(L, owner.limits.get());
+    let memory_handle = owner.memory.enforce_limit();
+
+    let mut traceback = Vec::new();
+    let initial_stack = lua_gettop(L);
+    let mut rcode = prepare(L);
+
+    if rcode == LuaRcode::Ok {
+        let num_args = lua_gettop(L) - initial_stack - 1;
+        rcode = execute_compiled_chunk(L, num_args, &mut traceback);
+    }
+
+    drop(memory_handle);
+    let num_stack_values = lua_gettop(L) - initial_stack;
+
+    if let Some(error) = take_exit_request(owner) {
+        lua_pop(L, num_stack_values);
+        Err(error)
+    } else if rcode == LuaRcode::Ok {
+        let stack_values = (initial_stack + 1 ..= initial_stack + num_stack_values)
+            .map(|idx| V::from_lua(L, idx).map_err(LuaError::from))
+            .collect();
+
+        // Remove all of the returned values from the stack.
+        lua_pop(L, num_stack_values);
+        stack_values
+    } else {
+        let mut error = get_execution_error(L, rcode, traceback);
+        if budget_handle.exceeded() {
+            error.status = LuaErrorStatus::Timeout;
+        }
+        lua_pop(L, num_stack_values);
+        Err(error)
+    }
+}
+
+
+/// Executes a function that is on the stack below the given number of arguments, which for a
+/// compiled chunk is zero.
+unsafe fn execute_compiled_chunk(L: *mut lua_State, num_args: c_int, traceback: &mut Vec<LuaStackFrame>) -> LuaRcode {
+    let base = lua_gettop(L) - num_args;
+    lua_pushlightuserdata(L, traceback as *mut Vec<LuaStackFrame> as *mut c_void);
+    lua_pushcclosure(L, message_handler, 1);
     lua_insert(L, base); // Push our message handler under the function to call
 
-    let rcode = lua_pcall(L, 0, LUA_MULTRET, base);
+    let rcode = lua_pcall(L, num_args, LUA_MULTRET, base);
     lua_remove(L, base); // Remove the message handler from the stack
 
     LuaRcode::from_raw_rcode(rcode)
 }
 
 
-/// Compiles, but does not execute, the given chunk.
-fn load_string(L: *mut lua_State, chunk: &str) -> LuaRcode {
+/// Compiles, but does not execute, the given chunk. Loading fails with a syntax error if the
+/// chunk is not of a kind allowed by the mode.
+fn load_string(L: *mut lua_State, chunk: &[u8], name: Option<&CStr>, mode: LuaChunkMode) -> LuaRcode {
+    let mode: &[u8] = match mode {
+        LuaChunkMode::Text => b"t\0",
+        LuaChunkMode::Binary => b"b\0",
+        LuaChunkMode::TextOrBinary => b"bt\0",
+    };
+
     let rcode = unsafe {
-        luaL_loadbuffer(
+        luaL_loadbufferx(
             L,
             chunk.as_ptr() as *const c_char,
             chunk.len() as libc::size_t,
-            ptr::null(),
+            name.map_or(ptr::null(), CStr::as_ptr),
+            mode.as_ptr() as *const c_char,
         )
     };
 
@@ -209,107 +821,248 @@ fn load_string(L: *mut lua_State, chunk: &str) -> LuaRcode {
 
 
 /// Extracts the specified number of values from the top of the stack
-unsafe fn dump_stack(L: *mut lua_State, num_values: i32) -> Vec<String> {
-    push_global(L, "tostring");
+unsafe fn dump_stack(L: *mut lua_State, num_values: i32) -> Vec<LuaString> {
+    (1 .. num_values + 1)
+        .map(|i| {
+            render_value(L, i).unwrap_or_else(|error| {
+                let type_name = type_name(L, i);
+                LuaString::from(format!("(error rendering {}: {})", type_name, error.message))
+            })
+        })
+        .collect()
+}
+
+
+/// Reads the specified number of values from the stack starting just above the given base
+/// index, leaving them in place.
+unsafe fn read_stack(L: *mut lua_State, base: i32, num_values: i32) -> Vec<LuaValue> {
+    (base + 1 .. base + num_values + 1)
+        .map(|idx| read_value(L, idx))
+        .collect()
+}
+
 
-    let mut values = Vec::with_capacity(num_values as usize);
-    for i in 1 .. num_values + 1 {
-        lua_pushvalue(L, -1); // Push the tostring function to the top of the stack
-        lua_pushvalue(L, i); // Push the ith argument passed to us to the top
-        lua_call(L, 1, 1);
+/// Pops the value on top of the stack and converts it.
+unsafe fn pop_value<V: FromLua>(L: *mut lua_State) -> Result<V, LuaError> {
+    let value = V::from_lua(L, -1);
+    lua_pop(L, 1);
+    value.map_err(LuaError::from)
+}
 
-        let value = stack_top_to_string(L);
-        values.push(value);
 
-        lua_pop(L, 1); // Remove the value from the stack
+/// Ensures that the given name can be passed to the Lua API as a C string.
+fn check_global_name(name: &str) -> Result<(), LuaError> {
+    if name.contains('\0') {
+        Err(LuaError::new(LuaErrorStatus::ConversionError, format!("global name {:?} contains a nul byte", name)))
+    } else {
+        Ok(())
     }
+}
 
-    values
+
+/// Pushes the value of the global variable named by the first argument.
+unsafe extern "C" fn get_global_value(L: *mut lua_State) -> c_int {
+    lua_getglobal(L, lua_tostring(L, 1));
+    1
+}
+
+
+/// Sets the global variable named by the first argument to the second argument.
+unsafe extern "C" fn set_global_value(L: *mut lua_State) -> c_int {
+    lua_pushglobaltable(L);
+    lua_insert(L, 2); // Place the global table below the value
+    lua_setfield(L, 2, lua_tostring(L, 1));
+    0
 }
 
 
 /// Retrieves all error information from the stack after an error is encountered in either the compiliation
 /// or execution of a chunk.
-unsafe fn get_execution_error(L: *mut lua_State, rcode: LuaRcode) -> LuaError {
+unsafe fn get_execution_error(L: *mut lua_State, rcode: LuaRcode, traceback: Vec<LuaStackFrame>) -> LuaError {
     let error_status = match rcode {
         LuaRcode::Yield => LuaErrorStatus::Yield,
         LuaRcode::ErrSyntax => LuaErrorStatus::SyntaxError,
         LuaRcode::ErrRun => LuaErrorStatus::RuntimeError,
+        LuaRcode::ErrMem => LuaErrorStatus::OutOfMemory,
         _ => LuaErrorStatus::InternalError,
     };
 
+    // Panics caught in Rust callbacks are raised as runtime errors with a dedicated error object.
+    if let Some(message) = read_panic(L, -1) {
+        return LuaError::with_traceback(LuaErrorStatus::Panic, message, traceback);
+    }
+
+    LuaError::with_traceback(error_status, stack_top_to_string(L), traceback)
+}
+
 
-    LuaError {
-        status: error_status,
-        message: stack_top_to_string(L),
+/// Calls the given C function in protected mode with the given number of arguments that are
+/// already on top of the stack, leaving the requested number of results on the stack. If an
+/// error is raised, nothing is left on the stack and the error is returned instead.
+unsafe fn protected_call(L: *mut lua_State, function: lua_CFunction, num_args: c_int, num_results: c_int)
+    -> Result<(), LuaError>
+{
+    lua_pushcfunction(L, function);
+    lua_insert(L, -(num_args + 1)); // Place the function below its arguments
+
+    let rcode = LuaRcode::from_raw_rcode(lua_pcall(L, num_args, num_results, 0));
+    if rcode == LuaRcode::Ok {
+        Ok(())
+    } else {
+        let error = get_execution_error(L, rcode, Vec::new());
+        lua_pop(L, 1); // Remove the error message from the stack
+        Err(error)
     }
 }
 
 
 /// Custom message handler invoked by the Lua runtime whenever an error is encountered
-/// executing a chunk.
+/// executing a chunk. Records the call stack in the vector passed as an up value, and turns the
+/// error object into a message.
 unsafe extern "C" fn message_handler(L: *mut lua_State) -> c_int {
-    let msg = lua_tostring(L, 1);
-    luaL_traceback(L, L, msg, 1); // Append a traceback to the message
-    LUA_ERRYIELD
+    let traceback = lua_touserdata(L, lua_upvalueindex(1)) as *mut Vec<LuaStackFrame>;
+    *traceback = catch_panic(L, || read_stack_frames(L, 1)); // Skip the message handler itself
+
+    // Leave the error object for a caught panic as it is, so that the panic can be recognised.
+    if lua_tostring(L, 1).is_null() && read_panic(L, 1).is_none() {
+        let tostring_name = b"__tostring\0";
+        let has_tostring = luaL_callmeta(L, 1, tostring_name.as_ptr() as *const c_char) != 0;
+        if !has_tostring || lua_type(L, -1) != LUA_TSTRING {
+            let message = format!("(error object is a {} value)", type_name(L, 1));
+            message.as_str().to_lua(L);
+        }
+    }
+
+    1
+}
+
+
+/// Returns the name of the type of the value at the given index.
+unsafe fn type_name(L: *mut lua_State, idx: c_int) -> String {
+    CStr::from_ptr(lua_typename(L, lua_type(L, idx))).to_string_lossy().into_owned()
+}
+
+
+/// Splits the position Lua puts in front of error messages, such as "stdin:3:" in
+/// "stdin:3:1: oops", into the chunk name and line number.
+fn parse_location(message: &str) -> Option<(String, u32)> {
+    let mut search_start = 0;
+    while let Some(offset) = message[search_start ..].find(':') {
+        let colon = search_start + offset;
+        let rest = &message[colon + 1 ..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+
+        if digits > 0 && rest[digits ..].starts_with(": ") {
+            return rest[.. digits].parse().ok().map(|line| (String::from(&message[.. colon]), line));
+        }
+
+        search_start = colon + 1;
+    }
+
+    None
 }
 
 
-/// Pushes the given global identifier to the top of the stack.
-unsafe fn push_global(L: *mut lua_State, name: &str) {
-    let to_string_name = CString::new(name).unwrap();
-    lua_getglobal(L, to_string_name.as_ptr());
+/// Invoked by the Lua runtime when an error is raised outside of protected mode, just before it
+/// aborts the process.
+unsafe extern "C" fn panic_handler(L: *mut lua_State) -> c_int {
+    let message = lua_tostring(L, -1);
+    let message = if message.is_null() {
+        String::from("error object is not a string")
+    } else {
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    };
+
+    eprintln!("PANIC: unprotected error in call to Lua API ({})", message);
+    0
 }
 
 
-/// Registers the custom print function as the default Lua print function. The given
-/// pointer is made available in the print as a light userdata value as one of the
-/// print function's up values.
-unsafe fn register_print(L: *mut lua_State, io_userdata: *mut c_void) {
+/// Pushes the table stored under the given name in the table on top of the stack, creating and
+/// storing a new table if there is not one already.
+unsafe fn push_subtable(L: *mut lua_State, name: &str) {
+    name.to_lua(L);
+    if lua_rawget(L, -2) != LUA_TTABLE {
+        lua_pop(L, 1); // Pop the non-table value
+
+        lua_newtable(L);
+        name.to_lua(L);
+        lua_pushvalue(L, -2);
+        lua_rawset(L, -4);
+    }
+}
+
+
+/// Registers the custom print function as the default Lua print function. The print function
+/// being replaced is kept in the registry to be restored, and as an up value of the custom print
+/// function, which falls back to it when called after the chunk has finished executing.
+unsafe fn register_print(L: *mut lua_State) {
+    let name = CString::new("print").unwrap();
+    let original_key = CString::new(ORIGINAL_PRINT).unwrap();
+    let console_key = CString::new(CONSOLE_PRINT).unwrap();
     lua_pushglobaltable(L);
-    
-    // Make a reference to the IO writer available as an up value in the
-    // print function.
-    lua_pushlightuserdata(L, io_userdata);
-    lua_pushcclosure(L, print, 1);
+
+    lua_getfield(L, -1, name.as_ptr());
+    lua_pushvalue(L, -1);
+    lua_setfield(L, LUA_REGISTRYINDEX, original_key.as_ptr());
 
     // Set the "print" value in the global table to our custom print function
-    let name = CString::new("print").unwrap();
+    lua_pushcclosure(L, print, 1);
+    lua_pushvalue(L, -1);
+    lua_setfield(L, LUA_REGISTRYINDEX, console_key.as_ptr());
     lua_setfield(L, -2, name.as_ptr());
 
     lua_pop(L, 1); // Pop the global table from the stack
 }
 
 
+/// Writer passed to lua_dump, which appends each piece of bytecode to the Vec<u8> given as the
+/// user data.
+unsafe extern "C" fn write_bytecode(_L: *mut lua_State, p: *const c_void, sz: libc::size_t, ud: *mut c_void) -> c_int {
+    let bytecode = &mut *(ud as *mut Vec<u8>);
+    bytecode.extend_from_slice(std::slice::from_raw_parts(p as *const u8, sz));
+    0
+}
+
+
 /// Custom print function that replaces the default Lua print function. This is invoked from the
 /// Lua library C code whenver the Lua function print is called.
 unsafe extern "C" fn print(L: *mut lua_State) -> c_int {
-    // Load the printer we saved in this function's closure
-    let io_idx = lua_upvalueindex(1);
-    let raw_io_ptr = lua_touserdata(L, io_idx);
+    let raw_io_ptr = get_owner(L).map_or(ptr::null_mut(), |owner| owner.io.get());
+    if raw_io_ptr.is_null() {
+        // No chunk is executing, so behave the same as the print function that was replaced
+        lua_pushvalue(L, lua_upvalueindex(1));
+        lua_insert(L, 1);
+        lua_call(L, lua_gettop(L) - 1, 0);
+        return 0;
+    }
+
     let io_box = &mut *(raw_io_ptr as *mut LuaIOBox);
     
     let num_params = lua_gettop(L);
     let values = dump_stack(L, num_params);
-    io_box.io.on_print(values);
+    catch_panic(L, || io_box.io.on_print(values));
 
     LUA_OK
 }
 
 
-/// Retrieves the string from the top of the stack.
+/// Retrieves the error message from the top of the stack. Bytes that are not valid UTF-8 are
+/// escaped so that the message can be displayed.
 unsafe fn stack_top_to_string(L: *mut lua_State) -> String {
-    let raw_value = lua_tostring(L, -1);
-    String::from(CStr::from_ptr(raw_value).to_str().unwrap())
+    match read_string(L, -1) {
+        Some(message) => message.to_string_escaped(),
+        None => format!("(error object is a {} value)", type_name(L, -1)),
+    }
 }
 
 
 /// Attempts to turn the given chunk into an expression by adding a "return" in
 /// front of it. Returns the status code from compiling the chunk with a return
-fn try_add_return(L: *mut lua_State, chunk: &str) -> LuaRcode {
+fn try_add_return(L: *mut lua_State, chunk: &str, name: Option<&CStr>) -> LuaRcode {
     let mut with_return = String::from("return ");
     with_return.push_str(chunk);
-    let rcode = load_string(L, &with_return);
+    let rcode = load_string(L, with_return.as_bytes(), name, LuaChunkMode::Text);
 
     if LuaRcode::Ok != rcode {
         unsafe { lua_pop(L, 1); } // Pop the result from load buffer
@@ -319,14 +1072,20 @@ fn try_add_return(L: *mut lua_State, chunk: &str) -> LuaRcode {
 }
 
 
-/// Unregisters the print function.
+/// Restores the print function replaced by register_print.
 unsafe fn unregister_print(L: *mut lua_State) {
-    // Set the global print functino to nil
-    lua_pushglobaltable(L);
-    lua_pushnil(L);
-    
     let name = CString::new("print").unwrap();
-    lua_setfield(L, -2, name.as_ptr());
+    let original_key = CString::new(ORIGINAL_PRINT).unwrap();
+    let console_key = CString::new(CONSOLE_PRINT).unwrap();
+    lua_pushglobaltable(L);
+
+    // Restore the original print function, unless the chunk replaced print with its own
+    lua_getfield(L, -1, name.as_ptr());
+    lua_getfield(L, LUA_REGISTRYINDEX, console_key.as_ptr());
+    if lua_rawequal(L, -1, -2) != 0 {
+        lua_getfield(L, LUA_REGISTRYINDEX, original_key.as_ptr());
+        lua_setfield(L, -4, name.as_ptr());
+    }
 
-    lua_pop(L, 1); // Remove the global table from the stack
+    lua_pop(L, 3); // Remove both print functions and the global table from the stack
 }
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use libc;
+
+use lua::{LuaIOBox, LuaStream};
+use lua::ffi::*;
+use lua::function::raise_panic;
+use lua::registry::get_owner;
+
+
+/// Name of the metatable that the io library gives to file handles.
+pub const FILE_HANDLE_METATABLE: &str = "FILE*";
+
+
+/// Registry key under which the io library keeps the default output file.
+const IO_OUTPUT: &str = "_IO_output";
+
+
+/// Format the io library writes floats with, the same as LUAI_NUMFFORMAT.
+const FLOAT_FORMAT: &[u8] = b"%.14g\0";
+
+
+/// Wraps "io.write" and the write method of file handles, so that while a chunk executes, what
+/// it writes to standard output or standard error is sent to its IO receiver. Writes to other
+/// files, and all writes made while no chunk is executing, are passed on to the original
+/// functions. Does nothing if the io library is not opened.
+pub unsafe fn capture_output(L: *mut lua_State) {
+    let top = lua_gettop(L);
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    let io_name = CString::new("io").unwrap();
+    let stdout_name = CString::new("stdout").unwrap();
+    let stderr_name = CString::new("stderr").unwrap();
+    let write_name = CString::new("write").unwrap();
+    let metatable_name = CString::new(FILE_HANDLE_METATABLE).unwrap();
+
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    if lua_getfield(L, -1, io_name.as_ptr()) != LUA_TTABLE {
+        lua_settop(L, top);
+        return;
+    }
+
+    let io_table = lua_gettop(L);
+    lua_getfield(L, io_table, stdout_name.as_ptr());
+    lua_getfield(L, io_table, stderr_name.as_ptr());
+    let (stdout, stderr) = (io_table + 1, io_table + 2);
+
+    if lua_getfield(L, io_table, write_name.as_ptr()) == LUA_TFUNCTION {
+        lua_pushvalue(L, stdout);
+        lua_pushcclosure(L, write_default_output, 2);
+        lua_setfield(L, io_table, write_name.as_ptr());
+    }
+    lua_settop(L, stderr);
+
+    if lua_getfield(L, LUA_REGISTRYINDEX, metatable_name.as_ptr()) == LUA_TTABLE {
+        let metatable = lua_gettop(L);
+        if lua_getfield(L, metatable, write_name.as_ptr()) == LUA_TFUNCTION {
+            lua_pushvalue(L, stdout);
+            lua_pushvalue(L, stderr);
+            lua_pushcclosure(L, write_file, 3);
+            lua_setfield(L, metatable, write_name.as_ptr());
+        }
+    }
+
+    lua_settop(L, top);
+}
+
+
+/// Replaces "io.write". The up values are the original function and the standard output file.
+/// Writes to the IO receiver if the default output file is still standard output.
+unsafe extern "C" fn write_default_output(L: *mut lua_State) -> c_int {
+    let io_output = CString::new(IO_OUTPUT).unwrap();
+    lua_getfield(L, LUA_REGISTRYINDEX, io_output.as_ptr());
+    let is_stdout = lua_rawequal(L, -1, lua_upvalueindex(2)) != 0;
+    lua_pop(L, 1);
+    drop(io_output);
+
+    if is_stdout && write_to_receiver(L, LuaStream::Stdout, 1) {
+        lua_pushvalue(L, lua_upvalueindex(2)); // Return the file, like the original function
+        return 1;
+    }
+
+    call_original(L)
+}
+
+
+/// Replaces the write method of file handles. The up values are the original method and the
+/// standard output and standard error files.
+unsafe extern "C" fn write_file(L: *mut lua_State) -> c_int {
+    let stream = if lua_rawequal(L, 1, lua_upvalueindex(2)) != 0 {
+        Some(LuaStream::Stdout)
+    } else if lua_rawequal(L, 1, lua_upvalueindex(3)) != 0 {
+        Some(LuaStream::Stderr)
+    } else {
+        None
+    };
+
+    match stream {
+        Some(stream) if write_to_receiver(L, stream, 2) => {
+            lua_settop(L, 1); // Return the file, like the original method
+            1
+        },
+        _ => call_original(L),
+    }
+}
+
+
+/// Calls the original function, kept as the first up value, with the arguments on the stack
+/// and returns all its results.
+pub unsafe fn call_original(L: *mut lua_State) -> c_int {
+    lua_pushvalue(L, lua_upvalueindex(1));
+    lua_insert(L, 1);
+    lua_call(L, lua_gettop(L) - 1, LUA_MULTRET);
+    lua_gettop(L)
+}
+
+
+/// Sends the arguments starting at the given index to the IO receiver of the executing chunk as
+/// one write. Arguments must be strings or numbers, and numbers are formatted the same way the
+/// io library formats them. Returns false without checking the arguments if no chunk is
+/// executing, or if the state is being closed.
+unsafe fn write_to_receiver(L: *mut lua_State, stream: LuaStream, first_arg: c_int) -> bool {
+    let raw_io_ptr = get_owner(L).map_or(ptr::null_mut(), |owner| owner.io.get());
+    if raw_io_ptr.is_null() {
+        return false;
+    }
+
+    // Raise errors for invalid arguments before anything is allocated, since raising an error
+    // skips destructors.
+    let num_args = lua_gettop(L);
+    for arg in first_arg ..= num_args {
+        if lua_type(L, arg) != LUA_TNUMBER {
+            luaL_checklstring(L, arg, ptr::null_mut());
+        }
+    }
+
+    // A panic in the IO receiver is only raised once the bytes are dropped.
+    let io_box = &mut *(raw_io_ptr as *mut LuaIOBox);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut bytes = Vec::new();
+        for arg in first_arg ..= num_args {
+            if lua_type(L, arg) == LUA_TNUMBER {
+                append_number(L, arg, &mut bytes);
+            } else {
+                let mut len = 0;
+                let data = lua_tolstring(L, arg, &mut len);
+                bytes.extend_from_slice(::std::slice::from_raw_parts(data as *const u8, len));
+            }
+        }
+        io_box.io.on_write(stream, &bytes)
+    }));
+
+    if let Err(payload) = result {
+        raise_panic(L, payload);
+    }
+    true
+}
+
+
+/// Formats the number at the given index and appends it to the given bytes.
+unsafe fn append_number(L: *mut lua_State, idx: c_int, bytes: &mut Vec<u8>) {
+    if lua_isinteger(L, idx) != 0 {
+        bytes.extend_from_slice(lua_tointeger(L, idx).to_string().as_bytes());
+        return;
+    }
+
+    let mut buffer = [0 as c_char; 64];
+    let len = libc::snprintf(
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        FLOAT_FORMAT.as_ptr() as *const c_char,
+        lua_tonumber(L, idx),
+    );
+    let len = (len.max(0) as usize).min(buffer.len() - 1);
+    bytes.extend_from_slice(::std::slice::from_raw_parts(buffer.as_ptr() as *const u8, len));
+}
@@ -0,0 +1,124 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_int;
+use std::ptr;
+
+use lua::{execute_function, pop_value, LuaError, LuaIO, LuaRcode};
+use lua::convert::{FromLua, LuaConversionError, ToLua, ToLuaMulti};
+use lua::ffi::*;
+use lua::registry::RegistryRef;
+use lua::value::{read_value, LuaValue};
+
+
+/// A handle to any Lua value, such as a function or table created by a chunk. The value is
+/// pinned in the registry so that it stays alive between chunks for as long as the handle
+/// exists, and is released once the last handle to it is dropped.
+#[derive(Clone)]
+pub struct LuaRef {
+    reference: RegistryRef,
+}
+
+
+impl LuaRef {
+    /// Pops the value on top of the stack and creates a handle to it.
+    pub(super) unsafe fn pop_from(L: *mut lua_State) -> LuaRef {
+        LuaRef{
+            reference: RegistryRef::pop_from(L),
+        }
+    }
+
+    /// Reads the current value. Tables and other objects are read as a handle and their string
+    /// form, not as their contents.
+    pub fn value(&self) -> LuaValue {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            let value = read_value(L, -1);
+            lua_pop(L, 1);
+            value
+        }
+    }
+
+    /// Converts the value into a Rust value, such as a LuaTable.
+    pub fn get<V: FromLua>(&self) -> Result<V, LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            pop_value(L)
+        }
+    }
+
+    /// Calls the value with the given arguments and returns its results. The call is made the
+    /// same way a chunk is executed, with print sent to the IO receiver and the execution
+    /// limits of the state enforced. When called from Rust code that a running chunk called,
+    /// the call counts against the limits of that chunk. Values that are not functions can only
+    /// be called if they have a "__call" metamethod.
+    pub fn call<A: ToLuaMulti>(&self, args: A, io: &mut dyn LuaIO) -> Result<Vec<LuaValue>, LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            execute_function(L, self.reference.owner(), io, |L| {
+                self.reference.push(L);
+                args.to_lua_multi(L);
+                LuaRcode::Ok
+            })
+        }
+    }
+}
+
+
+impl PartialEq for LuaRef {
+    /// Handles are equal if they refer to the same Lua value, compared without invoking the
+    /// "__eq" metamethod. Values of different Lua states are never equal.
+    fn eq(&self, other: &LuaRef) -> bool {
+        if !ptr::eq(self.reference.owner(), other.reference.owner()) {
+            return false;
+        }
+
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            other.reference.push(L);
+            let equal = lua_rawequal(L, -1, -2) != 0;
+            lua_pop(L, 2);
+            equal
+        }
+    }
+}
+
+
+impl fmt::Debug for LuaRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let L = self.reference.state();
+        let (type_name, address) = unsafe {
+            self.reference.push(L);
+            let type_name = lua_typename(L, lua_type(L, -1));
+            let address = lua_topointer(L, -1);
+            lua_pop(L, 1);
+            (CStr::from_ptr(type_name).to_string_lossy(), address)
+        };
+
+        write!(f, "LuaRef({}: {:p})", type_name, address)
+    }
+}
+
+
+impl FromLua for LuaRef {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaRef, LuaConversionError> {
+        lua_pushvalue(L, idx);
+        Ok(LuaRef::pop_from(L))
+    }
+}
+
+
+impl ToLua for LuaRef {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        (&self).to_lua(L);
+    }
+}
+
+
+impl ToLua for &LuaRef {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
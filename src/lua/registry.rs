@@ -0,0 +1,173 @@
+use std::cell::{Cell, RefCell};
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+use lua::LuaChunkMode;
+use lua::ffi::*;
+use lua::hook::{LuaHook, LuaHookMask};
+use lua::limits::LuaExecutionLimits;
+use lua::memory::MemoryTracker;
+
+
+/// Owns a lua_State and the data Rust keeps for it. The LuaState closes the state once it is
+/// dropped, while every handle into the Lua runtime shares ownership of the owner, so that the
+/// handles can tell that the state was closed instead of keeping it open. A handle that ends up
+/// owned by Lua, such as one captured by a registered function, would otherwise keep the state
+/// open forever.
+pub struct StateOwner {
+    pub L: *mut lua_State,
+
+    // Accounts for the state's allocations, so it must outlive the state itself.
+    pub memory: Box<MemoryTracker>,
+
+    // Limits enforced on all code run through the state or one of its threads.
+    pub limits: Cell<LuaExecutionLimits>,
+
+    // Kinds of chunks accepted from files and byte buffers.
+    pub chunk_mode: Cell<LuaChunkMode>,
+
+    // IO receiver of the chunk being executed, as a pointer to a LuaIOBox, or null while no
+    // chunk is executing.
+    pub io: Cell<*mut c_void>,
+
+    // Exit code passed to os.exit by the chunk being executed, if it called it.
+    pub exit_code: Cell<Option<i32>>,
+
+    // Hook set from Rust, and the events it is called for.
+    pub hook: RefCell<Option<LuaHook>>,
+    pub hook_mask: Cell<LuaHookMask>,
+
+    // Instructions left to execute before the hook is next called for a count event.
+    pub hook_countdown: Cell<u32>,
+
+    // Thread most recently resumed from Rust that is still running, or null if there is none.
+    pub resumed_thread: Cell<*mut lua_State>,
+
+    // Set once the state is being closed. Finalizers run by lua_close can still call back into
+    // Rust, but the owner can no longer be shared by then, and handles can no longer be used.
+    pub closing: Cell<bool>,
+}
+
+
+/// A Lua value pinned in the registry so that it is not garbage collected while Rust still holds
+/// on to it. The value is released from the registry when the reference is dropped. Using the
+/// reference after the LuaState is dropped panics.
+pub struct RegistryRef {
+    owner: Rc<StateOwner>,
+    key: c_int,
+}
+
+
+impl StateOwner {
+    /// Closes the state, unless it is already closed. Finalizers of the values left in the
+    /// state run while it is being closed.
+    pub fn close(&self) {
+        if !self.closing.replace(true) {
+            unsafe {
+                lua_close(self.L);
+            }
+        }
+    }
+}
+
+
+impl Drop for StateOwner {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+
+impl RegistryRef {
+    /// Pops the value on top of the stack and pins it in the registry.
+    pub unsafe fn pop_from(L: *mut lua_State) -> RegistryRef {
+        let owner = get_owner(L).expect("Lua value created while its state is being closed");
+        let key = luaL_ref(L, LUA_REGISTRYINDEX);
+
+        RegistryRef{
+            owner,
+            key,
+        }
+    }
+
+    /// Pushes the referenced value onto the stack of the given state, which must be the main
+    /// state or one of the threads of the Lua runtime the value belongs to.
+    pub unsafe fn push(&self, L: *mut lua_State) {
+        self.check_open();
+        assert!(
+            ptr::eq(get_owner_ptr(L), &*self.owner),
+            "Lua value used with a different Lua state than the one it was created in"
+        );
+        lua_rawgeti(L, LUA_REGISTRYINDEX, self.key as lua_Integer);
+    }
+
+    /// Returns the owner of the Lua runtime the value belongs to.
+    pub fn owner(&self) -> &StateOwner {
+        &self.owner
+    }
+
+    /// Returns the main state of the Lua runtime the value belongs to, with enough free stack
+    /// space to work with the value.
+    pub fn state(&self) -> *mut lua_State {
+        self.check_open();
+        let L = self.owner.L;
+        unsafe{ lua_checkstack(L, LUA_MINSTACK) };
+        L
+    }
+
+    /// Panics if the state the value belongs to has been closed.
+    fn check_open(&self) {
+        assert!(!self.owner.closing.get(), "Lua value used after its state was closed");
+    }
+}
+
+
+impl Clone for RegistryRef {
+    fn clone(&self) -> RegistryRef {
+        let L = self.state();
+        unsafe {
+            self.push(L);
+            RegistryRef::pop_from(L)
+        }
+    }
+}
+
+
+impl Drop for RegistryRef {
+    fn drop(&mut self) {
+        // The registry goes away with the state, so there is nothing to release once it closes.
+        if !self.owner.closing.get() {
+            unsafe {
+                luaL_unref(self.owner.L, LUA_REGISTRYINDEX, self.key);
+            }
+        }
+    }
+}
+
+
+/// Records the owner of the given state in the state's extra space. Lua copies the extra space
+/// of the main state into every new thread, so the owner can be found from any thread.
+pub unsafe fn set_owner(L: *mut lua_State, owner: &Rc<StateOwner>) {
+    let extra_space = lua_getextraspace(L) as *mut *const StateOwner;
+    *extra_space = &**owner as *const StateOwner;
+}
+
+
+/// Retrieves shared ownership of the state that the given state or thread belongs to. Returns
+/// None while the state is being closed, such as when finalizers run from lua_close.
+pub unsafe fn get_owner(L: *mut lua_State) -> Option<Rc<StateOwner>> {
+    let owner = get_owner_ptr(L);
+    if owner.is_null() || (*owner).closing.get() {
+        return None;
+    }
+
+    Rc::increment_strong_count(owner);
+    Some(Rc::from_raw(owner))
+}
+
+
+/// Retrieves the raw pointer to the owner stored in the state's extra space.
+unsafe fn get_owner_ptr(L: *mut lua_State) -> *const StateOwner {
+    *(lua_getextraspace(L) as *const *const StateOwner)
+}
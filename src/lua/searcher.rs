@@ -0,0 +1,95 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::ptr;
+
+use lua::{LuaError, LuaErrorStatus};
+use lua::convert::ToLua;
+use lua::ffi::*;
+
+
+/// Name of the registry table that maps the names of embedded modules to their loaders.
+const EMBEDDED_MODULES_TABLE: &str = "lua_console.EmbeddedModules";
+
+
+/// Position of the embedded module searcher in package.searchers. It comes right after the
+/// searcher for package.preload, so embedded modules are found before any file on package.path.
+const EMBEDDED_SEARCHER_POSITION: lua_Integer = 2;
+
+
+/// Pops the loader function on top of the stack and registers it as the loader of the embedded
+/// module with the given name, replacing any loader already registered under that name. The
+/// embedded module searcher is added to package.searchers along with the first module.
+pub unsafe fn add_module_loader(L: *mut lua_State, name: &str) -> Result<(), LuaError> {
+    let table_name = CString::new(EMBEDDED_MODULES_TABLE).unwrap();
+    let module_name = CString::new(name).map_err(|_| {
+        lua_pop(L, 1); // Pop the loader
+        LuaError::new(LuaErrorStatus::RuntimeError, format!("invalid module name '{}'", name))
+    })?;
+
+    if lua_getfield(L, LUA_REGISTRYINDEX, table_name.as_ptr()) != LUA_TTABLE {
+        lua_pop(L, 1); // Pop the missing table
+        if let Err(error) = add_searcher(L) {
+            lua_pop(L, 1); // Pop the loader
+            return Err(error);
+        }
+        luaL_getsubtable(L, LUA_REGISTRYINDEX, table_name.as_ptr());
+    }
+
+    lua_insert(L, -2); // Place the modules table under the loader
+    lua_setfield(L, -2, module_name.as_ptr());
+    lua_pop(L, 1); // Pop the modules table
+    Ok(())
+}
+
+
+/// Inserts the embedded module searcher into package.searchers. Fails if the package library
+/// has not been opened, since there is no require without it.
+unsafe fn add_searcher(L: *mut lua_State) -> Result<(), LuaError> {
+    let top = lua_gettop(L);
+    let loaded_table = CString::new(LUA_LOADED_TABLE).unwrap();
+    let package = CString::new("package").unwrap();
+    let searchers = CString::new("searchers").unwrap();
+
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, loaded_table.as_ptr());
+    let has_searchers = lua_getfield(L, -1, package.as_ptr()) == LUA_TTABLE
+        && lua_getfield(L, -1, searchers.as_ptr()) == LUA_TTABLE;
+    if !has_searchers {
+        lua_settop(L, top);
+        return Err(LuaError::new(
+            LuaErrorStatus::RuntimeError,
+            String::from("cannot add a module without the package library"),
+        ));
+    }
+
+    // Shift the later searchers up to make room for the new one
+    let num_searchers = lua_rawlen(L, -1) as lua_Integer;
+    for i in (EMBEDDED_SEARCHER_POSITION ..= num_searchers).rev() {
+        lua_rawgeti(L, -1, i);
+        lua_rawseti(L, -2, i + 1);
+    }
+
+    lua_pushcfunction(L, search_embedded_modules);
+    lua_rawseti(L, -2, EMBEDDED_SEARCHER_POSITION);
+
+    lua_settop(L, top);
+    Ok(())
+}
+
+
+/// Searcher called by require with the name of a module. Returns the module's loader if it is
+/// an embedded module, or otherwise a message that require adds to its list of places searched.
+unsafe extern "C" fn search_embedded_modules(L: *mut lua_State) -> c_int {
+    let name = luaL_checklstring(L, 1, ptr::null_mut());
+    let display_name = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+    let table_name = CString::new(EMBEDDED_MODULES_TABLE).unwrap();
+    lua_getfield(L, LUA_REGISTRYINDEX, table_name.as_ptr());
+    if lua_getfield(L, -1, name) != LUA_TNIL {
+        // Passed to the loader after the module name, as the file name is for modules in files
+        format!(":embedded:{}", display_name).as_str().to_lua(L);
+        return 2;
+    }
+
+    format!("\n\tno embedded module '{}'", display_name).as_str().to_lua(L);
+    1
+}
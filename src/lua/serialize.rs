@@ -0,0 +1,954 @@
+use std::error;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_void};
+use std::slice;
+use std::str;
+
+use libc;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use lua::convert::{FromLua, LuaConversionError, ToLua};
+use lua::ffi::*;
+use lua::registry::RegistryRef;
+
+
+/// Wraps a Rust value that is converted from Lua with serde. Tables become structs, maps,
+/// sequences and enums according to the type being deserialized. Metatables are ignored, so
+/// only the raw contents of tables are read.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LuaSerde<T>(pub T);
+
+
+/// A Lua value created by serializing a Rust value with LuaState::serialize. The value is kept
+/// alive until it has been stored somewhere in Lua.
+#[derive(Clone)]
+pub struct LuaSerialized {
+    reference: RegistryRef,
+}
+
+
+/// Error raised while converting between Lua and Rust values with serde.
+#[derive(Debug)]
+struct SerdeError {
+    message: String,
+}
+
+
+/// Serializes a Rust value by pushing the equivalent Lua value onto the stack.
+struct Serializer {
+    L: *mut lua_State,
+}
+
+
+/// Serializes the elements of a sequence or the fields of a struct into the table on top of the
+/// stack. For enum variants, the table is stored in the outer table under the variant's name
+/// once it is complete.
+struct TableSerializer {
+    L: *mut lua_State,
+    next_index: lua_Integer,
+    is_variant: bool,
+}
+
+
+/// Deserializes a Rust value from the Lua value at an absolute stack index.
+struct Deserializer<'a> {
+    L: *mut lua_State,
+    idx: c_int,
+
+    // Tables currently being deserialized, used to detect tables that contain themselves.
+    path: &'a mut Vec<*const c_void>,
+}
+
+
+/// Reads the elements 1 to n of a table for a sequence.
+struct SeqAccess<'a> {
+    L: *mut lua_State,
+    table: c_int,
+    next_index: lua_Integer,
+    len: lua_Integer,
+    path: &'a mut Vec<*const c_void>,
+}
+
+
+/// Reads the key/value pairs of a table for a map or a struct. While a pair is being read, its
+/// key and value are kept on the stack above the table.
+struct MapAccess<'a> {
+    L: *mut lua_State,
+    table: c_int,
+    path: &'a mut Vec<*const c_void>,
+}
+
+
+/// Reads an enum from either a string naming a unit variant or from a table holding a single
+/// entry that maps the variant name to its contents.
+struct EnumAccess<'a> {
+    L: *mut lua_State,
+    variant: String,
+    contents: Option<c_int>,
+    path: &'a mut Vec<*const c_void>,
+}
+
+
+impl LuaSerialized {
+    /// Serializes the value and pins the result in the registry of the given state.
+    pub(super) unsafe fn create<T: Serialize + ?Sized>(L: *mut lua_State, value: &T)
+        -> Result<LuaSerialized, LuaConversionError>
+    {
+        let top = lua_gettop(L);
+        match value.serialize(Serializer{ L }) {
+            Ok(()) => Ok(LuaSerialized{ reference: RegistryRef::pop_from(L) }),
+            Err(error) => {
+                lua_settop(L, top); // Remove any partially built tables
+                Err(LuaConversionError{ message: error.message })
+            },
+        }
+    }
+}
+
+
+impl ToLua for LuaSerialized {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        (&self).to_lua(L);
+    }
+}
+
+
+impl ToLua for &LuaSerialized {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
+
+
+impl<T: DeserializeOwned> FromLua for LuaSerde<T> {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaSerde<T>, LuaConversionError> {
+        let top = lua_gettop(L);
+        let mut path = Vec::new();
+        let deserializer = Deserializer{
+            L,
+            idx: lua_absindex(L, idx),
+            path: &mut path,
+        };
+
+        let value = T::deserialize(deserializer);
+        lua_settop(L, top);
+
+        value
+            .map(LuaSerde)
+            .map_err(|error| LuaConversionError{ message: error.message })
+    }
+}
+
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+
+impl error::Error for SerdeError {}
+
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(message: T) -> SerdeError {
+        SerdeError{ message: message.to_string() }
+    }
+}
+
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(message: T) -> SerdeError {
+        SerdeError{ message: message.to_string() }
+    }
+}
+
+
+impl SerdeError {
+    fn new(message: String) -> SerdeError {
+        SerdeError{ message }
+    }
+
+    /// Creates an error describing that the value at the given stack index is not of the
+    /// expected type.
+    unsafe fn type_mismatch(L: *mut lua_State, idx: c_int, expected: &str) -> SerdeError {
+        SerdeError{ message: LuaConversionError::type_mismatch(L, idx, expected).message }
+    }
+}
+
+
+impl Serializer {
+    /// Makes room on the stack for a value and the table it is stored in. Deeply nested values
+    /// can run out of stack space, which is reported as an error.
+    unsafe fn reserve(&self) -> Result<(), SerdeError> {
+        if lua_checkstack(self.L, 3) == 0 {
+            Err(SerdeError::new(String::from("value is nested too deeply to serialize")))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pushes a new table to serialize a compound value into. Variants are stored in an outer
+    /// table under their name.
+    fn push_table(self, len: usize, variant: Option<&'static str>) -> Result<TableSerializer, SerdeError> {
+        unsafe {
+            self.reserve()?;
+            if let Some(variant) = variant {
+                lua_createtable(self.L, 0, 1);
+                variant.to_lua(self.L);
+            }
+            lua_createtable(self.L, len as c_int, 0);
+        }
+
+        Ok(TableSerializer{
+            L: self.L,
+            next_index: 1,
+            is_variant: variant.is_some(),
+        })
+    }
+}
+
+
+impl ser::Serializer for Serializer {
+    type Ok = ();
+    type Error = SerdeError;
+    type SerializeSeq = TableSerializer;
+    type SerializeTuple = TableSerializer;
+    type SerializeTupleStruct = TableSerializer;
+    type SerializeTupleVariant = TableSerializer;
+    type SerializeMap = TableSerializer;
+    type SerializeStruct = TableSerializer;
+    type SerializeStructVariant = TableSerializer;
+
+    fn serialize_bool(self, value: bool) -> Result<(), SerdeError> {
+        unsafe{ value.to_lua(self.L) };
+        Ok(())
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<(), SerdeError> {
+        unsafe{ lua_pushinteger(self.L, value) };
+        Ok(())
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<(), SerdeError> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<(), SerdeError> {
+        if value > i64::MAX as u64 {
+            return Err(SerdeError::new(format!("integer {} is too large for a Lua integer", value)));
+        }
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<(), SerdeError> {
+        self.serialize_f64(f64::from(value))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<(), SerdeError> {
+        unsafe{ lua_pushnumber(self.L, value) };
+        Ok(())
+    }
+
+    fn serialize_char(self, value: char) -> Result<(), SerdeError> {
+        self.serialize_str(value.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<(), SerdeError> {
+        unsafe{ value.to_lua(self.L) };
+        Ok(())
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<(), SerdeError> {
+        unsafe{ lua_pushlstring(self.L, value.as_ptr() as *const c_char, value.len() as libc::size_t) };
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), SerdeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), SerdeError> {
+        unsafe{ lua_pushnil(self.L) };
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerdeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+        -> Result<(), SerdeError>
+    {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+        -> Result<(), SerdeError>
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        let L = self.L;
+        unsafe {
+            self.reserve()?;
+            lua_createtable(L, 0, 1);
+            variant.to_lua(L);
+            value.serialize(Serializer{ L })?;
+            lua_rawset(L, -3);
+        }
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<TableSerializer, SerdeError> {
+        self.push_table(len.unwrap_or(0), None)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<TableSerializer, SerdeError> {
+        self.push_table(len, None)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<TableSerializer, SerdeError> {
+        self.push_table(len, None)
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize)
+        -> Result<TableSerializer, SerdeError>
+    {
+        self.push_table(len, Some(variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<TableSerializer, SerdeError> {
+        self.push_table(0, None)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<TableSerializer, SerdeError> {
+        self.push_table(0, None)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize)
+        -> Result<TableSerializer, SerdeError>
+    {
+        self.push_table(0, Some(variant))
+    }
+}
+
+
+impl TableSerializer {
+    /// Appends the value to the sequence being built.
+    fn push_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(Serializer{ L: self.L })?;
+        unsafe{ lua_rawseti(self.L, -2, self.next_index) };
+        self.next_index += 1;
+        Ok(())
+    }
+
+    /// Stores the value under the given field name.
+    fn push_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerdeError> {
+        unsafe{ key.to_lua(self.L) };
+        value.serialize(Serializer{ L: self.L })?;
+        unsafe{ lua_rawset(self.L, -3) };
+        Ok(())
+    }
+
+    /// Completes the table, storing it in the outer table if it holds the contents of a variant.
+    fn finish(self) -> Result<(), SerdeError> {
+        if self.is_variant {
+            unsafe{ lua_rawset(self.L, -3) };
+        }
+        Ok(())
+    }
+}
+
+
+impl ser::SerializeSeq for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeTuple for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeTupleStruct for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeTupleVariant for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeMap for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        key.serialize(Serializer{ L: self.L })?;
+
+        // Lua tables cannot hold nil or NaN keys, and storing one would raise an error outside
+        // of protected mode.
+        unsafe {
+            let key_type = lua_type(self.L, -1);
+            if key_type == LUA_TNIL {
+                return Err(SerdeError::new(String::from("map key serializes to nil")));
+            }
+            if key_type == LUA_TNUMBER && lua_tonumber(self.L, -1).is_nan() {
+                return Err(SerdeError::new(String::from("map key serializes to NaN")));
+            }
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(Serializer{ L: self.L })?;
+        unsafe{ lua_rawset(self.L, -3) };
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeStruct for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerdeError> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl ser::SerializeStructVariant for TableSerializer {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerdeError> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+
+impl<'a> Deserializer<'a> {
+    /// Checks that the value is a table that is not already being deserialized, and records it
+    /// as being deserialized. The table must be left again with leave_table.
+    unsafe fn enter_table(&mut self) -> Result<(), SerdeError> {
+        if lua_type(self.L, self.idx) != LUA_TTABLE {
+            return Err(SerdeError::type_mismatch(self.L, self.idx, "table"));
+        }
+
+        let address = lua_topointer(self.L, self.idx);
+        if self.path.contains(&address) {
+            return Err(SerdeError::new(String::from("cannot deserialize a table that contains itself")));
+        }
+        if lua_checkstack(self.L, 3) == 0 {
+            return Err(SerdeError::new(String::from("table is nested too deeply to deserialize")));
+        }
+
+        self.path.push(address);
+        Ok(())
+    }
+
+    fn leave_table(&mut self) {
+        self.path.pop();
+    }
+
+    /// Deserializes a table as a sequence of the values stored under the keys 1 to n.
+    fn deserialize_table_seq<'de, V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            self.enter_table()?;
+            let len = lua_rawlen(self.L, self.idx) as lua_Integer;
+            let value = visitor.visit_seq(SeqAccess{
+                L: self.L,
+                table: self.idx,
+                next_index: 1,
+                len,
+                path: &mut *self.path,
+            });
+            self.leave_table();
+            value
+        }
+    }
+
+    /// Deserializes a table as a map of its key/value pairs.
+    fn deserialize_table_map<'de, V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            self.enter_table()?;
+            lua_pushnil(self.L); // Start iterating the table from its first key
+            let value = visitor.visit_map(MapAccess{
+                L: self.L,
+                table: self.idx,
+                path: &mut *self.path,
+            });
+            self.leave_table();
+            value
+        }
+    }
+
+    /// Returns true if the table at the index has a value under each of the keys 1 to n, and no
+    /// other keys. Empty tables are not sequences.
+    unsafe fn is_sequence(&self) -> bool {
+        let len = lua_rawlen(self.L, self.idx) as lua_Integer;
+        if len == 0 {
+            return false;
+        }
+
+        let mut num_entries = 0;
+        lua_pushnil(self.L);
+        while lua_next(self.L, self.idx) != 0 {
+            num_entries += 1;
+            let in_range = lua_type(self.L, -2) == LUA_TNUMBER && lua_isinteger(self.L, -2) != 0 && {
+                let key = lua_tointeger(self.L, -2);
+                key >= 1 && key <= len
+            };
+            lua_pop(self.L, 1); // Pop the value, leaving the key for the next iteration
+
+            if !in_range {
+                lua_pop(self.L, 1); // Pop the key
+                return false;
+            }
+        }
+
+        num_entries == len
+    }
+
+    /// Deserializes an integer, accepting floats that have an exact integer representation.
+    fn deserialize_integer<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            if lua_type(self.L, self.idx) == LUA_TNUMBER {
+                let mut is_integer = 0;
+                let value = lua_tointegerx(self.L, self.idx, &mut is_integer);
+                if is_integer != 0 {
+                    return visitor.visit_i64(value);
+                }
+            }
+        }
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
+
+
+macro_rules! deserialize_integer {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                self.deserialize_integer(visitor)
+            }
+        )*
+    }
+}
+
+
+impl<'de, 'a> de::Deserializer<'de> for Deserializer<'a> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            match lua_type(self.L, self.idx) {
+                LUA_TNIL => visitor.visit_unit(),
+                LUA_TBOOLEAN => visitor.visit_bool(lua_toboolean(self.L, self.idx) != 0),
+                LUA_TNUMBER => {
+                    if lua_isinteger(self.L, self.idx) != 0 {
+                        visitor.visit_i64(lua_tointeger(self.L, self.idx))
+                    } else {
+                        visitor.visit_f64(lua_tonumber(self.L, self.idx))
+                    }
+                },
+                LUA_TSTRING => match read_str(self.L, self.idx) {
+                    Ok(Some(value)) => visitor.visit_str(value),
+                    _ => visitor.visit_bytes(read_bytes(self.L, self.idx).unwrap()),
+                },
+                LUA_TTABLE => {
+                    if self.is_sequence() {
+                        self.deserialize_table_seq(visitor)
+                    } else {
+                        self.deserialize_table_map(visitor)
+                    }
+                },
+                _ => {
+                    let type_name = CStr::from_ptr(lua_typename(self.L, lua_type(self.L, self.idx)));
+                    Err(SerdeError::new(format!("cannot deserialize a {} value", type_name.to_string_lossy())))
+                },
+            }
+        }
+    }
+
+    deserialize_integer!{
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            if lua_type(self.L, self.idx) == LUA_TNUMBER {
+                return visitor.visit_f64(lua_tonumber(self.L, self.idx));
+            }
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match unsafe{ read_str(self.L, self.idx)? } {
+            Some(value) => visitor.visit_str(value),
+            None => Err(unsafe{ SerdeError::type_mismatch(self.L, self.idx, "string") }),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match unsafe{ read_str(self.L, self.idx)? } {
+            Some(value) => visitor.visit_str(value),
+            None => Err(unsafe{ SerdeError::type_mismatch(self.L, self.idx, "string") }),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match unsafe{ read_bytes(self.L, self.idx) } {
+            Some(bytes) => visitor.visit_bytes(bytes),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if unsafe{ lua_type(self.L, self.idx) } == LUA_TNIL {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if unsafe{ lua_type(self.L, self.idx) } == LUA_TNIL {
+            visitor.visit_unit()
+        } else {
+            Err(unsafe{ SerdeError::type_mismatch(self.L, self.idx, "nil") })
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, SerdeError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_table_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_table_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V)
+        -> Result<V::Value, SerdeError>
+    {
+        self.deserialize_table_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_table_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, SerdeError>
+    {
+        self.deserialize_table_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        unsafe {
+            if let Some(variant) = read_str(self.L, self.idx)? {
+                return visitor.visit_enum(EnumAccess{
+                    L: self.L,
+                    variant: String::from(variant),
+                    contents: None,
+                    path: &mut *self.path,
+                });
+            }
+
+            // Any other variant is a table with the variant name as its only key.
+            let expected = format!("string or single entry table for enum {}", name);
+            if lua_type(self.L, self.idx) != LUA_TTABLE {
+                return Err(SerdeError::type_mismatch(self.L, self.idx, &expected));
+            }
+            self.enter_table()?;
+
+            lua_pushnil(self.L);
+            let has_entry = lua_next(self.L, self.idx) != 0;
+            let variant = if has_entry { read_str(self.L, -2)? } else { None };
+            let is_single_entry = has_entry && {
+                lua_pushvalue(self.L, -2);
+                let has_next = lua_next(self.L, self.idx) != 0;
+                lua_pop(self.L, if has_next { 2 } else { 0 });
+                !has_next
+            };
+
+            let variant = match variant {
+                Some(variant) if is_single_entry => String::from(variant),
+                _ => {
+                    self.leave_table();
+                    return Err(SerdeError::new(format!("{} expected, got table", expected)));
+                },
+            };
+
+            let value = visitor.visit_enum(EnumAccess{
+                L: self.L,
+                variant,
+                contents: Some(lua_gettop(self.L)),
+                path: &mut *self.path,
+            });
+            lua_pop(self.L, 2); // Pop the variant name and contents
+            self.leave_table();
+            value
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqAccess<'a> {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, SerdeError> {
+        if self.next_index > self.len {
+            return Ok(None);
+        }
+
+        unsafe {
+            lua_rawgeti(self.L, self.table, self.next_index);
+            self.next_index += 1;
+
+            let value = seed.deserialize(Deserializer{
+                L: self.L,
+                idx: lua_gettop(self.L),
+                path: &mut *self.path,
+            });
+            lua_pop(self.L, 1);
+            value.map(Some)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.next_index + 1) as usize)
+    }
+}
+
+
+impl<'de, 'a> de::MapAccess<'de> for MapAccess<'a> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, SerdeError> {
+        unsafe {
+            // The previous key is on top of the stack, or nil before the first key.
+            if lua_next(self.L, self.table) == 0 {
+                return Ok(None);
+            }
+
+            let key = seed.deserialize(Deserializer{
+                L: self.L,
+                idx: lua_gettop(self.L) - 1,
+                path: &mut *self.path,
+            });
+
+            key.map(Some).map_err(|error| {
+                SerdeError::new(format!("invalid table key: {}", error.message))
+            })
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, SerdeError> {
+        unsafe {
+            let value = seed.deserialize(Deserializer{
+                L: self.L,
+                idx: lua_gettop(self.L),
+                path: &mut *self.path,
+            });
+            lua_pop(self.L, 1); // Pop the value, leaving the key for the next iteration
+            value
+        }
+    }
+}
+
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumAccess<'a> {
+    type Error = SerdeError;
+    type Variant = EnumAccess<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, EnumAccess<'a>), SerdeError> {
+        let variant: de::value::StringDeserializer<SerdeError> = self.variant.clone().into_deserializer();
+        let value = seed.deserialize(variant)?;
+        Ok((value, self))
+    }
+}
+
+
+impl<'de, 'a> de::VariantAccess<'de> for EnumAccess<'a> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, SerdeError> {
+        seed.deserialize(self.contents_deserializer()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, SerdeError> {
+        self.contents_deserializer()?.deserialize_table_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, SerdeError>
+    {
+        self.contents_deserializer()?.deserialize_table_map(visitor)
+    }
+}
+
+
+impl<'a> EnumAccess<'a> {
+    /// Returns a deserializer for the contents of a variant, which only variants written as a
+    /// table have.
+    fn contents_deserializer(self) -> Result<Deserializer<'a>, SerdeError> {
+        match self.contents {
+            Some(idx) => Ok(Deserializer{ L: self.L, idx, path: self.path }),
+            None => Err(SerdeError::new(format!("variant {} expects a value", self.variant))),
+        }
+    }
+}
+
+
+/// Reads the value at the given stack index as a UTF-8 string, if it is a string.
+unsafe fn read_str<'s>(L: *mut lua_State, idx: c_int) -> Result<Option<&'s str>, SerdeError> {
+    match read_bytes(L, idx) {
+        Some(bytes) => str::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| SerdeError::new(String::from("string is not valid UTF-8"))),
+        None => Ok(None),
+    }
+}
+
+
+/// Reads the value at the given stack index as raw bytes, if it is a string. The bytes remain
+/// valid for as long as the string stays on the stack.
+unsafe fn read_bytes<'s>(L: *mut lua_State, idx: c_int) -> Option<&'s [u8]> {
+    if lua_type(L, idx) != LUA_TSTRING {
+        return None;
+    }
+
+    let mut len = 0;
+    let raw_value = lua_tolstring(L, idx, &mut len);
+    Some(slice::from_raw_parts(raw_value as *const u8, len))
+}
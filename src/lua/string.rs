@@ -0,0 +1,198 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::os::raw::{c_char, c_int};
+use std::slice;
+use std::str::{self, Utf8Error};
+
+use libc;
+
+use lua::convert::{FromLua, LuaConversionError, ToLua};
+use lua::ffi::*;
+
+
+/// A Lua string. Lua strings are arbitrary byte sequences that may contain embedded nul bytes
+/// and need not be valid UTF-8, so the exact bytes are kept and converting to a Rust string is
+/// left to the caller.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct LuaString {
+    bytes: Vec<u8>,
+}
+
+
+impl LuaString {
+    /// Returns the bytes of the string.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consumes the string, returning its bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the number of bytes in the string.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns true if the string has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns the string as a Rust string, failing if it is not valid UTF-8.
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self.bytes)
+    }
+
+    /// Converts the string into a Rust string, replacing any invalid UTF-8 sequences with the
+    /// replacement character.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+
+    /// Converts the string into a Rust string, writing every byte that is not part of a valid
+    /// UTF-8 sequence as a "\xNN" escape the same way it could be written in Lua source.
+    pub fn to_string_escaped(&self) -> String {
+        let mut escaped = String::with_capacity(self.bytes.len());
+        let mut remaining = &self.bytes[..];
+
+        loop {
+            match str::from_utf8(remaining) {
+                Ok(valid) => {
+                    escaped.push_str(valid);
+                    return escaped;
+                },
+                Err(error) => {
+                    let (valid, invalid) = remaining.split_at(error.valid_up_to());
+                    escaped.push_str(str::from_utf8(valid).unwrap());
+
+                    let invalid_len = error.error_len().unwrap_or(invalid.len());
+                    for byte in &invalid[.. invalid_len] {
+                        escaped.push_str(&format!("\\x{:02x}", byte));
+                    }
+                    remaining = &invalid[invalid_len ..];
+                },
+            }
+        }
+    }
+}
+
+
+impl fmt::Display for LuaString {
+    /// Displays the string with invalid UTF-8 bytes escaped.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_escaped())
+    }
+}
+
+
+impl fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_string_escaped())
+    }
+}
+
+
+impl<'a> From<&'a str> for LuaString {
+    fn from(value: &'a str) -> LuaString {
+        LuaString{ bytes: value.as_bytes().to_vec() }
+    }
+}
+
+
+impl From<String> for LuaString {
+    fn from(value: String) -> LuaString {
+        LuaString{ bytes: value.into_bytes() }
+    }
+}
+
+
+impl<'a> From<&'a [u8]> for LuaString {
+    fn from(value: &'a [u8]) -> LuaString {
+        LuaString{ bytes: value.to_vec() }
+    }
+}
+
+
+impl From<Vec<u8>> for LuaString {
+    fn from(bytes: Vec<u8>) -> LuaString {
+        LuaString{ bytes }
+    }
+}
+
+
+impl PartialEq<str> for LuaString {
+    fn eq(&self, other: &str) -> bool {
+        self.bytes == other.as_bytes()
+    }
+}
+
+
+impl<'a> PartialEq<&'a str> for LuaString {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.bytes == other.as_bytes()
+    }
+}
+
+
+impl PartialEq<LuaString> for str {
+    fn eq(&self, other: &LuaString) -> bool {
+        self.as_bytes() == other.bytes.as_slice()
+    }
+}
+
+
+impl PartialEq<LuaString> for &str {
+    fn eq(&self, other: &LuaString) -> bool {
+        self.as_bytes() == other.bytes.as_slice()
+    }
+}
+
+
+impl FromLua for LuaString {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaString, LuaConversionError> {
+        let value_type = lua_type(L, idx);
+        if value_type != LUA_TSTRING && value_type != LUA_TNUMBER {
+            return Err(LuaConversionError::type_mismatch(L, idx, "string"));
+        }
+
+        // Convert a copy of the value so that numbers are not turned into strings in place.
+        lua_pushvalue(L, idx);
+        let value = read_string(L, -1);
+        lua_pop(L, 1);
+
+        Ok(value.unwrap())
+    }
+}
+
+
+impl ToLua for LuaString {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        (&self).to_lua(L);
+    }
+}
+
+
+impl ToLua for &LuaString {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        lua_pushlstring(L, self.bytes.as_ptr() as *const c_char, self.bytes.len() as libc::size_t);
+    }
+}
+
+
+/// Reads the string at the given stack index, including any embedded nul bytes. Returns None if
+/// the value is not a string. Numbers are converted to strings in place, as lua_tolstring does.
+///
+/// # Safety
+/// The state must be valid and the index must be an acceptable index into its stack.
+pub unsafe fn read_string(L: *mut lua_State, idx: c_int) -> Option<LuaString> {
+    let mut len = 0;
+    let raw_value = lua_tolstring(L, idx, &mut len);
+    if raw_value.is_null() {
+        None
+    } else {
+        let bytes = slice::from_raw_parts(raw_value as *const u8, len);
+        Some(LuaString::from(bytes))
+    }
+}
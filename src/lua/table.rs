@@ -0,0 +1,246 @@
+use std::fmt;
+use std::os::raw::c_int;
+
+use lua::{pop_value, protected_call, LuaError, LuaErrorStatus};
+use lua::convert::{FromLua, LuaConversionError, ToLua};
+use lua::ffi::*;
+use lua::registry::RegistryRef;
+
+
+/// A handle to a Lua table. The table is kept alive for as long as the handle exists, even if it
+/// is no longer reachable from Lua, but not beyond the LuaState it belongs to. Using the handle
+/// once the state is dropped panics.
+#[derive(Clone)]
+pub struct LuaTable {
+    reference: RegistryRef,
+}
+
+
+impl LuaTable {
+    /// Creates a new, empty table in the given state.
+    pub(super) unsafe fn create(L: *mut lua_State) -> LuaTable {
+        lua_newtable(L);
+        LuaTable::pop_from(L)
+    }
+
+    /// Pops the table on top of the stack and creates a handle to it.
+    pub(super) unsafe fn pop_from(L: *mut lua_State) -> LuaTable {
+        LuaTable{
+            reference: RegistryRef::pop_from(L),
+        }
+    }
+
+    /// Returns the value stored under the given key, invoking the "__index" metamethod if the
+    /// key is not present in the table.
+    pub fn get<K: ToLua, V: FromLua>(&self, key: K) -> Result<V, LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            key.to_lua(L);
+            protected_call(L, get_field, 2, 1)?;
+            pop_value(L)
+        }
+    }
+
+    /// Stores the value under the given key, invoking the "__newindex" metamethod if the key is
+    /// not already present in the table.
+    pub fn set<K: ToLua, V: ToLua>(&self, key: K, value: V) -> Result<(), LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            key.to_lua(L);
+            value.to_lua(L);
+            protected_call(L, set_field, 3, 0)
+        }
+    }
+
+    /// Returns the value stored under the given key without invoking any metamethods.
+    pub fn raw_get<K: ToLua, V: FromLua>(&self, key: K) -> Result<V, LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            key.to_lua(L);
+            lua_rawget(L, -2);
+            lua_remove(L, -2); // Remove the table from under the value
+            pop_value(L)
+        }
+    }
+
+    /// Stores the value under the given key without invoking any metamethods.
+    pub fn raw_set<K: ToLua, V: ToLua>(&self, key: K, value: V) -> Result<(), LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            key.to_lua(L);
+
+            // Lua raises an error for keys that can never be stored in a table, which must be
+            // caught here since lua_rawset is not called in protected mode.
+            let key_type = lua_type(L, -1);
+            let invalid_key = if key_type == LUA_TNIL {
+                Some("index is nil")
+            } else if key_type == LUA_TNUMBER && lua_tonumber(L, -1).is_nan() {
+                Some("index is NaN")
+            } else {
+                None
+            };
+
+            if let Some(message) = invalid_key {
+                lua_pop(L, 2); // Pop the key and the table
+                return Err(LuaError::new(LuaErrorStatus::RuntimeError, String::from(message)));
+            }
+
+            value.to_lua(L);
+            lua_rawset(L, -3);
+            lua_pop(L, 1); // Pop the table
+            Ok(())
+        }
+    }
+
+    /// Returns the length of the table as given by the Lua length operator, which invokes the
+    /// "__len" metamethod if there is one.
+    pub fn len(&self) -> Result<i64, LuaError> {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            protected_call(L, get_length, 1, 1)?;
+            pop_value(L)
+        }
+    }
+
+    /// Returns the length of the table without invoking any metamethods.
+    pub fn raw_len(&self) -> usize {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            let len = lua_rawlen(L, -1);
+            lua_pop(L, 1);
+            len as usize
+        }
+    }
+
+    /// Returns true if the table has no entries at all.
+    pub fn is_empty(&self) -> bool {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            lua_pushnil(L);
+            let has_entries = lua_next(L, -2) != 0;
+            let num_values = if has_entries { 3 } else { 1 };
+            lua_pop(L, num_values);
+            !has_entries
+        }
+    }
+
+    /// Returns all of the key/value pairs in the table, in the order given by the Lua "next"
+    /// function. The pairs are read without invoking any metamethods.
+    pub fn pairs<K: FromLua, V: FromLua>(&self) -> Result<Vec<(K, V)>, LuaError> {
+        let L = self.reference.state();
+        let mut pairs = Vec::new();
+        unsafe {
+            self.reference.push(L);
+            lua_pushnil(L);
+            while lua_next(L, -2) != 0 {
+                let pair = K::from_lua(L, -2).and_then(|key| V::from_lua(L, -1).map(|value| (key, value)));
+                lua_pop(L, 1); // Pop the value, leaving the key for the next iteration
+
+                match pair {
+                    Ok(pair) => pairs.push(pair),
+                    Err(error) => {
+                        lua_pop(L, 2); // Pop the key and the table
+                        return Err(LuaError::from(error));
+                    },
+                }
+            }
+
+            lua_pop(L, 1); // Pop the table
+        }
+
+        Ok(pairs)
+    }
+
+    /// Returns the values stored under the keys 1, 2, 3, ... up to the first nil value, the
+    /// same way the Lua "ipairs" function would. Values are read with the "__index" metamethod.
+    pub fn ipairs<V: FromLua>(&self) -> Result<Vec<V>, LuaError> {
+        let L = self.reference.state();
+        let mut values = Vec::new();
+        unsafe {
+            for index in 1 .. {
+                self.reference.push(L);
+                lua_pushinteger(L, index);
+                protected_call(L, get_field, 2, 1)?;
+
+                if lua_type(L, -1) == LUA_TNIL {
+                    lua_pop(L, 1);
+                    break;
+                }
+
+                values.push(pop_value(L)?);
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+
+impl fmt::Debug for LuaTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let L = self.reference.state();
+        let address = unsafe {
+            self.reference.push(L);
+            let address = lua_topointer(L, -1);
+            lua_pop(L, 1);
+            address
+        };
+
+        write!(f, "LuaTable({:p})", address)
+    }
+}
+
+
+impl FromLua for LuaTable {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaTable, LuaConversionError> {
+        if lua_type(L, idx) != LUA_TTABLE {
+            return Err(LuaConversionError::type_mismatch(L, idx, "table"));
+        }
+
+        lua_pushvalue(L, idx);
+        Ok(LuaTable::pop_from(L))
+    }
+}
+
+
+impl ToLua for LuaTable {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        (&self).to_lua(L);
+    }
+}
+
+
+impl ToLua for &LuaTable {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
+
+
+/// Indexes the table passed as the first argument with the key passed as the second argument.
+unsafe extern "C" fn get_field(L: *mut lua_State) -> c_int {
+    lua_gettable(L, 1);
+    1
+}
+
+
+/// Stores the third argument in the table passed as the first argument under the key passed as
+/// the second argument.
+unsafe extern "C" fn set_field(L: *mut lua_State) -> c_int {
+    lua_settable(L, 1);
+    0
+}
+
+
+/// Returns the length of the value passed as the first argument.
+unsafe extern "C" fn get_length(L: *mut lua_State) -> c_int {
+    lua_pushinteger(L, luaL_len(L, 1));
+    1
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::mem;
+use std::os::raw::c_int;
+
+use lua::{get_execution_error, read_stack, IORegistrationHandle, LuaError, LuaErrorStatus, LuaIO, LuaRcode};
+use lua::convert::{FromLua, LuaConversionError, ToLua, ToLuaMulti};
+use lua::debug::read_stack_frames;
+use lua::exit::take_exit_request;
+use lua::ffi::*;
+use lua::limits::BudgetRegistrationHandle;
+use lua::registry::RegistryRef;
+use lua::value::LuaValue;
+
+
+/// A handle to a Lua thread, also known as a coroutine. The thread is kept alive for as long as
+/// the handle exists, even if it is no longer reachable from Lua.
+#[derive(Clone)]
+pub struct LuaThread {
+    reference: RegistryRef,
+}
+
+
+/// The outcome of resuming a thread.
+#[derive(PartialEq, Debug)]
+pub enum LuaResume {
+    /// The thread yielded the given values, or was suspended by a hook with none, and can be
+    /// resumed again.
+    Yielded(Vec<LuaValue>),
+
+    /// The thread's function returned the given values. The thread cannot be resumed again.
+    Finished(Vec<LuaValue>),
+
+    /// The thread raised an error. The thread cannot be resumed again.
+    Error(LuaError),
+}
+
+
+impl LuaThread {
+    /// Creates a new thread that will run the function on top of the stack of the given state,
+    /// popping the function.
+    pub(super) unsafe fn create(L: *mut lua_State) -> LuaThread {
+        let thread = lua_newthread(L);
+        lua_insert(L, -2); // Place the thread below the function
+        lua_xmove(L, thread, 1);
+        LuaThread::pop_from(L)
+    }
+
+    /// Pops the thread on top of the stack and creates a handle to it.
+    pub(super) unsafe fn pop_from(L: *mut lua_State) -> LuaThread {
+        LuaThread{
+            reference: RegistryRef::pop_from(L),
+        }
+    }
+
+    /// Returns true if the thread can be resumed, which is the case if it has not been started
+    /// yet or is suspended in a yield.
+    pub fn is_resumable(&self) -> bool {
+        unsafe{ is_resumable(self.thread_state()) }
+    }
+
+    /// Starts or continues running the thread. When starting the thread, the arguments are
+    /// passed to its function. When continuing it, they are returned from the call to
+    /// "coroutine.yield" that suspended it. Output printed by the thread is sent to the IO
+    /// receiver, and the execution limits of the state apply until the thread yields or stops.
+    pub fn resume<A: ToLuaMulti>(&self, args: A, io: &mut dyn LuaIO) -> LuaResume {
+        let L = self.reference.state();
+        let thread = self.thread_state();
+
+        unsafe {
+            if !is_resumable(thread) {
+                let message = if lua_status(thread) == LUA_OK && lua_gettop(thread) == 0 {
+                    "cannot resume dead coroutine"
+                } else {
+                    "cannot resume non-suspended coroutine"
+                };
+                return LuaResume::Error(LuaError::new(LuaErrorStatus::RuntimeError, String::from(message)));
+            }
+
+            lua_checkstack(thread, LUA_MINSTACK);
+            let num_args = args.to_lua_multi(thread);
+
+            let owner = self.reference.owner();
+            let _io_handle = IORegistrationHandle::new(L, io);
+            let budget_handle = BudgetRegistrationHandle::new(thread, owner.limits.get());
+            let memory_handle = owner.memory.enforce_limit();
+
+            let previous_thread = owner.resumed_thread.replace(thread);
+            let rcode = LuaRcode::from_raw_rcode(lua_resume(thread, L, num_args));
+            owner.resumed_thread.set(previous_thread);
+            drop(memory_handle);
+
+            if let Some(error) = take_exit_request(owner) {
+                return LuaResume::Error(error);
+            }
+
+            match rcode {
+                LuaRcode::Ok | LuaRcode::Yield => {
+                    // Values are read on the main state, since no calls can be made on a
+                    // suspended thread.
+                    let num_values = lua_gettop(thread);
+                    lua_checkstack(L, num_values);
+                    lua_xmove(thread, L, num_values);
+
+                    let initial_stack = lua_gettop(L) - num_values;
+                    let values = read_stack(L, initial_stack, num_values);
+                    lua_pop(L, num_values);
+
+                    if rcode == LuaRcode::Yield {
+                        LuaResume::Yielded(values)
+                    } else {
+                        LuaResume::Finished(values)
+                    }
+                },
+                _ => {
+                    // The stack of a thread that raised an error is left as it was, so the
+                    // traceback can still be read from it.
+                    let traceback = read_stack_frames(thread, 0);
+                    let mut error = get_execution_error(thread, rcode, traceback);
+                    if budget_handle.exceeded() {
+                        error.status = LuaErrorStatus::Timeout;
+                    }
+                    LuaResume::Error(error)
+                },
+            }
+        }
+    }
+
+    /// Returns the lua_State of the thread itself. The thread is kept alive by the handle.
+    fn thread_state(&self) -> *mut lua_State {
+        let L = self.reference.state();
+        unsafe {
+            self.reference.push(L);
+            let thread = lua_tothread(L, -1);
+            lua_pop(L, 1);
+            thread
+        }
+    }
+}
+
+
+impl fmt::Debug for LuaThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LuaThread({:p})", self.thread_state())
+    }
+}
+
+
+impl FromLua for LuaThread {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaThread, LuaConversionError> {
+        if lua_type(L, idx) != LUA_TTHREAD {
+            return Err(LuaConversionError::type_mismatch(L, idx, "thread"));
+        }
+
+        lua_pushvalue(L, idx);
+        Ok(LuaThread::pop_from(L))
+    }
+}
+
+
+impl ToLua for LuaThread {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        (&self).to_lua(L);
+    }
+}
+
+
+impl ToLua for &LuaThread {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
+
+
+/// Returns true if the thread is suspended in a yield, or has a function to run and has not been
+/// started yet. This follows the checks made by "coroutine.resume".
+unsafe fn is_resumable(thread: *mut lua_State) -> bool {
+    match lua_status(thread) {
+        LUA_YIELD => true,
+        LUA_OK => {
+            let mut ar: lua_Debug = mem::zeroed();
+            lua_getstack(thread, 0, &mut ar) == 0 && lua_gettop(thread) > 0
+        },
+        _ => false,
+    }
+}
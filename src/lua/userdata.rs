@@ -0,0 +1,460 @@
+use std::any::{self, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::cmp;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use libc;
+
+use lua::convert::{FromLua, FromLuaMulti, LuaConversionError, ToLua, ToLuaMulti};
+use lua::ffi::*;
+use lua::function::{argument_error, push_rust_function, raise_error, raise_panic, RustFunction};
+use lua::registry::RegistryRef;
+
+
+/// Rust types that can be pushed into Lua as full userdata. The value is moved into memory owned
+/// by Lua, so Lua code and Rust handles to the value all work with the same object rather than
+/// with copies of it. The value is dropped once Lua garbage collects it.
+pub trait LuaUserData: 'static + Sized {
+    /// Name of the type as shown in Lua error messages and by "tostring".
+    fn type_name() -> &'static str {
+        any::type_name::<Self>()
+    }
+
+    /// Adds the methods, fields and metamethods that Lua code can use on values of this type.
+    fn add_methods(_methods: &mut LuaUserDataMethods<Self>) {
+    }
+}
+
+
+/// Metamethods that can be implemented for userdata.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LuaMetaMethod {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Unm,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    BNot,
+    Shl,
+    Shr,
+    Concat,
+    Len,
+    Eq,
+    Lt,
+    Le,
+    Call,
+    ToString,
+}
+
+
+/// Collects the methods, fields and metamethods of a userdata type when its metatable is built.
+pub struct LuaUserDataMethods<T> {
+    methods: Vec<(String, RustFunction)>,
+    getters: Vec<(String, RustFunction)>,
+    setters: Vec<(String, RustFunction)>,
+    meta_methods: Vec<(LuaMetaMethod, RustFunction)>,
+    _type: PhantomData<T>,
+}
+
+
+/// A handle to a userdata value living inside of Lua. The value is kept alive for as long as the
+/// handle exists.
+pub struct LuaUserDataRef<T> {
+    reference: RegistryRef,
+    cell: *const RefCell<T>,
+}
+
+
+impl LuaMetaMethod {
+    /// Returns the name of the metatable field holding the metamethod.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LuaMetaMethod::Add => "__add",
+            LuaMetaMethod::Sub => "__sub",
+            LuaMetaMethod::Mul => "__mul",
+            LuaMetaMethod::Div => "__div",
+            LuaMetaMethod::Mod => "__mod",
+            LuaMetaMethod::Pow => "__pow",
+            LuaMetaMethod::Unm => "__unm",
+            LuaMetaMethod::IDiv => "__idiv",
+            LuaMetaMethod::BAnd => "__band",
+            LuaMetaMethod::BOr => "__bor",
+            LuaMetaMethod::BXor => "__bxor",
+            LuaMetaMethod::BNot => "__bnot",
+            LuaMetaMethod::Shl => "__shl",
+            LuaMetaMethod::Shr => "__shr",
+            LuaMetaMethod::Concat => "__concat",
+            LuaMetaMethod::Len => "__len",
+            LuaMetaMethod::Eq => "__eq",
+            LuaMetaMethod::Lt => "__lt",
+            LuaMetaMethod::Le => "__le",
+            LuaMetaMethod::Call => "__call",
+            LuaMetaMethod::ToString => "__tostring",
+        }
+    }
+}
+
+
+impl<T: LuaUserData> LuaUserDataMethods<T> {
+    fn new() -> LuaUserDataMethods<T> {
+        LuaUserDataMethods{
+            methods: Vec::new(),
+            getters: Vec::new(),
+            setters: Vec::new(),
+            meta_methods: Vec::new(),
+            _type: PhantomData,
+        }
+    }
+
+    /// Adds a method, called from Lua as "value:name(...)", that can read the value.
+    pub fn add_method<A, R, F>(&mut self, name: &str, method: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + Fn(&T, A) -> Result<R, String>
+    {
+        let function = borrowing_method(name, method);
+        self.methods.push((String::from(name), function));
+    }
+
+    /// Adds a method, called from Lua as "value:name(...)", that can modify the value.
+    pub fn add_method_mut<A, R, F>(&mut self, name: &str, method: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + Fn(&mut T, A) -> Result<R, String>
+    {
+        let function = mutably_borrowing_method(name, method);
+        self.methods.push((String::from(name), function));
+    }
+
+    /// Adds a field, read from Lua as "value.name", whose value is given by the getter.
+    pub fn add_field_getter<R, F>(&mut self, name: &str, getter: F)
+        where R: ToLua,
+              F: 'static + Fn(&T) -> Result<R, String>
+    {
+        let function = borrowing_method(name, move |value: &T, ()| getter(value));
+        self.getters.push((String::from(name), function));
+    }
+
+    /// Adds a field, assigned from Lua as "value.name = x", that is stored by the setter.
+    pub fn add_field_setter<V, F>(&mut self, name: &str, setter: F)
+        where V: FromLua,
+              F: 'static + Fn(&mut T, V) -> Result<(), String>
+    {
+        let function = mutably_borrowing_method(name, setter);
+        self.setters.push((String::from(name), function));
+    }
+
+    /// Adds a metamethod whose first argument must be a value of this type.
+    pub fn add_meta_method<A, R, F>(&mut self, meta_method: LuaMetaMethod, method: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + Fn(&T, A) -> Result<R, String>
+    {
+        let function = borrowing_method(meta_method.name(), method);
+        self.meta_methods.push((meta_method, function));
+    }
+
+    /// Adds a metamethod that receives all of its operands as plain arguments. This is needed for
+    /// binary operators, where the value of this type may be either operand.
+    pub fn add_meta_function<A, R, F>(&mut self, meta_method: LuaMetaMethod, function: F)
+        where A: FromLuaMulti,
+              R: ToLuaMulti,
+              F: 'static + Fn(A) -> Result<R, String>
+    {
+        let name = meta_method.name();
+        let function: RustFunction = Box::new(move |L| unsafe {
+            let num_args = lua_gettop(L);
+            let args = A::from_lua_multi(L, 1, num_args)
+                .map_err(|(position, error)| argument_error(position, name, error))?;
+            let results = function(args)?;
+            Ok(results.to_lua_multi(L))
+        });
+        self.meta_methods.push((meta_method, function));
+    }
+}
+
+
+impl<T: LuaUserData> LuaUserDataRef<T> {
+    /// Pops the userdata on top of the stack, which must be of type T, and creates a handle to it.
+    pub(super) unsafe fn pop_from(L: *mut lua_State) -> LuaUserDataRef<T> {
+        let cell = lua_touserdata(L, -1) as *const RefCell<T>;
+        LuaUserDataRef{
+            reference: RegistryRef::pop_from(L),
+            cell,
+        }
+    }
+
+    /// Immutably borrows the value. Panics if the value is currently mutably borrowed, such as by
+    /// a method that is still running.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        unsafe{ (*self.cell).borrow() }
+    }
+
+    /// Mutably borrows the value. Panics if the value is currently borrowed, such as by a method
+    /// that is still running.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        unsafe{ (*self.cell).borrow_mut() }
+    }
+}
+
+
+impl<T> Clone for LuaUserDataRef<T> {
+    fn clone(&self) -> LuaUserDataRef<T> {
+        LuaUserDataRef{
+            reference: self.reference.clone(),
+            cell: self.cell,
+        }
+    }
+}
+
+
+impl<T: LuaUserData> FromLua for LuaUserDataRef<T> {
+    unsafe fn from_lua(L: *mut lua_State, idx: c_int) -> Result<LuaUserDataRef<T>, LuaConversionError> {
+        check_userdata::<T>(L, idx)?;
+        lua_pushvalue(L, idx);
+        Ok(LuaUserDataRef::pop_from(L))
+    }
+}
+
+
+impl<T> ToLua for LuaUserDataRef<T> {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
+
+
+impl<T> ToLua for &LuaUserDataRef<T> {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        self.reference.push(L);
+    }
+}
+
+
+impl<T: LuaUserData> ToLua for T {
+    unsafe fn to_lua(self, L: *mut lua_State) {
+        push_userdata(L, self);
+    }
+}
+
+
+/// Moves the given value into a new userdata object on top of the stack.
+pub unsafe fn push_userdata<T: LuaUserData>(L: *mut lua_State, value: T) {
+    // Lua only aligns userdata memory as strictly as any of the basic C types it uses.
+    let max_align = cmp::max(mem::align_of::<lua_Number>(), cmp::max(mem::align_of::<lua_Integer>(), mem::align_of::<*mut c_void>()));
+    assert!(
+        mem::align_of::<RefCell<T>>() <= max_align,
+        "{} is too strictly aligned to be stored as Lua userdata", T::type_name()
+    );
+
+    let cell = lua_newuserdata(L, mem::size_of::<RefCell<T>>() as libc::size_t) as *mut RefCell<T>;
+    ptr::write(cell, RefCell::new(value));
+
+    push_metatable::<T>(L);
+    lua_setmetatable(L, -2);
+}
+
+
+/// Returns the value held by the userdata at the given stack index, if it is of type T.
+///
+/// # Safety
+/// The state must be valid and the index must be an acceptable index into its stack.
+pub unsafe fn check_userdata<T: LuaUserData>(L: *mut lua_State, idx: c_int) -> Result<*const RefCell<T>, LuaConversionError> {
+    let name = metatable_name::<T>();
+    let cell = luaL_testudata(L, idx, name.as_ptr()) as *const RefCell<T>;
+
+    if cell.is_null() {
+        Err(LuaConversionError::type_mismatch(L, idx, T::type_name()))
+    } else {
+        Ok(cell)
+    }
+}
+
+
+/// Returns the name the metatable for userdata of type T is registered under. The name is based
+/// on the type's TypeId since the user facing type name is not guaranteed to be unique.
+fn metatable_name<T: 'static>() -> CString {
+    CString::new(format!("lua_console.{:?}", TypeId::of::<T>())).unwrap()
+}
+
+
+/// Pushes the metatable shared by all userdata of type T, creating it the first time it is needed.
+unsafe fn push_metatable<T: LuaUserData>(L: *mut lua_State) {
+    let name = metatable_name::<T>();
+    if luaL_newmetatable(L, name.as_ptr()) == 0 {
+        return;
+    }
+
+    let mut methods = LuaUserDataMethods::new();
+    T::add_methods(&mut methods);
+
+    set_field(L, "__name", T::type_name());
+    set_field(L, "__metatable", false); // Keep Lua code from reaching __gc through getmetatable
+
+    "__gc".to_lua(L);
+    lua_pushcfunction(L, gc_userdata::<T>);
+    lua_rawset(L, -3);
+
+    "__index".to_lua(L);
+    push_function_table(L, methods.methods);
+    push_function_table(L, methods.getters);
+    lua_pushcclosure(L, index_userdata, 2);
+    lua_rawset(L, -3);
+
+    "__newindex".to_lua(L);
+    push_function_table(L, methods.setters);
+    T::type_name().to_lua(L);
+    lua_pushcclosure(L, newindex_userdata, 2);
+    lua_rawset(L, -3);
+
+    for (meta_method, function) in methods.meta_methods {
+        meta_method.name().to_lua(L);
+        push_rust_function(L, function);
+        lua_rawset(L, -3);
+    }
+}
+
+
+/// Sets a field in the table on top of the stack without invoking any metamethods.
+unsafe fn set_field<V: ToLua>(L: *mut lua_State, name: &str, value: V) {
+    name.to_lua(L);
+    value.to_lua(L);
+    lua_rawset(L, -3);
+}
+
+
+/// Pushes a new table mapping each of the given names to its function.
+unsafe fn push_function_table(L: *mut lua_State, functions: Vec<(String, RustFunction)>) {
+    lua_newtable(L);
+    for (name, function) in functions {
+        name.to_lua(L);
+        push_rust_function(L, function);
+        lua_rawset(L, -3);
+    }
+}
+
+
+/// Wraps a method that borrows the userdata passed as the first argument.
+fn borrowing_method<T, A, R, F>(name: &str, method: F) -> RustFunction
+    where T: LuaUserData,
+          A: FromLuaMulti,
+          R: ToLuaMulti,
+          F: 'static + Fn(&T, A) -> Result<R, String>
+{
+    let name = String::from(name);
+    Box::new(move |L| unsafe {
+        let (cell, args) = read_method_args::<T, A>(L, &name)?;
+        let value = (*cell).try_borrow()
+            .map_err(|_| format!("{} is already being modified", T::type_name()))?;
+
+        let results = method(&value, args)?;
+        Ok(results.to_lua_multi(L))
+    })
+}
+
+
+/// Wraps a method that mutably borrows the userdata passed as the first argument.
+fn mutably_borrowing_method<T, A, R, F>(name: &str, method: F) -> RustFunction
+    where T: LuaUserData,
+          A: FromLuaMulti,
+          R: ToLuaMulti,
+          F: 'static + Fn(&mut T, A) -> Result<R, String>
+{
+    let name = String::from(name);
+    Box::new(move |L| unsafe {
+        let (cell, args) = read_method_args::<T, A>(L, &name)?;
+        let mut value = (*cell).try_borrow_mut()
+            .map_err(|_| format!("{} is already in use", T::type_name()))?;
+
+        let results = method(&mut value, args)?;
+        Ok(results.to_lua_multi(L))
+    })
+}
+
+
+/// Reads the userdata passed as the first argument to a method along with the remaining arguments.
+unsafe fn read_method_args<T, A>(L: *mut lua_State, name: &str) -> Result<(*const RefCell<T>, A), String>
+    where T: LuaUserData,
+          A: FromLuaMulti
+{
+    let cell = check_userdata::<T>(L, 1).map_err(|error| argument_error(1, name, error))?;
+
+    let num_args = cmp::max(lua_gettop(L) - 1, 0);
+    let args = A::from_lua_multi(L, 2, num_args)
+        .map_err(|(position, error)| argument_error(position + 1, name, error))?;
+
+    Ok((cell, args))
+}
+
+
+/// Garbage collection metamethod that drops the Rust value held in a userdata object.
+unsafe extern "C" fn gc_userdata<T: LuaUserData>(L: *mut lua_State) -> c_int {
+    let cell = lua_touserdata(L, 1) as *mut RefCell<T>;
+    let dropped = panic::catch_unwind(AssertUnwindSafe(|| ptr::drop_in_place(cell)));
+
+    // Remove the metatable so that the value can no longer be used if it is resurrected by
+    // another finalizer.
+    lua_pushnil(L);
+    lua_setmetatable(L, 1);
+
+    if let Err(payload) = dropped {
+        raise_panic(L, payload);
+    }
+    0
+}
+
+
+/// Index metamethod for userdata. Methods are looked up in the table held in the first up value
+/// and field getters in the table held in the second up value.
+unsafe extern "C" fn index_userdata(L: *mut lua_State) -> c_int {
+    lua_pushvalue(L, 2);
+    if lua_rawget(L, lua_upvalueindex(1)) != LUA_TNIL {
+        return 1;
+    }
+    lua_pop(L, 1);
+
+    lua_pushvalue(L, 2);
+    if lua_rawget(L, lua_upvalueindex(2)) != LUA_TNIL {
+        lua_pushvalue(L, 1);
+        lua_call(L, 1, 1); // Call the getter with the userdata
+        return 1;
+    }
+
+    0
+}
+
+
+/// New index metamethod for userdata. Field setters are looked up in the table held in the first
+/// up value, and the type name is held in the second up value.
+unsafe extern "C" fn newindex_userdata(L: *mut lua_State) -> c_int {
+    lua_pushvalue(L, 2);
+    if lua_rawget(L, lua_upvalueindex(1)) != LUA_TNIL {
+        lua_pushvalue(L, 1);
+        lua_pushvalue(L, 3);
+        lua_call(L, 2, 0); // Call the setter with the userdata and the new value
+        return 0;
+    }
+
+    let message = {
+        let type_name = String::from_lua(L, lua_upvalueindex(2)).unwrap_or_default();
+        if lua_type(L, 2) == LUA_TSTRING {
+            let field = String::from_lua(L, 2).unwrap_or_default();
+            format!("cannot set unknown field '{}' of {}", field, type_name)
+        } else {
+            format!("cannot set unknown field of {}", type_name)
+        }
+    };
+
+    raise_error(L, message)
+}
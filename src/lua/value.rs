@@ -0,0 +1,161 @@
+use std::fmt;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use libc;
+
+use lua::{protected_call, LuaError};
+use lua::ffi::*;
+use lua::string::{read_string, LuaString};
+
+
+/// A typed value read from the Lua stack.
+#[derive(Clone, PartialEq, Debug)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(LuaString),
+    Table(LuaObject),
+    Function(LuaObject),
+    UserData(LuaObject),
+    Thread(LuaObject),
+}
+
+
+/// A value that only lives inside of the Lua runtime, such as a table or a function. Once the
+/// value is removed from the stack all that survives is its identity and its string form.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LuaObject {
+    /// The address of the object inside of the Lua runtime. Two objects with the same address
+    /// refer to the same Lua value.
+    pub address: usize,
+
+    /// The string representation of the object as produced by the Lua "tostring" function, or
+    /// the message of the error raised by its "__tostring" metamethod.
+    pub display: Result<String, String>,
+}
+
+
+impl LuaValue {
+    /// Returns the name of the Lua type of this value, as the Lua "type" function would.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            LuaValue::Nil => "nil",
+            LuaValue::Boolean(_) => "boolean",
+            LuaValue::Integer(_) | LuaValue::Float(_) => "number",
+            LuaValue::String(_) => "string",
+            LuaValue::Table(_) => "table",
+            LuaValue::Function(_) => "function",
+            LuaValue::UserData(_) => "userdata",
+            LuaValue::Thread(_) => "thread",
+        }
+    }
+}
+
+
+impl fmt::Display for LuaValue {
+    /// Renders the value the same way the Lua "tostring" function would.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LuaValue::Nil => write!(f, "nil"),
+            LuaValue::Boolean(value) => write!(f, "{}", value),
+            LuaValue::Integer(value) => write!(f, "{}", value),
+            LuaValue::Float(value) => write!(f, "{}", format_float(value)),
+            LuaValue::String(ref value) => write!(f, "{}", value),
+            LuaValue::Table(ref object) |
+            LuaValue::Function(ref object) |
+            LuaValue::UserData(ref object) |
+            LuaValue::Thread(ref object) => match object.display {
+                Ok(ref display) => write!(f, "{}", display),
+                Err(ref message) => write!(f, "(error rendering {}: {})", self.type_name(), message),
+            },
+        }
+    }
+}
+
+
+/// Reads the value at the given stack index without removing it from the stack.
+pub unsafe fn read_value(L: *mut lua_State, idx: i32) -> LuaValue {
+    let idx = lua_absindex(L, idx);
+
+    match lua_type(L, idx) {
+        LUA_TBOOLEAN => LuaValue::Boolean(lua_toboolean(L, idx) != 0),
+        LUA_TNUMBER => {
+            if lua_isinteger(L, idx) != 0 {
+                LuaValue::Integer(lua_tointeger(L, idx))
+            } else {
+                LuaValue::Float(lua_tonumber(L, idx))
+            }
+        },
+        LUA_TSTRING => LuaValue::String(read_string(L, idx).unwrap()),
+        LUA_TTABLE => LuaValue::Table(read_object(L, idx)),
+        LUA_TFUNCTION => LuaValue::Function(read_object(L, idx)),
+        LUA_TUSERDATA | LUA_TLIGHTUSERDATA => LuaValue::UserData(read_object(L, idx)),
+        LUA_TTHREAD => LuaValue::Thread(read_object(L, idx)),
+        _ => LuaValue::Nil,
+    }
+}
+
+
+/// Converts the value at the given stack index to a string the same way the Lua "tostring"
+/// function does, using the "__tostring" and "__name" metafields. The conversion runs in
+/// protected mode so that errors raised by "__tostring" are returned instead of unwinding
+/// through the caller. It does not depend on the global "tostring", which chunks may replace.
+///
+/// # Safety
+/// The state must be valid and the index must be an acceptable index into its stack.
+pub unsafe fn render_value(L: *mut lua_State, idx: c_int) -> Result<LuaString, LuaError> {
+    lua_pushvalue(L, idx);
+    protected_call(L, to_display_string, 1, 1)?;
+
+    let display = read_string(L, -1);
+    lua_pop(L, 1); // Remove the string representation from the stack
+    Ok(display.unwrap())
+}
+
+
+/// Reads the identity and string representation of the Lua object at the given absolute
+/// stack index.
+unsafe fn read_object(L: *mut lua_State, idx: i32) -> LuaObject {
+    let address = lua_topointer(L, idx) as usize;
+    let display = render_value(L, idx)
+        .map(|display| display.to_string())
+        .map_err(|error| error.message);
+
+    LuaObject{
+        address,
+        display,
+    }
+}
+
+
+/// Converts the value passed as the first argument into its string representation.
+unsafe extern "C" fn to_display_string(L: *mut lua_State) -> c_int {
+    luaL_tolstring(L, 1, ptr::null_mut());
+    1
+}
+
+
+/// Formats a floating point number the way Lua does, which always includes either a decimal
+/// point or an exponent so that floats can be told apart from integers.
+fn format_float(value: f64) -> String {
+    let mut buffer = [0u8; 64];
+    let format = b"%.14g\0";
+    let len = unsafe {
+        libc::snprintf(
+            buffer.as_mut_ptr() as *mut c_char,
+            buffer.len(),
+            format.as_ptr() as *const c_char,
+            value,
+        )
+    };
+
+    let mut formatted = String::from_utf8_lossy(&buffer[..len as usize]).into_owned();
+    if formatted.chars().all(|c| c == '-' || c.is_ascii_digit()) {
+        formatted.push_str(".0");
+    }
+
+    formatted
+}
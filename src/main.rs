@@ -1,10 +1,22 @@
 extern crate lua_console;
 
+use std::env;
+
+use lua_console::lua::LuaChunkMode;
 use lua_console::repl::ConsoleRepl;
 
 
 fn main() {
-    let mut repl = ConsoleRepl::new();
+    let sandboxed = env::args().skip(1).any(|arg| arg == "--sandbox");
+    let bytecode = env::args().skip(1).any(|arg| arg == "--bytecode");
+    let mut repl = if sandboxed {
+        ConsoleRepl::sandboxed()
+    } else {
+        ConsoleRepl::new()
+    };
+    if bytecode {
+        repl.set_chunk_mode(LuaChunkMode::TextOrBinary);
+    }
     repl.run_repl();
 }
 
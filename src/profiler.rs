@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use lua::{LuaFunctionKind, LuaHookEvent, LuaStackFrame};
+
+
+/// Call stacks sampled while a chunk ran, kept in the folded format that flame graph tools read:
+/// the names of the functions on the stack from the outermost in, separated by semicolons.
+pub struct Profile {
+    stacks: HashMap<String, usize>,
+    num_samples: usize,
+}
+
+
+/// How often a function was sampled.
+pub struct FunctionSamples {
+    pub function: String,
+
+    /// Samples taken while the function itself was running.
+    pub self_samples: usize,
+
+    /// Samples taken while the function was anywhere on the stack.
+    pub total_samples: usize,
+}
+
+
+/// Samples the call stack of a running chunk from a count hook.
+pub struct Profiler {
+    profile: Rc<RefCell<Profile>>,
+}
+
+
+impl Profile {
+    /// Returns the number of call stacks sampled.
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    /// Returns the functions with the most samples of their own, most sampled first.
+    pub fn hottest_functions(&self, count: usize) -> Vec<FunctionSamples> {
+        let mut functions: HashMap<&str, FunctionSamples> = HashMap::new();
+        for (stack, &samples) in &self.stacks {
+            let frames: Vec<&str> = stack.split(';').collect();
+            for (i, &frame) in frames.iter().enumerate() {
+                let function = functions.entry(frame).or_insert_with(|| FunctionSamples{
+                    function: String::from(frame),
+                    self_samples: 0,
+                    total_samples: 0,
+                });
+
+                if i + 1 == frames.len() {
+                    function.self_samples += samples;
+                }
+                // Recursive functions count once per sample
+                if !frames[.. i].contains(&frame) {
+                    function.total_samples += samples;
+                }
+            }
+        }
+
+        let mut functions: Vec<FunctionSamples> = functions.into_values().collect();
+        functions.sort_by(|a, b| {
+            b.self_samples.cmp(&a.self_samples)
+                .then(b.total_samples.cmp(&a.total_samples))
+                .then(a.function.cmp(&b.function))
+        });
+        functions.truncate(count);
+        functions
+    }
+
+    /// Writes the sampled stacks in the folded format, one stack per line followed by the number
+    /// of times it was sampled.
+    pub fn write_folded<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(&String, &usize)> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, samples) in stacks {
+            writeln!(writer, "{} {}", stack, samples)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a sample of the given call stack, which starts with the running function.
+    pub fn record(&mut self, stack: &[LuaStackFrame]) {
+        if stack.is_empty() {
+            return;
+        }
+
+        let frames: Vec<String> = stack.iter().rev().map(function_label).collect();
+        *self.stacks.entry(frames.join(";")).or_insert(0) += 1;
+        self.num_samples += 1;
+    }
+}
+
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler{
+            profile: Rc::new(RefCell::new(Profile{
+                stacks: HashMap::new(),
+                num_samples: 0,
+            })),
+        }
+    }
+
+    /// Returns the hook to call on count events, which samples the call stack.
+    pub fn hook(&self) -> impl FnMut(&LuaHookEvent) {
+        let profile = self.profile.clone();
+        move |event| profile.borrow_mut().record(&event.stack())
+    }
+
+    /// Stops profiling and returns the samples taken. The hook must have been removed.
+    pub fn finish(self) -> Profile {
+        match Rc::try_unwrap(self.profile) {
+            Ok(profile) => profile.into_inner(),
+            Err(_) => panic!("profiler hook still set"),
+        }
+    }
+}
+
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+
+/// Names the function running in a stack frame after the place it was defined, such as
+/// "add (utils.lua:12)", so that different functions with the same name are told apart.
+fn function_label(frame: &LuaStackFrame) -> String {
+    let label = match (frame.what, &frame.function_name, frame.line_defined) {
+        (LuaFunctionKind::Main, _, _) => format!("main chunk ({})", frame.source),
+        (LuaFunctionKind::C, Some(name), _) => format!("{} [C]", name),
+        (LuaFunctionKind::C, None, _) => String::from("? [C]"),
+        (_, Some(name), Some(line)) => format!("{} ({}:{})", name, frame.source, line),
+        (_, _, Some(line)) => format!("function ({}:{})", frame.source, line),
+        (_, _, None) => format!("function ({})", frame.source),
+    };
+
+    // Semicolons separate the functions of a folded stack
+    label.replace(';', ":")
+}
@@ -1,11 +1,87 @@
+use std::fs::File;
 use std::io::{Stdout, Write, stdin, stdout};
+use std::process;
+use std::time::Duration;
 
 use termion;
 use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 
-use lua::{LuaError, LuaIO, LuaState};
+use debugger::{Breakpoint, Debugger, PausedFrame, StepMode};
+use profiler::{Profile, Profiler};
+use lua::{LuaChunkMode, LuaEnvironment, LuaError, LuaErrorStatus, LuaExecutionLimits, LuaHookMask, LuaIO, LuaReadFormat, LuaRef, LuaResume, LuaState, LuaStateBuilder, LuaStream, LuaString, LuaThread, LuaValue};
+
+
+/// Longest time a chunk entered into the console may run before it is stopped.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Console command that displays how much memory the Lua state is using.
+const MEMORY_COMMAND: &str = ":memory";
+
+
+/// Console command that resumes the chunk that last yielded.
+const RESUME_COMMAND: &str = ":resume";
+
+
+/// Console command that discards all globals set by earlier chunks.
+const RESET_COMMAND: &str = ":reset";
+
+
+/// Console command that executes a Lua file, given as its argument.
+const LOAD_COMMAND: &str = ":load";
+
+
+/// Console command that adds a breakpoint at the chunk and line given as its argument, or lists
+/// the breakpoints if given none.
+const BREAK_COMMAND: &str = ":break";
+
+
+/// Console command that removes the breakpoint at the chunk and line given as its argument.
+const CLEAR_COMMAND: &str = ":clear";
+
+
+/// Console command that carries on running the paused chunk until the next breakpoint.
+const CONTINUE_COMMAND: &str = ":continue";
+
+
+/// Console command that runs the paused chunk to the next line, stepping into function calls.
+const STEP_COMMAND: &str = ":step";
+
+
+/// Console command that runs the paused chunk to the next line, stepping over function calls.
+const NEXT_COMMAND: &str = ":next";
+
+
+/// Console command that runs the paused chunk until the paused function returns.
+const FINISH_COMMAND: &str = ":finish";
+
+
+/// Console command that displays the local variables of the paused function.
+const LOCALS_COMMAND: &str = ":locals";
+
+
+/// Console command that displays the up values of the paused function.
+const UPVALUES_COMMAND: &str = ":upvalues";
+
+
+/// Console command that executes the chunk given as its argument while sampling its call stack,
+/// then displays the functions it spent the most time in.
+const PROFILE_COMMAND: &str = ":profile";
+
+
+/// Console command that writes the call stacks sampled by the last profile to the file given as
+/// its argument, in the folded format read by flame graph tools.
+const FOLDED_COMMAND: &str = ":folded";
+
+
+/// Number of instructions executed between samples of the call stack of a profiled chunk.
+const PROFILE_INTERVAL: u32 = 100;
+
+
+/// Number of functions displayed after profiling a chunk.
+const PROFILE_FUNCTIONS: usize = 10;
 
 
 /// External events to update the state of the REPL and perform effects.
@@ -14,7 +90,9 @@ enum Msg {
     AddChar(char),
     Backspace,
     ClearScreen,
-    ExecutionCompleted(Result<Vec<String>, LuaError>),
+    ExecutionCompleted(Result<Vec<LuaValue>, LuaError>),
+    ExecutionPaused(String),
+    ExecutionYielded(Vec<LuaValue>),
     GoBackInHistory,
     GoForwardInHistory,
     Quit,
@@ -26,12 +104,25 @@ enum Msg {
 /// Descriptions of side effects to be performed.
 #[derive(PartialEq, Debug)]
 enum Cmd {
+    AddBreakpoint(Breakpoint),
     ClearScreen,
+    DisplayBreakpoints,
     DisplayErrorMessage(String),
+    DisplayLocals,
+    DisplayMemoryUsage,
     DisplayOutput(String),
-    ExecuteChunk(String),
+    DisplayUpvalues,
+    ExecuteChunk(String, String),
+    Exit(i32),
+    LoadFile(String),
     None,
+    ProfileChunk(String, String),
     Quit,
+    RemoveBreakpoint(Breakpoint),
+    ResetSession,
+    ResumeChunk,
+    StepChunk(StepMode),
+    WriteFoldedStacks(String),
 }
 
 
@@ -39,7 +130,7 @@ enum Cmd {
 struct Repl {
     input_buffer: String,
     inputs: Vec<String>,
-    outputs: Vec<String>,
+    outputs: Vec<LuaValue>,
     input_history_index: Option<usize>,
 }
 
@@ -48,6 +139,24 @@ struct Repl {
 pub struct ConsoleRepl {
     lua_state: LuaState,
     repl: Repl,
+
+    // Environment that entered chunks run in, replaced to start over with a clean session.
+    session: LuaEnvironment,
+
+    // Chunk that yielded at the top level, kept until it is resumed or another chunk yields.
+    suspended_chunk: Option<LuaThread>,
+
+    // Exit code passed to os.exit by a chunk, which ends the session.
+    exit_code: Option<i32>,
+
+    // Breakpoints and stepping, and the frame of the suspended chunk if it paused rather than
+    // yielding. Input entered while paused is evaluated in the paused frame.
+    debugger: Debugger,
+    paused_frame: Option<PausedFrame>,
+
+    // Call stacks sampled the last time a chunk was profiled.
+    last_profile: Option<Profile>,
+
     stdout: RawTerminal<Stdout>,
 }
 
@@ -77,6 +186,8 @@ impl Repl {
             Msg::ClearScreen => self.on_clear_screen(),
             Msg::ExecutionCompleted(Ok(return_values)) => self.on_values_returned(return_values),
             Msg::ExecutionCompleted(Err(error)) => self.on_execution_error(error),
+            Msg::ExecutionPaused(location) => self.on_execution_paused(location),
+            Msg::ExecutionYielded(values) => self.on_values_yielded(values),
             Msg::GoBackInHistory => self.on_go_back_in_history(),
             Msg::GoForwardInHistory => self.on_go_forward_in_history(),
             Msg::ResetInput => self.on_reset_input(),
@@ -100,7 +211,14 @@ impl Repl {
     }
 
     fn on_execution_error(&mut self, error: LuaError) -> Cmd {
-        Cmd::DisplayErrorMessage(error.message)
+        match error.status {
+            LuaErrorStatus::Exit(code) => Cmd::Exit(code),
+            _ => Cmd::DisplayErrorMessage(error.to_string()),
+        }
+    }
+
+    fn on_execution_paused(&mut self, location: String) -> Cmd {
+        Cmd::DisplayOutput(format!("paused at {}", location))
     }
 
     fn on_go_back_in_history(&mut self) -> Cmd {
@@ -145,35 +263,126 @@ impl Repl {
         self.inputs.push(self.input_buffer.clone());
         self.input_buffer.clear();
         self.input_history_index = None;
-        Cmd::ExecuteChunk(chunk)
+
+        if chunk.trim() == MEMORY_COMMAND {
+            Cmd::DisplayMemoryUsage
+        } else if chunk.trim() == RESUME_COMMAND {
+            Cmd::ResumeChunk
+        } else if chunk.trim() == RESET_COMMAND {
+            Cmd::ResetSession
+        } else if chunk.trim().starts_with(&format!("{} ", LOAD_COMMAND)) {
+            let path = chunk.trim()[LOAD_COMMAND.len()..].trim();
+            Cmd::LoadFile(String::from(path))
+        } else if chunk.trim() == BREAK_COMMAND {
+            Cmd::DisplayBreakpoints
+        } else if chunk.trim().starts_with(&format!("{} ", BREAK_COMMAND)) {
+            parse_breakpoint_command(&chunk, BREAK_COMMAND, Cmd::AddBreakpoint)
+        } else if chunk.trim() == CLEAR_COMMAND || chunk.trim().starts_with(&format!("{} ", CLEAR_COMMAND)) {
+            parse_breakpoint_command(&chunk, CLEAR_COMMAND, Cmd::RemoveBreakpoint)
+        } else if chunk.trim() == CONTINUE_COMMAND {
+            Cmd::StepChunk(StepMode::Continue)
+        } else if chunk.trim() == STEP_COMMAND {
+            Cmd::StepChunk(StepMode::Into)
+        } else if chunk.trim() == NEXT_COMMAND {
+            Cmd::StepChunk(StepMode::Over)
+        } else if chunk.trim() == FINISH_COMMAND {
+            Cmd::StepChunk(StepMode::Out)
+        } else if chunk.trim() == LOCALS_COMMAND {
+            Cmd::DisplayLocals
+        } else if chunk.trim() == UPVALUES_COMMAND {
+            Cmd::DisplayUpvalues
+        } else if chunk.trim() == PROFILE_COMMAND || chunk.trim().starts_with(&format!("{} ", PROFILE_COMMAND)) {
+            let profiled_chunk = chunk.trim()[PROFILE_COMMAND.len()..].trim();
+            if profiled_chunk.is_empty() {
+                Cmd::DisplayErrorMessage(format!("usage: {} <chunk>", PROFILE_COMMAND))
+            } else {
+                let name = format!("stdin:{}", self.inputs.len());
+                Cmd::ProfileChunk(name, String::from(profiled_chunk))
+            }
+        } else if chunk.trim() == FOLDED_COMMAND || chunk.trim().starts_with(&format!("{} ", FOLDED_COMMAND)) {
+            let path = chunk.trim()[FOLDED_COMMAND.len()..].trim();
+            if path.is_empty() {
+                Cmd::DisplayErrorMessage(format!("usage: {} <path>", FOLDED_COMMAND))
+            } else {
+                Cmd::WriteFoldedStacks(String::from(path))
+            }
+        } else {
+            // Name the chunk after its input number, so errors point back to the input.
+            let name = format!("stdin:{}", self.inputs.len());
+            Cmd::ExecuteChunk(name, chunk)
+        }
     }
 
-    fn on_values_returned(&mut self, mut values: Vec<String>) -> Cmd {
+    fn on_values_returned(&mut self, mut values: Vec<LuaValue>) -> Cmd {
         let mut output_display = String::new();
         for value in &values {
-            output_display.push_str(value);
+            output_display.push_str(&value.to_string());
             output_display.push_str("   ");
         }
         
         self.outputs.append(&mut values);
         Cmd::DisplayOutput(output_display)
     }
+
+    fn on_values_yielded(&mut self, values: Vec<LuaValue>) -> Cmd {
+        let mut output_display = String::from("(yielded)   ");
+        if let Cmd::DisplayOutput(values_display) = self.on_values_returned(values) {
+            output_display.push_str(&values_display);
+        }
+
+        Cmd::DisplayOutput(output_display)
+    }
 }
 
 
 impl ConsoleRepl {
     pub fn new() -> ConsoleRepl {
+        ConsoleRepl::with_builder(LuaStateBuilder::new())
+    }
+
+    /// Creates a REPL whose Lua state has no access to files, other processes or the debug
+    /// library, for handing to users who should not have access to the host.
+    pub fn sandboxed() -> ConsoleRepl {
+        ConsoleRepl::with_builder(LuaStateBuilder::sandboxed())
+    }
+
+    fn with_builder(builder: LuaStateBuilder) -> ConsoleRepl {
+        let lua_state = builder
+            .execution_limits(LuaExecutionLimits{
+                max_instructions: None,
+                timeout: Some(CHUNK_TIMEOUT),
+            })
+            .build()
+            .unwrap();
+
+        let session = lua_state.create_environment();
         ConsoleRepl{
-            lua_state: LuaState::new(),
+            lua_state,
             repl: Repl::new(),
+            session,
+            suspended_chunk: None,
+            exit_code: None,
+            debugger: Debugger::new(),
+            paused_frame: None,
+            last_profile: None,
             stdout: stdout().into_raw_mode().unwrap(),
         }
     }
 
+    /// Sets which kinds of chunks the load command accepts, such as to allow precompiled scripts.
+    pub fn set_chunk_mode(&mut self, mode: LuaChunkMode) {
+        self.lua_state.set_chunk_mode(mode);
+    }
+
+    /// Adds a Lua module that chunks entered into the console can require, such as a helper
+    /// library embedded into the binary with include_str!.
+    pub fn add_module_source(&mut self, name: &str, source: &str) -> Result<(), LuaError> {
+        self.lua_state.add_module_source(name, source)
+    }
+
     /// Runs the REPL reading and writing from standard in and standard out.
     pub fn run_repl(&mut self) {
-        write!(self.stdout, "\r/> ").unwrap();
-        self.stdout.flush().unwrap();
+        self.render_input_buffer();
 
         let key_messages = stdin().keys()
             .into_iter()
@@ -182,17 +391,50 @@ impl ConsoleRepl {
         for msg in key_messages {
             let cmd = self.repl.update(msg);
             match cmd {
+                Cmd::AddBreakpoint(breakpoint) => self.on_add_breakpoint(breakpoint),
                 Cmd::ClearScreen => self.on_clear_screen(),
+                Cmd::DisplayBreakpoints => self.on_display_breakpoints(),
                 Cmd::DisplayErrorMessage(error) => self.on_display_error_message(error),
+                Cmd::DisplayLocals => self.on_display_locals(),
+                Cmd::DisplayMemoryUsage => self.on_display_memory_usage(),
                 Cmd::DisplayOutput(output) => self.on_display_output(output),
-                Cmd::ExecuteChunk(chunk) => self.on_execute_chunk(chunk),
+                Cmd::DisplayUpvalues => self.on_display_upvalues(),
+                Cmd::ExecuteChunk(name, chunk) => self.on_execute_chunk(name, chunk),
+                Cmd::Exit(code) => self.exit_code = Some(code),
+                Cmd::LoadFile(path) => self.on_load_file(path),
                 Cmd::None => self.render_input_buffer(),
+                Cmd::ProfileChunk(name, chunk) => self.on_profile_chunk(name, chunk),
                 Cmd::Quit => break,
+                Cmd::RemoveBreakpoint(breakpoint) => self.on_remove_breakpoint(breakpoint),
+                Cmd::ResetSession => self.on_reset_session(),
+                Cmd::ResumeChunk => self.on_resume_chunk(),
+                Cmd::StepChunk(step_mode) => self.on_step_chunk(step_mode),
+                Cmd::WriteFoldedStacks(path) => self.on_write_folded_stacks(path),
+            }
+
+            if self.exit_code.is_some() {
+                break;
             }
         }
 
         write!(self.stdout, "\r\nGoodbye!\r\n").unwrap();
         self.stdout.flush().unwrap();
+
+        if let Some(code) = self.exit_code {
+            // Exiting skips destructors, so the terminal has to be restored beforehand
+            self.stdout.suspend_raw_mode().unwrap();
+            process::exit(code);
+        }
+    }
+
+    fn on_add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        let output = format!("breakpoint at {}", breakpoint);
+        if self.debugger.add_breakpoint(breakpoint) {
+            self.update_hook();
+            self.on_display_output(output);
+        } else {
+            self.on_display_error_message(format!("already a {}", output));
+        }
     }
 
     fn on_clear_screen(&mut self) {
@@ -202,6 +444,18 @@ impl ConsoleRepl {
         self.render_input_buffer();
     }
 
+    fn on_display_breakpoints(&mut self) {
+        let breakpoints: Vec<String> = self.debugger.breakpoints().iter()
+            .map(|breakpoint| breakpoint.to_string())
+            .collect();
+
+        if breakpoints.is_empty() {
+            self.on_display_output(String::from("no breakpoints"));
+        } else {
+            self.on_display_output(breakpoints.join("\r\n"));
+        }
+    }
+
     fn on_display_error_message(&mut self, error: String) {
         write!(self.stdout, "\r\n").unwrap();
         if error.len() > 0 {
@@ -211,6 +465,25 @@ impl ConsoleRepl {
         self.render_input_buffer();
     }
 
+    fn on_display_locals(&mut self) {
+        let output = self.paused_frame.as_ref().map(|paused| format_variables(&paused.locals, "no locals"));
+        self.display_paused_output(output);
+    }
+
+    fn on_display_memory_usage(&mut self) {
+        let usage = self.lua_state.memory_usage();
+        let limit = match usage.limit {
+            Some(limit) => format_bytes(limit),
+            None => String::from("none"),
+        };
+
+        let output = format!("used: {}   peak: {}   limit: {}",
+            format_bytes(usage.used),
+            format_bytes(usage.peak),
+            limit);
+        self.on_display_output(output);
+    }
+
     fn on_display_output(&mut self, output: String) {
         write!(self.stdout, "\r\n").unwrap();
         if output.len() > 0 {
@@ -220,23 +493,198 @@ impl ConsoleRepl {
         self.render_input_buffer();
     }
 
-    fn on_execute_chunk(&mut self, chunk: String) {
+    fn on_display_upvalues(&mut self) {
+        let output = self.paused_frame.as_ref().map(|paused| format_variables(&paused.upvalues, "no upvalues"));
+        self.display_paused_output(output);
+    }
+
+    fn on_execute_chunk(&mut self, name: String, chunk: String) {
+        if self.paused_frame.is_some() {
+            self.evaluate_in_paused_frame(name, chunk);
+            return;
+        }
+
+        // Chunks run as threads so that they can yield at the top level, and pause in the
+        // debugger.
+        match self.session.create_thread(&name, &chunk) {
+            Ok(thread) => self.run_chunk(thread),
+            Err(error) => self.on_chunk_result(Msg::ExecutionCompleted(Err(error))),
+        }
+    }
+
+    fn on_load_file(&mut self, path: String) {
         let result = {
             let mut io_receiver = ConsoleIOReceiver{ stdout: &mut self.stdout };
-            self.lua_state.execute_chunk(&chunk, &mut io_receiver)
+            self.session.execute_file(&path, &mut io_receiver)
         };
+        self.on_chunk_result(Msg::ExecutionCompleted(result));
+    }
+
+    fn on_profile_chunk(&mut self, name: String, chunk: String) {
+        // The profiler's hook takes the place of the debugger's until the chunk is done, so
+        // breakpoints are not hit while profiling.
+        let profiler = Profiler::new();
+        let interval = LuaHookMask{ count: Some(PROFILE_INTERVAL), ..LuaHookMask::default() };
+        self.lua_state.set_hook(interval, profiler.hook());
+
+        let result = {
+            let mut io_receiver = ConsoleIOReceiver{ stdout: &mut self.stdout };
+            self.session.execute_named_chunk(&name, &chunk, &mut io_receiver)
+        };
+        self.update_hook();
+        let profile = profiler.finish();
+
+        self.on_chunk_result(Msg::ExecutionCompleted(result));
+        if self.exit_code.is_none() {
+            self.on_display_output(format_profile(&profile));
+            self.last_profile = Some(profile);
+        }
+    }
+
+    fn on_remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if self.debugger.remove_breakpoint(&breakpoint) {
+            self.update_hook();
+            self.on_display_output(format!("removed breakpoint at {}", breakpoint));
+        } else {
+            self.on_display_error_message(format!("no breakpoint at {}", breakpoint));
+        }
+    }
 
-       let cmd = self.repl.update(Msg::ExecutionCompleted(result));
+    fn on_reset_session(&mut self) {
+        self.session = self.lua_state.create_environment();
+        self.suspended_chunk = None;
+        self.paused_frame = None;
+        self.debugger.stop_stepping();
+        self.update_hook();
+        self.on_display_output(String::from("session reset"));
+    }
+
+    fn on_resume_chunk(&mut self) {
+        match self.suspended_chunk.take() {
+            Some(thread) => self.run_chunk(thread),
+            None => self.on_display_error_message(String::from("no suspended chunk to resume")),
+        }
+    }
+
+    fn on_step_chunk(&mut self, step_mode: StepMode) {
+        if self.paused_frame.is_none() {
+            self.on_display_error_message(String::from("no paused chunk to step through"));
+            return;
+        }
+
+        if let Some(thread) = self.suspended_chunk.take() {
+            self.debugger.step(step_mode);
+            self.update_hook();
+            self.run_chunk(thread);
+        }
+    }
+
+    fn on_write_folded_stacks(&mut self, path: String) {
+        let result = match self.last_profile {
+            Some(ref profile) => File::create(&path).and_then(|mut file| profile.write_folded(&mut file)),
+            None => {
+                self.on_display_error_message(String::from("no profile to write"));
+                return;
+            },
+        };
+
+        match result {
+            Ok(()) => self.on_display_output(format!("wrote folded stacks to {}", path)),
+            Err(error) => self.on_display_error_message(format!("cannot write {}: {}", path, error)),
+        }
+    }
+
+    fn run_chunk(&mut self, thread: LuaThread) {
+        if self.paused_frame.take().is_some() {
+            self.debugger.resume();
+        }
+        let resume = {
+            let mut io_receiver = ConsoleIOReceiver{ stdout: &mut self.stdout };
+            thread.resume((), &mut io_receiver)
+        };
+
+        let paused_frame = self.debugger.take_paused_frame();
+        if paused_frame.is_none() {
+            // The chunk is done or yielded, so any step in progress ends with it
+            self.debugger.stop_stepping();
+            self.update_hook();
+        }
+
+        let msg = match resume {
+            LuaResume::Yielded(_) if paused_frame.is_some() => {
+                let location = paused_frame.as_ref().map(|paused| paused.frame.to_string()).unwrap();
+                self.suspended_chunk = Some(thread);
+                self.paused_frame = paused_frame;
+                Msg::ExecutionPaused(location)
+            },
+            LuaResume::Yielded(values) => {
+                self.suspended_chunk = Some(thread);
+                Msg::ExecutionYielded(values)
+            },
+            LuaResume::Finished(values) => Msg::ExecutionCompleted(Ok(values)),
+            LuaResume::Error(error) => Msg::ExecutionCompleted(Err(error)),
+        };
+        self.on_chunk_result(msg);
+    }
+
+    fn on_chunk_result(&mut self, msg: Msg) {
+       let cmd = self.repl.update(msg);
        if let Cmd::DisplayErrorMessage(error) = cmd {
            self.on_display_error_message(error);
        } else if let Cmd::DisplayOutput(output) = cmd {
            self.on_display_output(output);
+       } else if let Cmd::Exit(code) = cmd {
+           self.exit_code = Some(code);
        }
     }
 
+    /// Evaluates the chunk with the local variables and up values of the paused function in
+    /// scope. Assigning to them only changes the copies the chunk sees.
+    fn evaluate_in_paused_frame(&mut self, name: String, chunk: String) {
+        let result = {
+            let paused = self.paused_frame.as_ref().unwrap();
+            let scope = self.session.create_environment();
+            let variables = scope.globals();
+
+            // Locals are set last, since they shadow up values of the same name
+            let mut set_result = Ok(());
+            for (variable, value) in paused.upvalues.iter().chain(paused.locals.iter()) {
+                if variable != "_ENV" {
+                    set_result = set_result.and(variables.raw_set(variable.as_str(), value));
+                }
+            }
+
+            let mut io_receiver = ConsoleIOReceiver{ stdout: &mut self.stdout };
+            set_result.and_then(|_| scope.execute_named_chunk(&name, &chunk, &mut io_receiver))
+        };
+        self.on_chunk_result(Msg::ExecutionCompleted(result));
+    }
+
+    /// Displays output about the paused function, or an error if no chunk is paused.
+    fn display_paused_output(&mut self, output: Option<String>) {
+        match output {
+            Some(output) => self.on_display_output(output),
+            None => self.on_display_error_message(String::from("no paused chunk")),
+        }
+    }
+
+    /// Sets the debugger's hook on the Lua state while it could pause a chunk, and removes it
+    /// otherwise so that chunks run at full speed.
+    fn update_hook(&mut self) {
+        if self.debugger.is_active() {
+            let lines = LuaHookMask{ lines: true, ..LuaHookMask::default() };
+            self.lua_state.set_hook(lines, self.debugger.hook());
+        } else {
+            self.lua_state.remove_hook();
+        }
+    }
+
     fn render_input_buffer(&mut self) {
-        write!(self.stdout, "{}\r/> {}",
+        // The prompt shows where the paused chunk is paused
+        let location = self.paused_frame.as_ref().map_or(String::new(), PausedFrame::location);
+        write!(self.stdout, "{}\r{}/> {}",
             termion::clear::CurrentLine,
+            location,
             self.repl.input_buffer).unwrap();
         self.stdout.flush().unwrap();
     }
@@ -244,12 +692,102 @@ impl ConsoleRepl {
 
 
 impl<'a> LuaIO for ConsoleIOReceiver<'a> {
-    fn on_print (&mut self, values: Vec<String>) {
+    fn on_print (&mut self, values: Vec<LuaString>) {
         for value in &values {
             write!(self.stdout, "\r\n{}", value).unwrap();
         }
         self.stdout.flush().unwrap();
     }
+
+    fn on_read(&mut self, format: LuaReadFormat) -> Option<Vec<u8>> {
+        // Read in cooked mode, so that the user sees what they type and can edit the line
+        write!(self.stdout, "\r\n").unwrap();
+        self.stdout.flush().unwrap();
+        self.stdout.suspend_raw_mode().unwrap();
+        let input = format.read_from(&mut stdin().lock());
+        self.stdout.activate_raw_mode().unwrap();
+        input
+    }
+
+    fn on_write(&mut self, _stream: LuaStream, bytes: &[u8]) {
+        // The terminal is in raw mode, so line feeds need a carriage return to start a new line
+        for (i, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+            if i > 0 {
+                self.stdout.write_all(b"\r\n").unwrap();
+            }
+            self.stdout.write_all(line).unwrap();
+        }
+        self.stdout.flush().unwrap();
+    }
+}
+
+
+/// Formats variables for display one per line, or the given message if there are none.
+fn format_variables(variables: &[(String, LuaRef)], none_message: &str) -> String {
+    if variables.is_empty() {
+        return String::from(none_message);
+    }
+
+    let lines: Vec<String> = variables.iter()
+        .map(|(name, value)| format!("{} = {}", name, value.value()))
+        .collect();
+    lines.join("\r\n")
+}
+
+
+/// Formats the functions a profiled chunk spent the most time in for display, with the share of
+/// samples taken in each function itself and anywhere below it.
+fn format_profile(profile: &Profile) -> String {
+    let num_samples = profile.num_samples();
+    if num_samples == 0 {
+        return format!("no samples taken, since the chunk ran fewer than {} instructions", PROFILE_INTERVAL);
+    }
+
+    let mut lines = vec![
+        format!("{} samples, one every {} instructions", num_samples, PROFILE_INTERVAL),
+        format!("{:>7} {:>7}  function", "self", "total"),
+    ];
+    for function in profile.hottest_functions(PROFILE_FUNCTIONS) {
+        lines.push(format!("{:>6.1}% {:>6.1}%  {}",
+            100.0 * function.self_samples as f64 / num_samples as f64,
+            100.0 * function.total_samples as f64 / num_samples as f64,
+            function.function));
+    }
+    lines.join("\r\n")
+}
+
+
+/// Parses the breakpoint given as the argument of a console command, and wraps it in the command
+/// to perform. Returns a command to display the usage of the console command if the argument is
+/// not a breakpoint.
+fn parse_breakpoint_command<F: FnOnce(Breakpoint) -> Cmd>(input: &str, command: &str, to_cmd: F) -> Cmd {
+    let argument = input.trim()[command.len()..].trim();
+    match Breakpoint::parse(argument) {
+        Some(breakpoint) => to_cmd(breakpoint),
+        None => Cmd::DisplayErrorMessage(format!("usage: {} <chunk>:<line>", command)),
+    }
+}
+
+
+/// Formats a number of bytes for display, using the largest unit that keeps the value above one.
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next_unit in UNITS.iter() {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, unit)
+    }
 }
 
 
@@ -0,0 +1,128 @@
+extern crate lua_console;
+
+use lua_console::debugger::{Breakpoint, Debugger, StepMode};
+use lua_console::lua;
+use lua_console::lua::{LuaHookMask, LuaResume, LuaString, LuaThread, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+const NESTED_CALLS: &str = "local function inner(x)
+    local y = x * 2
+    return y
+end
+local a = inner(1)
+local b = inner(a)
+return a + b";
+
+
+/// Creates a thread running the given chunk, with the debugger's hook set on its state.
+fn debugged_thread(lua_state: &mut lua::LuaState, debugger: &Debugger, chunk: &str) -> LuaThread {
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, debugger.hook());
+    lua_state.create_thread("chunk", chunk).unwrap()
+}
+
+
+/// Resumes the thread the way the console does, and returns where it paused, or None once it
+/// finished.
+fn resume(debugger: &mut Debugger, thread: &LuaThread) -> Option<String> {
+    debugger.resume();
+    let result = thread.resume((), &mut IOReceiver{});
+    let paused_frame = debugger.take_paused_frame();
+    match result {
+        LuaResume::Yielded(_) => Some(paused_frame.expect("thread yielded without pausing").location()),
+        LuaResume::Finished(values) => {
+            assert!(paused_frame.is_none());
+            assert_eq!(vec![LuaValue::Integer(6)], values);
+            None
+        },
+        LuaResume::Error(error) => panic!("thread failed: {:?}", error),
+    }
+}
+
+
+#[test]
+fn step_over_and_out_of_nested_call() {
+    let mut lua_state = lua::LuaState::new();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(Breakpoint::parse("chunk:5").unwrap());
+    let thread = debugged_thread(&mut lua_state, &debugger, NESTED_CALLS);
+
+    assert_eq!(Some(String::from("chunk:5")), resume(&mut debugger, &thread));
+
+    // Stepping over the call runs through every line of the called function.
+    debugger.step(StepMode::Over);
+    assert_eq!(Some(String::from("chunk:6")), resume(&mut debugger, &thread));
+
+    debugger.step(StepMode::Into);
+    assert_eq!(Some(String::from("chunk:2")), resume(&mut debugger, &thread));
+
+    debugger.step(StepMode::Over);
+    assert_eq!(Some(String::from("chunk:3")), resume(&mut debugger, &thread));
+
+    // Stepping out pauses at the next line of the calling function.
+    debugger.step(StepMode::Out);
+    assert_eq!(Some(String::from("chunk:7")), resume(&mut debugger, &thread));
+
+    debugger.step(StepMode::Continue);
+    assert_eq!(None, resume(&mut debugger, &thread));
+}
+
+
+#[test]
+fn step_over_returns_to_caller() {
+    let mut lua_state = lua::LuaState::new();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(Breakpoint::parse("chunk:3").unwrap());
+    let thread = debugged_thread(&mut lua_state, &debugger, NESTED_CALLS);
+
+    assert_eq!(Some(String::from("chunk:3")), resume(&mut debugger, &thread));
+
+    // Stepping over the last line of a function pauses in the function it returns to.
+    debugger.remove_breakpoint(&Breakpoint::parse("chunk:3").unwrap());
+    debugger.step(StepMode::Over);
+    assert_eq!(Some(String::from("chunk:6")), resume(&mut debugger, &thread));
+
+    debugger.stop_stepping();
+    assert!(!debugger.is_active());
+    assert_eq!(None, resume(&mut debugger, &thread));
+}
+
+
+#[test]
+fn breakpoints_pause_every_time_they_are_hit() {
+    let mut lua_state = lua::LuaState::new();
+    let mut debugger = Debugger::new();
+    assert!(!debugger.is_active());
+    assert!(debugger.add_breakpoint(Breakpoint::parse("chunk:2").unwrap()));
+    assert!(!debugger.add_breakpoint(Breakpoint::parse(" chunk : 2 ").unwrap()));
+    assert!(debugger.add_breakpoint(Breakpoint::parse("other:7").unwrap()));
+    assert!(debugger.is_active());
+    let thread = debugged_thread(&mut lua_state, &debugger, NESTED_CALLS);
+
+    // Continuing from a breakpoint does not pause on it again until it is next reached.
+    assert_eq!(Some(String::from("chunk:2")), resume(&mut debugger, &thread));
+    assert_eq!(Some(String::from("chunk:2")), resume(&mut debugger, &thread));
+    assert_eq!(None, resume(&mut debugger, &thread));
+
+    assert!(debugger.remove_breakpoint(&Breakpoint::parse("chunk:2").unwrap()));
+    assert!(!debugger.remove_breakpoint(&Breakpoint::parse("chunk:2").unwrap()));
+    assert_eq!(vec![Breakpoint::parse("other:7").unwrap()], debugger.breakpoints());
+}
+
+
+#[test]
+fn parse_breakpoints() {
+    assert_eq!(Some(Breakpoint{ chunk: String::from("stdin:3"), line: 2 }), Breakpoint::parse("stdin:3:2"));
+    assert_eq!(Some(String::from("init.lua:12")), Breakpoint::parse("init.lua:12").map(|b| b.to_string()));
+    for invalid in &["", "12", ":12", "init.lua:", "init.lua:0", "init.lua:x"] {
+        assert_eq!(None, Breakpoint::parse(invalid), "{:?} parsed", invalid);
+    }
+}
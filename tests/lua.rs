@@ -1,10 +1,11 @@
 extern crate lua_console;
 
 use lua_console::lua;
+use lua_console::lua::{LuaString, LuaValue};
 
 
 struct IOReceiver {
-    values: Vec<String>
+    values: Vec<LuaString>
 }
 
 
@@ -18,7 +19,7 @@ impl IOReceiver {
 
 
 impl lua::LuaIO for IOReceiver {
-    fn on_print(&mut self, mut values: Vec<String>) {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
         self.values.append(&mut values);
     }
 }
@@ -26,7 +27,7 @@ impl lua::LuaIO for IOReceiver {
 
 struct TestChunk {
     chunk: &'static str,
-    expected_return_values: Vec<&'static str>
+    expected_return_values: Vec<LuaValue>
 }
 
 
@@ -58,7 +59,7 @@ fn explicit_return() {
         chunks: vec![
             TestChunk{
                 chunk: "return 17, false",
-                expected_return_values: vec!["17", "false"],
+                expected_return_values: vec![LuaValue::Integer(17), LuaValue::Boolean(false)],
             }
         ],
         expected_print_values: vec![],
@@ -78,7 +79,7 @@ fn leftover_stack_values_returned() {
             },
             TestChunk{
                 chunk: "give_two()",
-                expected_return_values: vec!["5", "true"],
+                expected_return_values: vec![LuaValue::Integer(5), LuaValue::Boolean(true)],
             },
             TestChunk{
                 chunk: "x = 5",
@@ -86,11 +87,16 @@ fn leftover_stack_values_returned() {
             },
             TestChunk{
                 chunk: "x",
-                expected_return_values: vec!["5"],
+                expected_return_values: vec![LuaValue::Integer(5)],
             },
             TestChunk{
                 chunk: "5, nil, false, 'Hello'",
-                expected_return_values: vec!["5", "nil", "false", "Hello"],
+                expected_return_values: vec![
+                    LuaValue::Integer(5),
+                    LuaValue::Nil,
+                    LuaValue::Boolean(false),
+                    LuaValue::String(LuaString::from("Hello")),
+                ],
             }
         ],
         expected_print_values: vec![],
@@ -100,6 +106,46 @@ fn leftover_stack_values_returned() {
 }
 
 
+#[test]
+fn numbers_and_strings_are_distinct() {
+    let test_case = TestCase {
+        chunks: vec![
+            TestChunk{
+                chunk: "5, '5', 2.5, 10 / 2, 'nil'",
+                expected_return_values: vec![
+                    LuaValue::Integer(5),
+                    LuaValue::String(LuaString::from("5")),
+                    LuaValue::Float(2.5),
+                    LuaValue::Float(5.0),
+                    LuaValue::String(LuaString::from("nil")),
+                ],
+            },
+        ],
+        expected_print_values: vec![],
+    };
+
+    test_case.run();
+}
+
+
+#[test]
+fn objects_returned_with_string_form() {
+    let mut io_receiver = IOReceiver::new();
+    let lua_state = lua::LuaState::new();
+
+    let values = lua_state.execute_chunk("{}, print, 10 / 4, 2^53", &mut io_receiver).unwrap();
+    assert_eq!(4, values.len());
+
+    match values[0] {
+        LuaValue::Table(ref table) => assert!(table.display.as_ref().unwrap().starts_with("table: ")),
+        ref other => panic!("Expected a table, got {:?}", other),
+    }
+    assert_eq!("function", values[1].type_name());
+    assert_eq!("2.5", values[2].to_string());
+    assert_eq!("9.007199254741e+15", values[3].to_string());
+}
+
+
 #[test]
 fn print_local_vars() {
     let test_case = TestCase {
@@ -185,3 +231,48 @@ fn use_standard_module() {
     
     test_case.run();
 }
+
+
+#[test]
+fn rendering_does_not_use_global_tostring() {
+    let test_case = TestCase{
+        chunks: vec![
+            TestChunk{
+                chunk: "tostring = nil",
+                expected_return_values: vec![],
+            },
+            TestChunk{
+                chunk: "print(1, 'two', 3.5, nil, true)",
+                expected_return_values: vec![],
+            },
+        ],
+        expected_print_values: vec!["1", "two", "3.5", "nil", "true"],
+    };
+
+    test_case.run();
+}
+
+
+#[test]
+fn rendering_errors_are_reported_per_value() {
+    let lua_state = lua::LuaState::new();
+    let mut io_receiver = IOReceiver::new();
+    let chunk = "bad = setmetatable({}, {__tostring = function() error('no string form', 0) end}) \
+                 good = setmetatable({}, {__tostring = function() return 'good' end})";
+    lua_state.execute_chunk(chunk, &mut io_receiver).unwrap();
+
+    lua_state.execute_chunk("print(1, bad, good)", &mut io_receiver).unwrap();
+    assert_eq!(vec!["1", "(error rendering table: no string form)", "good"], io_receiver.values);
+
+    let result = lua_state.execute_chunk("bad, good", &mut io_receiver).unwrap();
+    match result[0] {
+        LuaValue::Table(ref table) => assert_eq!(Err(String::from("no string form")), table.display),
+        ref other => panic!("Unexpected value {:?}", other),
+    }
+    assert_eq!("(error rendering table: no string form)", result[0].to_string());
+    assert_eq!("good", result[1].to_string());
+
+    // The state is still usable afterwards.
+    let result = lua_state.execute_chunk("1 + 1", &mut io_receiver);
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), result);
+}
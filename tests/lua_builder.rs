@@ -0,0 +1,133 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaChunkMode, LuaErrorStatus, LuaLibrary, LuaStateBuilder, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+fn type_of(lua_state: &lua::LuaState, expression: &str) -> String {
+    let chunk = format!("type({})", expression);
+    match lua_state.execute_chunk(&chunk, &mut IOReceiver{}).unwrap().pop() {
+        Some(LuaValue::String(type_name)) => type_name.to_str().unwrap().to_string(),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+
+#[test]
+fn default_builder_opens_every_library() {
+    let lua_state = LuaStateBuilder::new().build().unwrap();
+    for library in &["coroutine", "debug", "io", "math", "os", "package", "string", "table", "utf8"] {
+        assert_eq!("table", type_of(&lua_state, library));
+    }
+    assert_eq!("function", type_of(&lua_state, "dofile"));
+}
+
+
+#[test]
+fn open_chosen_libraries_only() {
+    let lua_state = LuaStateBuilder::new()
+        .libraries(&[LuaLibrary::Base, LuaLibrary::Math])
+        .library(LuaLibrary::String)
+        .build()
+        .unwrap();
+
+    assert_eq!("table", type_of(&lua_state, "math"));
+    assert_eq!("table", type_of(&lua_state, "string"));
+    assert_eq!("nil", type_of(&lua_state, "io"));
+    assert_eq!("nil", type_of(&lua_state, "os"));
+    assert_eq!("nil", type_of(&lua_state, "require"));
+
+    // String methods work through the string metatable set by the string library.
+    let result = lua_state.execute_chunk("('abc'):upper()", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("ABC"))]), result);
+}
+
+
+#[test]
+fn remove_dangerous_functions() {
+    let lua_state = LuaStateBuilder::new()
+        .remove_function("os.execute")
+        .remove_function("io.popen")
+        .remove_function("loadfile")
+        .remove_function("missing.library.function")
+        .build()
+        .unwrap();
+
+    assert_eq!("nil", type_of(&lua_state, "os.execute"));
+    assert_eq!("nil", type_of(&lua_state, "io.popen"));
+    assert_eq!("nil", type_of(&lua_state, "loadfile"));
+    assert_eq!("function", type_of(&lua_state, "os.time"));
+    assert_eq!("function", type_of(&lua_state, "dofile"));
+}
+
+
+#[test]
+fn sandboxed_profile() {
+    let lua_state = LuaStateBuilder::sandboxed().build().unwrap();
+    for expression in &["io", "debug", "package", "require", "dofile", "loadfile", "os.execute", "os.exit", "os.getenv"] {
+        assert_eq!("nil", type_of(&lua_state, expression), "{} is available", expression);
+    }
+
+    let result = lua_state.execute_chunk("string.format('%d', math.floor(os.clock() * 0))", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("0"))]), result);
+}
+
+
+#[test]
+fn sandboxed_load_bytecode() {
+    let lua_state = LuaStateBuilder::sandboxed()
+        .chunk_mode(LuaChunkMode::TextOrBinary)
+        .build()
+        .unwrap();
+    let result = lua_state.execute_chunk("return load(string.dump(function() return 42 end))", &mut IOReceiver{});
+    assert_eq!(LuaErrorStatus::RuntimeError, result.unwrap_err().status);
+
+    // Bytecode that reaches the sandbox some other way cannot be loaded either.
+    let bytecode = lua_state.compile("return 42", "bytecode", false).unwrap();
+    lua_state.set_global("bytecode", LuaString::from(bytecode)).unwrap();
+    let result = lua_state.execute_chunk("return load(bytecode, 'bytecode', 'b')", &mut IOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::Nil,
+        LuaValue::String(LuaString::from("attempt to load a binary chunk (mode is 't')")),
+    ]), result);
+
+    let result = lua_state.execute_chunk("return load('return 42')()", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(42)]), result);
+}
+
+
+#[test]
+fn attach_rust_modules() {
+    let lua_state = LuaStateBuilder::new()
+        .module("greeting", |lua_state| {
+            let module = lua_state.create_table();
+            module.set("text", "hello")?;
+            Ok(module)
+        })
+        .build()
+        .unwrap();
+
+    let chunk = "return greeting.text, require('greeting') == greeting";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("hello")), LuaValue::Boolean(true)]), result);
+}
+
+
+#[test]
+fn module_errors_fail_the_build() {
+    let error = LuaStateBuilder::new()
+        .module("bad\0name", |lua_state| Ok(lua_state.create_table()))
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(LuaErrorStatus::ConversionError, error.status);
+}
@@ -0,0 +1,110 @@
+extern crate lua_console;
+
+use std::env;
+use std::fs;
+
+use lua_console::lua;
+use lua_console::lua::{LuaChunkMode, LuaErrorStatus, LuaStateBuilder, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn execute_compiled_chunk() {
+    let lua_state = lua::LuaState::new();
+    let bytecode = lua_state.compile("local x = ... or 20 return x * 2 + 2", "answer", false).unwrap();
+    assert!(bytecode.starts_with(b"\x1bLua"));
+
+    let other_state = LuaStateBuilder::new().chunk_mode(LuaChunkMode::TextOrBinary).build().unwrap();
+    let result = other_state.execute_bytes("answer", &bytecode, &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(42)]), result);
+
+    let result = other_state.execute_bytes("source", b"return 'text'", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("text"))]), result);
+}
+
+
+#[test]
+fn binary_chunks_rejected_by_default() {
+    let mut lua_state = lua::LuaState::new();
+    assert_eq!(LuaChunkMode::Text, lua_state.chunk_mode());
+
+    let bytecode = lua_state.compile("return 1", "one", false).unwrap();
+    let error = lua_state.execute_bytes("one", &bytecode, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::SyntaxError, error.status);
+    assert!(error.message.contains("attempt to load a binary chunk"), "{}", error.message);
+
+    lua_state.set_chunk_mode(LuaChunkMode::Binary);
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), lua_state.execute_bytes("one", &bytecode, &mut IOReceiver{}));
+    let error = lua_state.execute_bytes("text", b"return 1", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("attempt to load a text chunk"), "{}", error.message);
+}
+
+
+#[test]
+fn strip_debug_information() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_chunk_mode(LuaChunkMode::Binary);
+    let source = "local function fail() error('failed') end\nfail()";
+
+    let bytecode = lua_state.compile(source, "script", false).unwrap();
+    let error = lua_state.execute_bytes("script", &bytecode, &mut IOReceiver{}).unwrap_err();
+    assert_eq!("script:1: failed", error.message);
+
+    let stripped = lua_state.compile(source, "script", true).unwrap();
+    assert!(stripped.len() < bytecode.len());
+    let error = lua_state.execute_bytes("script", &stripped, &mut IOReceiver{}).unwrap_err();
+    assert_eq!("failed", error.message);
+}
+
+
+#[test]
+fn compile_errors() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.compile("return +", "broken", false).unwrap_err();
+    assert_eq!(LuaErrorStatus::SyntaxError, error.status);
+    assert!(error.message.starts_with("broken:1:"), "{}", error.message);
+}
+
+
+#[test]
+fn execute_bytecode_file() {
+    let lua_state = LuaStateBuilder::new().chunk_mode(LuaChunkMode::TextOrBinary).build().unwrap();
+    let bytecode = lua_state.compile("return 'precompiled'", "script.luac", true).unwrap();
+    let path = env::temp_dir().join("lua_console_execute_bytecode_file.luac");
+    fs::write(&path, &bytecode).unwrap();
+
+    let result = lua_state.execute_file(&path, &mut IOReceiver{});
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("precompiled"))]), result);
+
+    let error = lua::LuaState::new().execute_file(&path, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::FileError, error.status);
+}
+
+
+#[test]
+fn load_bytecode_from_lua() {
+    let mut lua_state = lua::LuaState::new();
+    let chunk = "return load(string.dump(function() return 42 end), 'dumped', 'bt')";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::Nil,
+        LuaValue::String(LuaString::from("attempt to load a binary chunk (mode is 't')")),
+    ]), result);
+
+    // Source code can still be loaded, with its environment.
+    let result = lua_state.execute_chunk("return load('return x', 'source', 'b', {x = 7})()", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(7)]), result);
+
+    lua_state.set_chunk_mode(LuaChunkMode::TextOrBinary);
+    let result = lua_state.execute_chunk("return load(string.dump(function() return 42 end))()", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(42)]), result);
+}
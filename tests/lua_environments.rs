@@ -0,0 +1,138 @@
+extern crate lua_console;
+
+use std::env;
+use std::fs;
+
+use lua_console::lua;
+use lua_console::lua::{LuaResume, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn environments_have_separate_globals() {
+    let lua_state = lua::LuaState::new();
+    let first = lua_state.create_environment();
+    let second = lua_state.create_environment();
+
+    first.execute_chunk("x = 1 _G.y = 2", &mut IOReceiver{}).unwrap();
+    second.execute_chunk("x = 'second'", &mut IOReceiver{}).unwrap();
+
+    assert_eq!(Ok(vec![LuaValue::Integer(1), LuaValue::Integer(2)]), first.execute_chunk("x, y", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::String(LuaString::from("second")), LuaValue::Nil]),
+        second.execute_chunk("x, y", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Nil, LuaValue::Nil]), lua_state.execute_chunk("x, y", &mut IOReceiver{}));
+
+    assert_eq!(Ok(1), first.globals().get::<_, i64>("x"));
+}
+
+
+#[test]
+fn environments_fall_back_to_globals() {
+    let lua_state = lua::LuaState::new();
+    lua_state.set_global("shared", 42).unwrap();
+    let environment = lua_state.create_environment();
+
+    let result = environment.execute_chunk("shared, string.upper('lua')", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(42), LuaValue::String(LuaString::from("LUA"))]), result);
+
+    environment.execute_chunk("shared = 7", &mut IOReceiver{}).unwrap();
+    assert_eq!(Ok(vec![LuaValue::Integer(7)]), environment.execute_chunk("shared", &mut IOReceiver{}));
+    assert_eq!(Ok(42), lua_state.get_global::<i64>("shared"));
+}
+
+
+#[test]
+fn environments_fall_back_to_other_environments() {
+    let lua_state = lua::LuaState::new();
+    let outer = lua_state.create_environment();
+    outer.execute_chunk("x = 1 y = 2", &mut IOReceiver{}).unwrap();
+
+    let inner = outer.create_environment();
+    inner.globals().set("y", "inner").unwrap();
+    inner.execute_chunk("z = x + 2", &mut IOReceiver{}).unwrap();
+
+    assert_eq!(Ok(vec![LuaValue::Integer(1), LuaValue::String(LuaString::from("inner")), LuaValue::Integer(3)]),
+        inner.execute_chunk("x, y, z", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Integer(2), LuaValue::Nil, LuaValue::Integer(4)]),
+        outer.execute_chunk("y, z, #string.rep('a', 4)", &mut IOReceiver{}));
+}
+
+
+#[test]
+fn environments_hide_their_metatable() {
+    let lua_state = lua::LuaState::new();
+    let first = lua_state.create_environment();
+    let second = lua_state.create_environment();
+
+    assert_eq!(Ok(vec![LuaValue::Boolean(false)]), first.execute_chunk("getmetatable(_G)", &mut IOReceiver{}));
+    assert!(first.execute_chunk("getmetatable(_G).__index.leaked = true", &mut IOReceiver{}).is_err());
+    assert!(first.execute_chunk("setmetatable(_G, nil)", &mut IOReceiver{}).is_err());
+
+    assert_eq!(Ok(vec![LuaValue::Nil]), second.execute_chunk("leaked", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Nil]), lua_state.execute_chunk("leaked", &mut IOReceiver{}));
+}
+
+
+#[test]
+fn chunks_loaded_in_environment_run_in_it() {
+    let lua_state = lua::LuaState::new();
+    let first = lua_state.create_environment();
+    let second = lua_state.create_environment();
+
+    first.execute_chunk("load('leaked = 1')()", &mut IOReceiver{}).unwrap();
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), first.execute_chunk("leaked", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Nil]), second.execute_chunk("leaked", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Nil]), lua_state.execute_chunk("leaked", &mut IOReceiver{}));
+
+    // An environment given by the caller is still used.
+    let result = first.execute_chunk("local t = {} load('x = 2', 'chunk', 't', t)() return t.x, x", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(2), LuaValue::Nil]), result);
+
+    let path = env::temp_dir().join("lua_console_chunks_loaded_in_environment_run_in_it.lua");
+    fs::write(&path, "count = (count or 0) + 1 return count").unwrap();
+    let chunk = format!("dofile({:?}) return loadfile({:?})()", path.to_str().unwrap(), path.to_str().unwrap());
+    let result = first.execute_chunk(&chunk, &mut IOReceiver{});
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), result);
+    assert_eq!(Ok(vec![LuaValue::Nil]), second.execute_chunk("count", &mut IOReceiver{}));
+
+    let error = first.execute_chunk("dofile('lua_console_missing_file.lua')", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("lua_console_missing_file.lua"), "{}", error.message);
+}
+
+
+#[test]
+fn threads_and_files_run_in_environment() {
+    let lua_state = lua::LuaState::new();
+    let environment = lua_state.create_environment();
+
+    let thread = environment.create_thread("session", "count = 1 coroutine.yield() count = count + 1 return count").unwrap();
+    assert_eq!(LuaResume::Yielded(vec![]), thread.resume((), &mut IOReceiver{}));
+    assert_eq!(LuaResume::Finished(vec![LuaValue::Integer(2)]), thread.resume((), &mut IOReceiver{}));
+
+    let path = env::temp_dir().join("lua_console_threads_and_files_run_in_environment.lua");
+    fs::write(&path, "loaded = count * 10").unwrap();
+    let result = environment.execute_file(&path, &mut IOReceiver{});
+    fs::remove_file(&path).unwrap();
+    assert_eq!(Ok(vec![]), result);
+
+    assert_eq!(Ok(vec![LuaValue::Integer(20)]), environment.execute_chunk("loaded", &mut IOReceiver{}));
+    assert_eq!(Ok(vec![LuaValue::Nil]), lua_state.execute_chunk("loaded", &mut IOReceiver{}));
+}
+
+
+#[test]
+fn errors_name_environment_chunks() {
+    let lua_state = lua::LuaState::new();
+    let environment = lua_state.create_environment();
+    let error = environment.execute_named_chunk("stdin:1", "error('failed')", &mut IOReceiver{}).unwrap_err();
+    assert_eq!("stdin:1:1: failed", error.message);
+}
@@ -1,13 +1,14 @@
 extern crate lua_console;
 
 use lua_console::lua;
+use lua_console::lua::LuaString;
 
 
 struct IOReceiver;
 
 
 impl lua::LuaIO for IOReceiver {
-    fn on_print(&mut self, _values: Vec<String>) {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
     }
 }
 
@@ -68,3 +69,56 @@ fn syntax_error() {
 
     test_case.run();
 }
+
+
+#[test]
+fn error_location_and_traceback() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "local function inner()\n  error('oops')\nend\nlocal function outer()\n  inner()\nend\nouter()";
+    let error = lua_state.execute_named_chunk("test", chunk, &mut IOReceiver{}).unwrap_err();
+
+    assert_eq!("test:2: oops", error.message);
+    assert_eq!(Some(String::from("test")), error.chunk_name);
+    assert_eq!(Some(2), error.line);
+
+    let frames: Vec<(Option<&str>, &str, Option<u32>, lua::LuaFunctionKind)> = error.traceback.iter()
+        .map(|frame| (frame.function_name.as_deref(), frame.source.as_str(), frame.current_line, frame.what))
+        .collect();
+    assert_eq!(
+        vec![
+            (Some("error"), "[C]", None, lua::LuaFunctionKind::C),
+            (Some("inner"), "test", Some(2), lua::LuaFunctionKind::Lua),
+            (Some("outer"), "test", Some(5), lua::LuaFunctionKind::Lua),
+            (None, "test", Some(7), lua::LuaFunctionKind::Main),
+        ],
+        frames
+    );
+
+    let display = error.to_string();
+    assert!(display.starts_with("test:2: oops\nstack traceback:\n\t[C]: in function 'error'"), "{}", display);
+    assert!(display.contains("\n\ttest:7: in main chunk"), "{}", display);
+}
+
+
+#[test]
+fn error_location_without_position() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_named_chunk("test", "local t = {}\nerror(setmetatable(t, {__tostring = function() return 'custom' end}))", &mut IOReceiver{}).unwrap_err();
+    assert_eq!("custom", error.message);
+    assert_eq!(Some(String::from("test")), error.chunk_name);
+    assert_eq!(Some(2), error.line);
+
+    let error = lua_state.execute_named_chunk("test", "error({})", &mut IOReceiver{}).unwrap_err();
+    assert_eq!("(error object is a table value)", error.message);
+}
+
+
+#[test]
+fn syntax_error_location() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_named_chunk("stdin:3", "local x = 1\nx = {]", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::SyntaxError, error.status);
+    assert_eq!(Some(String::from("stdin:3")), error.chunk_name);
+    assert_eq!(Some(2), error.line);
+    assert!(error.traceback.is_empty());
+}
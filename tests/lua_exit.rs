@@ -0,0 +1,102 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaResume, LuaStateBuilder, LuaString, LuaValue};
+
+
+struct IOReceiver {
+    printed: Vec<LuaString>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
+        self.printed.append(&mut values);
+    }
+}
+
+
+fn io() -> IOReceiver {
+    IOReceiver{ printed: Vec::new() }
+}
+
+
+#[test]
+fn exit_stops_chunk() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io();
+    let error = lua_state.execute_chunk("print('before') os.exit(3) print('after')", &mut io).unwrap_err();
+    assert_eq!(LuaErrorStatus::Exit(3), error.status);
+    assert_eq!(vec![LuaString::from("before")], io.printed);
+
+    // The state can go on to execute further chunks.
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), lua_state.execute_chunk("1", &mut io));
+}
+
+
+#[test]
+fn exit_codes() {
+    let lua_state = lua::LuaState::new();
+    let chunks = [("os.exit()", 0), ("os.exit(true)", 0), ("os.exit(false)", 1), ("os.exit(42, true)", 42)];
+    for &(chunk, code) in &chunks {
+        let error = lua_state.execute_chunk(chunk, &mut io()).unwrap_err();
+        assert_eq!(LuaErrorStatus::Exit(code), error.status, "{}", chunk);
+    }
+
+    let error = lua_state.execute_named_chunk("test", "os.exit({})", &mut io()).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert_eq!("test:1: bad argument #1 to 'exit' (number expected, got table)", error.message);
+}
+
+
+#[test]
+fn exit_caught_by_pcall() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io();
+    let error = lua_state.execute_chunk("pcall(os.exit, 5) print('still running after exit')", &mut io).unwrap_err();
+    assert_eq!(LuaErrorStatus::Exit(5), error.status);
+    assert!(io.printed.is_empty());
+
+    // Code that resumed the coroutine calling os.exit stops as well.
+    let chunk = "pcall(coroutine.wrap(function() pcall(os.exit, 6) print('coroutine') end)) print('chunk')";
+    let error = lua_state.execute_chunk(chunk, &mut io).unwrap_err();
+    assert_eq!(LuaErrorStatus::Exit(6), error.status);
+    assert!(io.printed.is_empty());
+
+    // The state can go on to execute further chunks.
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), lua_state.execute_chunk("1", &mut io));
+}
+
+
+#[test]
+fn exit_from_thread() {
+    let lua_state = lua::LuaState::new();
+    let thread = lua_state.create_thread("thread", "coroutine.yield(1) os.exit(false)").unwrap();
+    assert_eq!(LuaResume::Yielded(vec![LuaValue::Integer(1)]), thread.resume((), &mut io()));
+
+    match thread.resume((), &mut io()) {
+        LuaResume::Error(error) => assert_eq!(LuaErrorStatus::Exit(1), error.status),
+        other => panic!("unexpected resume {:?}", other),
+    }
+}
+
+
+#[test]
+fn exit_not_available_in_sandbox() {
+    let lua_state = LuaStateBuilder::sandboxed().build().unwrap();
+    let result = lua_state.execute_chunk("os.exit", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Nil]), result);
+}
+
+
+#[test]
+fn exit_from_finalizer_while_closing() {
+    let lua_state = lua::LuaState::new();
+
+    // The invalid argument makes the original function fail before it exits the process.
+    let chunk = "x = setmetatable({}, {__gc = function() pcall(os.exit, {}) end})";
+    lua_state.execute_chunk(chunk, &mut io()).unwrap();
+
+    // The finalizer runs while the state is being closed, after its owner is gone.
+    drop(lua_state);
+}
@@ -0,0 +1,87 @@
+extern crate lua_console;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+/// Writes the given source to a file in the temporary directory, unique to this test process.
+fn write_source_file(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lua_console_{}_{}", process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+
+#[test]
+fn named_chunk_errors_use_name() {
+    let lua_state = lua::LuaState::new();
+    let result = lua_state.execute_named_chunk("stdin:3", "1 + 1", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), result);
+
+    let chunk = "local function fail()\n  error('oops')\nend\nfail()";
+    let error = lua_state.execute_named_chunk("stdin:3", chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert!(error.message.starts_with("stdin:3:2: oops"), "{}", error.message);
+    assert!(error.to_string().contains("stdin:3:4: in main chunk"), "{}", error);
+
+    let error = lua_state.execute_named_chunk("stdin:4", "x = {]", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::SyntaxError, error.status);
+    assert!(error.message.starts_with("stdin:4:1:"), "{}", error.message);
+}
+
+
+#[test]
+fn named_chunk_with_invalid_name() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_named_chunk("std\0in", "1", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::ConversionError, error.status);
+}
+
+
+#[test]
+fn execute_file() {
+    let path = write_source_file("execute_file.lua", "#!/usr/bin/env lua\nlocal x = 20\nreturn x + 1, ...");
+    let lua_state = lua::LuaState::new();
+    let result = lua_state.execute_file(&path, &mut IOReceiver{});
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(Ok(vec![LuaValue::Integer(21)]), result);
+}
+
+
+#[test]
+fn file_errors_use_path() {
+    let path = write_source_file("file_errors.lua", "#!/usr/bin/env lua\nlocal x = nil\nreturn x.y");
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_file(&path, &mut IOReceiver{}).unwrap_err();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    let location = format!("{}:3:", path.display());
+    assert!(error.message.starts_with(&location), "{}", error.message);
+}
+
+
+#[test]
+fn missing_file() {
+    let path = env::temp_dir().join("lua_console_missing_file.lua");
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_file(&path, &mut IOReceiver{}).unwrap_err();
+
+    assert_eq!(LuaErrorStatus::FileError, error.status);
+    assert!(error.message.starts_with("cannot open"), "{}", error.message);
+}
@@ -0,0 +1,110 @@
+extern crate lua_console;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lua_console::lua;
+use lua_console::lua::{LuaString, LuaValue, LuaVariadic};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn call_registered_function() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("add", |(a, b): (i64, i64)| Ok(a + b));
+
+    let result = lua_state.execute_chunk("add(2, 3)", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(5)]), result);
+}
+
+
+#[test]
+fn call_table_function() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_table_function("app", "greet", |name: String| Ok(format!("Hello, {}!", name)));
+    lua_state.register_table_function("app", "version", |()| Ok((1, 2, "beta")));
+
+    let result = lua_state.execute_chunk("app.greet('Lua'), app.version()", &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![
+            LuaValue::String(LuaString::from("Hello, Lua!")),
+            LuaValue::Integer(1),
+            LuaValue::Integer(2),
+            LuaValue::String(LuaString::from("beta")),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn closure_keeps_state_between_calls() {
+    let lua_state = lua::LuaState::new();
+    let counter = Rc::new(Cell::new(0));
+    let counter_in_lua = counter.clone();
+    lua_state.register_function("bump", move |()| {
+        counter_in_lua.set(counter_in_lua.get() + 1);
+        Ok(())
+    });
+
+    let result = lua_state.execute_chunk("for i = 1, 3 do bump() end", &mut IOReceiver{});
+    assert!(result.is_ok());
+    assert_eq!(3, counter.get());
+}
+
+
+#[test]
+fn optional_and_variadic_arguments() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("count", |(first, rest): (Option<String>, Option<bool>)| {
+        Ok(first.is_some() as i64 + rest.is_some() as i64)
+    });
+    lua_state.register_function("sum", |values: LuaVariadic<f64>| {
+        Ok(values.0.iter().sum::<f64>())
+    });
+
+    let result = lua_state.execute_chunk("count(), count('a', true), sum(1, 2.5, '3')", &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![LuaValue::Integer(0), LuaValue::Integer(2), LuaValue::Float(6.5)]),
+        result
+    );
+}
+
+
+#[test]
+fn bad_argument_raises_lua_error() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("add", |(a, b): (i64, i64)| Ok(a + b));
+
+    let error = lua_state.execute_chunk("add(1, 'x')", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::RuntimeError, error.status);
+    assert!(error.message.contains("bad argument #2 to 'add' (number expected, got string)"));
+
+    let result = lua_state.execute_chunk("pcall(add, 1.5, 2)", &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![
+            LuaValue::Boolean(false),
+            LuaValue::String(LuaString::from("bad argument #1 to 'add' (number has no integer representation)")),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn returned_error_raises_lua_error() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("fail", |message: String| -> Result<(), String> { Err(message) });
+
+    let error = lua_state.execute_chunk("fail('Something went wrong')", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::RuntimeError, error.status);
+    assert!(error.message.contains("Something went wrong"));
+}
@@ -0,0 +1,73 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn set_globals_from_rust() {
+    let lua_state = lua::LuaState::new();
+    let tricky = "It's \"quoted\"\nand ]] bracketed";
+    lua_state.set_global("message", tricky).unwrap();
+    lua_state.set_global("limit", 10).unwrap();
+    lua_state.set_global("enabled", true).unwrap();
+
+    let result = lua_state.execute_chunk("message, limit * 2, not enabled", &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![
+            LuaValue::String(LuaString::from(tricky)),
+            LuaValue::Integer(20),
+            LuaValue::Boolean(false),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn get_globals_from_rust() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("x = 5; name = 'console'; ratio = 0.5", &mut IOReceiver{}).unwrap();
+
+    assert_eq!(Ok(5), lua_state.get_global::<i64>("x"));
+    assert_eq!(Ok(String::from("console")), lua_state.get_global::<String>("name"));
+    assert_eq!(Ok(LuaValue::Float(0.5)), lua_state.get_global::<LuaValue>("ratio"));
+    assert_eq!(Ok(None), lua_state.get_global::<Option<i64>>("undefined"));
+}
+
+
+#[test]
+fn get_global_type_mismatch() {
+    let lua_state = lua::LuaState::new();
+    lua_state.set_global("name", "console").unwrap();
+
+    let error = lua_state.get_global::<bool>("name").unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::ConversionError, error.status);
+    assert_eq!("boolean expected, got string", error.message);
+
+    let error = lua_state.set_global("bad\0name", 1).unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::ConversionError, error.status);
+}
+
+
+#[test]
+fn global_metamethod_errors_are_returned() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk(
+        "setmetatable(_G, { __index = function(_, name) error('undefined global ' .. name) end })",
+        &mut IOReceiver{}
+    ).unwrap();
+
+    let error = lua_state.get_global::<LuaValue>("missing").unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::RuntimeError, error.status);
+    assert!(error.message.contains("undefined global missing"));
+}
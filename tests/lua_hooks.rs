@@ -0,0 +1,242 @@
+extern crate lua_console;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaExecutionLimits, LuaHookKind, LuaHookMask, LuaRef, LuaResume, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+/// Reads the values of the variables handed out by a hook.
+fn values(variables: &[(String, LuaRef)]) -> Vec<(String, LuaValue)> {
+    variables.iter().map(|(name, value)| (name.clone(), value.value())).collect()
+}
+
+
+#[test]
+fn line_events() {
+    let mut lua_state = lua::LuaState::new();
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let recorded = lines.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |event| {
+        assert_eq!(LuaHookKind::Line, event.kind);
+        recorded.borrow_mut().push((event.frame.source.clone(), event.frame.current_line));
+    });
+
+    let chunk = "local n = 0\nfor i = 1, 2 do\n  n = n + i\nend\nreturn n";
+    let result = lua_state.execute_named_chunk("test", chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(3)]), result);
+
+    let expected: Vec<_> = [1, 2, 3, 2, 3, 2, 5].iter()
+        .map(|&line| (String::from("test"), Some(line)))
+        .collect();
+    assert_eq!(expected, *lines.borrow());
+}
+
+
+#[test]
+fn call_and_return_events() {
+    let mut lua_state = lua::LuaState::new();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = events.clone();
+    lua_state.set_hook(LuaHookMask{ calls: true, returns: true, ..LuaHookMask::default() }, move |event| {
+        if event.frame.source == "test" {
+            recorded.borrow_mut().push((event.kind, event.frame.function_name.clone()));
+        }
+    });
+
+    let chunk = "local function add(a, b) return a + b end\nlocal function call() return add(1, 2) end\nlocal n = call()";
+    lua_state.execute_named_chunk("test", chunk, &mut IOReceiver{}).unwrap();
+
+    let expected = vec![
+        (LuaHookKind::Call, None),
+        (LuaHookKind::Call, Some(String::from("call"))),
+        (LuaHookKind::TailCall, None),
+        (LuaHookKind::Return, None),
+        (LuaHookKind::Return, None),
+    ];
+    assert_eq!(expected, *events.borrow());
+}
+
+
+#[test]
+fn count_events_with_execution_limits() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: Some(10_000),
+        timeout: None,
+    });
+
+    let counts = Rc::new(RefCell::new(0));
+    let recorded = counts.clone();
+    lua_state.set_hook(LuaHookMask{ count: Some(100), ..LuaHookMask::default() }, move |event| {
+        assert_eq!(LuaHookKind::Count, event.kind);
+        *recorded.borrow_mut() += 1;
+    });
+
+    let error = lua_state.execute_chunk("while true do end", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+    assert_eq!(100, *counts.borrow());
+}
+
+
+#[test]
+fn hook_interrupts_chunk() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, |event| {
+        if event.frame.current_line == Some(3) {
+            event.interrupt("stopped at line 3");
+        }
+    });
+
+    let chunk = "x = 1\nx = 2\nx = 3\nx = 4";
+    let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert_eq!("stopped at line 3", error.message);
+    assert_eq!(Ok(2), lua_state.get_global::<i64>("x"));
+}
+
+
+#[test]
+fn hook_applies_to_threads() {
+    let lua_state = lua::LuaState::new();
+    let thread = lua_state.create_thread("thread", "coroutine.yield()\nlocal x = 1\nreturn x").unwrap();
+
+    let mut lua_state = lua_state;
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let recorded = lines.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |event| {
+        recorded.borrow_mut().push(event.frame.current_line);
+    });
+
+    assert_eq!(LuaResume::Yielded(vec![]), thread.resume((), &mut IOReceiver{}));
+    assert_eq!(LuaResume::Finished(vec![LuaValue::Integer(1)]), thread.resume((), &mut IOReceiver{}));
+    assert_eq!(vec![Some(1), Some(2), Some(3)], *lines.borrow());
+}
+
+
+#[test]
+fn remove_hook() {
+    let mut lua_state = lua::LuaState::new();
+    let lines = Rc::new(RefCell::new(0));
+    let recorded = lines.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |_event| {
+        *recorded.borrow_mut() += 1;
+    });
+
+    lua_state.execute_chunk("local x = 1", &mut IOReceiver{}).unwrap();
+    assert_eq!(1, *lines.borrow());
+
+    lua_state.remove_hook();
+    lua_state.execute_chunk("local x = 1", &mut IOReceiver{}).unwrap();
+    assert_eq!(1, *lines.borrow());
+}
+
+
+#[test]
+fn hook_suspends_thread() {
+    let mut lua_state = lua::LuaState::new();
+    let thread = lua_state.create_thread("thread", "local x = 1\nx = x + 1\nreturn x").unwrap();
+
+    let locals = Rc::new(RefCell::new(Vec::new()));
+    let recorded = locals.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |event| {
+        if event.frame.current_line == Some(2) {
+            *recorded.borrow_mut() = event.locals();
+            assert!(event.suspend());
+        }
+    });
+
+    assert_eq!(LuaResume::Yielded(vec![]), thread.resume((), &mut IOReceiver{}));
+    assert_eq!(vec![(String::from("x"), LuaValue::Integer(1))], values(&locals.borrow()));
+
+    // The suspended line runs once the thread is resumed.
+    assert_eq!(LuaResume::Finished(vec![LuaValue::Integer(2)]), thread.resume((), &mut IOReceiver{}));
+}
+
+
+#[test]
+fn hook_cannot_suspend_outside_threads() {
+    let mut lua_state = lua::LuaState::new();
+    let suspended = Rc::new(RefCell::new(Vec::new()));
+    let recorded = suspended.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |event| {
+        recorded.borrow_mut().push(event.suspend());
+    });
+
+    // Neither the main state nor a coroutine started from Lua can be suspended by the hook.
+    let chunk = "local co = coroutine.wrap(function() return 1 end)\nreturn co()";
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), lua_state.execute_chunk(chunk, &mut IOReceiver{}));
+    assert_eq!(vec![false, false, false], *suspended.borrow());
+}
+
+
+#[test]
+fn hook_reads_upvalues_and_stack_depth() {
+    let mut lua_state = lua::LuaState::new();
+    let seen = Rc::new(RefCell::new(None));
+    let recorded = seen.clone();
+    lua_state.set_hook(LuaHookMask{ lines: true, ..LuaHookMask::default() }, move |event| {
+        if event.frame.function_name.as_deref() == Some("inner") {
+            *recorded.borrow_mut() = Some((event.upvalues(), event.locals(), event.stack_depth()));
+        }
+    });
+
+    let chunk = "local a, b = 1, 'two'\nlocal function inner(c) return a + c end\nlocal r = inner(3)\nreturn r";
+    assert_eq!(Ok(vec![LuaValue::Integer(4)]), lua_state.execute_chunk(chunk, &mut IOReceiver{}));
+
+    let (upvalues, locals, depth) = seen.borrow_mut().take().unwrap();
+    assert_eq!(vec![(String::from("a"), LuaValue::Integer(1))], values(&upvalues));
+    assert_eq!(vec![(String::from("c"), LuaValue::Integer(3))], values(&locals));
+    assert_eq!(2, depth);
+}
+
+
+#[test]
+fn hook_reads_stack() {
+    let mut lua_state = lua::LuaState::new();
+    let stacks = Rc::new(RefCell::new(Vec::new()));
+    let recorded = stacks.clone();
+    lua_state.set_hook(LuaHookMask{ calls: true, ..LuaHookMask::default() }, move |event| {
+        if event.frame.function_name.as_deref() == Some("inner") {
+            recorded.borrow_mut().push(event.stack().iter().map(|frame| frame.to_string()).collect::<Vec<_>>());
+        }
+    });
+
+    let chunk = "local function inner() end\nlocal function outer()\n  inner()\nend\nouter()";
+    lua_state.execute_named_chunk("test", chunk, &mut IOReceiver{}).unwrap();
+
+    let expected = vec![vec![
+        String::from("test:1: in function 'inner'"),
+        String::from("test:3: in function 'outer'"),
+        String::from("test:5: in main chunk"),
+    ]];
+    assert_eq!(expected, *stacks.borrow());
+}
+
+
+#[test]
+fn hook_set_while_closing() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("x = setmetatable({}, {__gc = function() local y = 1 end})", &mut IOReceiver{}).unwrap();
+
+    let events = Rc::new(RefCell::new(0));
+    let counted = events.clone();
+    lua_state.set_hook(LuaHookMask{ calls: true, lines: true, count: Some(1), ..LuaHookMask::default() }, move |_| {
+        *counted.borrow_mut() += 1;
+    });
+
+    // The finalizer runs while the state is being closed, after its owner is gone, so the hook
+    // is no longer called.
+    drop(lua_state);
+    assert_eq!(0, *events.borrow());
+}
@@ -0,0 +1,138 @@
+extern crate lua_console;
+
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+use lua_console::lua;
+use lua_console::lua::{LuaReadFormat, LuaString, LuaValue};
+
+
+struct IOReceiver {
+    input: Cursor<Vec<u8>>,
+    formats: Vec<LuaReadFormat>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+
+    fn on_read(&mut self, format: LuaReadFormat) -> Option<Vec<u8>> {
+        self.formats.push(format);
+        format.read_from(&mut self.input)
+    }
+}
+
+
+fn io(input: &str) -> IOReceiver {
+    IOReceiver{ input: Cursor::new(input.as_bytes().to_vec()), formats: Vec::new() }
+}
+
+
+fn string(value: &str) -> LuaValue {
+    LuaValue::String(LuaString::from(value))
+}
+
+
+#[test]
+fn read_formats() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io("first\nsecond\n42\nabcdef\nrest\n");
+    let chunk = "local line, with_break = io.read(), io.read('L') local number, rest = io.read('n', '*l') \
+                 local three, none = io.stdin:read(3, 0) return line, with_break, number, rest, three, none, io.read('a')";
+    let result = lua_state.execute_chunk(chunk, &mut io);
+
+    assert_eq!(Ok(vec![
+        string("first"),
+        string("second\n"),
+        LuaValue::Integer(42),
+        string("abcdef"),
+        string("res"),
+        string(""),
+        string("t\n"),
+    ]), result);
+    assert_eq!(vec![
+        LuaReadFormat::Line,
+        LuaReadFormat::LineWithBreak,
+        LuaReadFormat::Number,
+        LuaReadFormat::Line,
+        LuaReadFormat::Bytes(3),
+        LuaReadFormat::Bytes(0),
+        LuaReadFormat::All,
+    ], io.formats);
+}
+
+
+#[test]
+fn read_at_end_of_input() {
+    let lua_state = lua::LuaState::new();
+    let result = lua_state.execute_chunk("local last, none = io.read('l', 'l') return last, none, io.read('a'), io.read('n')", &mut io("last"));
+    assert_eq!(Ok(vec![string("last"), LuaValue::Nil, string(""), LuaValue::Nil]), result);
+
+    let result = lua_state.execute_chunk("io.read('n', 'l')", &mut io("not a number\nline\n"));
+    assert_eq!(Ok(vec![LuaValue::Nil]), result);
+
+    // Reading zero bytes tests for the end of the input.
+    let chunk = "local count = 0 while io.read(0) do io.read(1) count = count + 1 end return count, io.read(0)";
+    let result = lua_state.execute_chunk(chunk, &mut io("abc"));
+    assert_eq!(Ok(vec![LuaValue::Integer(3), LuaValue::Nil]), result);
+}
+
+
+#[test]
+fn read_lines() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "local lines = {} for line in io.lines() do lines[#lines + 1] = line end \
+                 for a, b in io.stdin:lines(1, 'l') do lines[#lines + 1] = b .. a end return table.concat(lines, ',')";
+    let result = lua_state.execute_chunk(chunk, &mut io("a\nb\nc"));
+    assert_eq!(Ok(vec![string("a,b,c")]), result);
+
+    let result = lua_state.execute_chunk(chunk, &mut io(""));
+    assert_eq!(Ok(vec![string("")]), result);
+}
+
+
+#[test]
+fn read_other_files() {
+    let lua_state = lua::LuaState::new();
+    let path = env::temp_dir().join("lua_console_read_other_files.txt");
+    fs::write(&path, "from file\n").unwrap();
+    lua_state.set_global("path", path.to_str().unwrap()).unwrap();
+
+    let mut io = io("from receiver\n");
+    let chunk = "local file = io.open(path) local line = file:read() file:close() \
+                 io.input(path) local default = io.read() io.input(io.stdin) \
+                 return line, default, io.read()";
+    let result = lua_state.execute_chunk(chunk, &mut io);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(Ok(vec![string("from file"), string("from file"), string("from receiver")]), result);
+    assert_eq!(vec![LuaReadFormat::Line], io.formats);
+}
+
+
+#[test]
+fn read_errors() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io("input\n");
+    let error = lua_state.execute_named_chunk("test", "io.read('l', 'x')", &mut io).unwrap_err();
+    assert_eq!("test:1: bad argument #2 to 'read' (invalid format)", error.message);
+
+    let error = lua_state.execute_named_chunk("test", "io.stdin:read({})", &mut io).unwrap_err();
+    assert_eq!("test:1: bad argument #1 to 'read' (invalid format)", error.message);
+    assert!(io.formats.is_empty());
+}
+
+
+#[test]
+fn read_from_finalizer_while_closing() {
+    let lua_state = lua::LuaState::new();
+
+    // The invalid format makes the original method fail before it reads anything.
+    let chunk = "x = setmetatable({}, {__gc = function() pcall(io.stdin.read, io.stdin, 'x') pcall(io.read, 'x') end})";
+    lua_state.execute_chunk(chunk, &mut io("")).unwrap();
+
+    // The finalizer runs while the state is being closed, after its owner is gone.
+    drop(lua_state);
+}
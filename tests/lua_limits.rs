@@ -0,0 +1,113 @@
+extern crate lua_console;
+
+use std::time::{Duration, Instant};
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaExecutionLimits, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn instruction_limit_stops_infinite_loop() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: Some(100_000),
+        timeout: None,
+    });
+
+    let error = lua_state.execute_chunk("while true do end", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+    assert!(error.message.contains("instruction limit exceeded"));
+
+    // The state is still usable, and the budget starts again for every chunk.
+    let result = lua_state.execute_chunk("local n = 0 for i = 1, 1000 do n = n + i end return n", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(500500)]), result);
+}
+
+
+#[test]
+fn timeout_stops_infinite_loop() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: None,
+        timeout: Some(Duration::from_millis(50)),
+    });
+
+    let start = Instant::now();
+    let error = lua_state.execute_chunk("while true do end", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+    assert!(error.message.contains("time limit exceeded"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+
+#[test]
+fn limit_cannot_be_caught_by_pcall() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: Some(100_000),
+        timeout: None,
+    });
+
+    let chunk = "while true do pcall(function() while true do end end) end";
+    let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+
+    let chunk = "local co = coroutine.wrap(function() while true do end end) co()";
+    let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+}
+
+
+#[test]
+fn runtime_errors_are_not_timeouts() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: Some(100_000),
+        timeout: Some(Duration::from_secs(10)),
+    });
+
+    let error = lua_state.execute_chunk("error('boom')", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+}
+
+
+#[test]
+fn limits_apply_to_coroutines_created_before() {
+    let mut lua_state = lua::LuaState::new();
+    let chunk = "spin = coroutine.create(function() while true do end end) \
+                 wrapped = coroutine.wrap(function() while true do end end)";
+    lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap();
+
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: None,
+        timeout: Some(Duration::from_millis(100)),
+    });
+    for chunk in &["coroutine.resume(spin)", "wrapped()"] {
+        let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+        assert_eq!(LuaErrorStatus::Timeout, error.status, "{}", chunk);
+    }
+}
+
+
+#[test]
+fn wrapped_coroutine_errors() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "local co = coroutine.wrap(function(a) local b = coroutine.yield(a + 1) error('oops ' .. b) end)\n\
+                 local first = co(1)\n\
+                 return first, co('again')";
+    let error = lua_state.execute_named_chunk("test", chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert_eq!("test:3: test:1: oops again", error.message);
+
+    let error = lua_state.execute_chunk("coroutine.wrap(42)", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("bad argument #1 to 'wrap' (function expected, got number)"), "{}", error.message);
+}
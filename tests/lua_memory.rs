@@ -0,0 +1,88 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn memory_usage_is_tracked() {
+    let lua_state = lua::LuaState::new();
+    let initial = lua_state.memory_usage();
+    assert!(initial.used > 0);
+    assert_eq!(None, initial.limit);
+
+    lua_state.execute_chunk("big = string.rep('x', 1000000)", &mut IOReceiver{}).unwrap();
+    let grown = lua_state.memory_usage();
+    assert!(grown.used >= initial.used + 1000000);
+    assert!(grown.peak >= grown.used);
+
+    lua_state.execute_chunk("big = nil; collectgarbage()", &mut IOReceiver{}).unwrap();
+    let collected = lua_state.memory_usage();
+    assert!(collected.used < grown.used);
+    assert_eq!(grown.peak, collected.peak);
+}
+
+
+#[test]
+fn memory_limit_stops_chunk() {
+    let lua_state = lua::LuaState::with_memory_limit(4 * 1024 * 1024);
+    assert_eq!(Some(4 * 1024 * 1024), lua_state.memory_usage().limit);
+
+    let chunk = "local t = {} for i = 1, 1e8 do t[i] = string.rep('x', 100) .. i end";
+    let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::OutOfMemory, error.status);
+    assert!(lua_state.memory_usage().peak <= 4 * 1024 * 1024);
+
+    // The garbage left behind is collected and the state can keep going.
+    let result = lua_state.execute_chunk("collectgarbage() return #string.rep('y', 1000)", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(1000)]), result);
+}
+
+
+#[test]
+fn memory_errors_can_be_caught_in_lua() {
+    let lua_state = lua::LuaState::with_memory_limit(1024 * 1024);
+    let chunk = "local ok, err = pcall(function() local t = {} for i = 1, 1e7 do t[i] = i end end) return ok, err";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![LuaValue::Boolean(false), LuaValue::String(LuaString::from("not enough memory"))]),
+        result
+    );
+}
+
+
+#[test]
+fn function_callable_after_running_out_of_memory() {
+    let lua_state = lua::LuaState::with_memory_limit(400_000);
+    lua_state.register_function("huge", |n: i64| Ok("x".repeat(n as usize)));
+
+    let error = lua_state.execute_chunk("huge(1000000)", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::OutOfMemory, error.status);
+
+    // Running out of memory while pushing the results does not leave the function borrowed.
+    let result = lua_state.execute_chunk("return #huge(10)", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(10)]), result);
+}
+
+
+#[test]
+fn memory_limit_kept_after_nested_call() {
+    let lua_state = lua::LuaState::with_memory_limit(4 * 1024 * 1024);
+    lua_state.register_function("call", |function: lua::LuaRef| {
+        function.call((), &mut IOReceiver{}).map(|_| ()).map_err(|error| error.message)
+    });
+
+    // The limit is still enforced on the chunk once the function it called has returned.
+    let chunk = "call(function() end) local t = {} for i = 1, 1e8 do t[i] = string.rep('x', 100) .. i end";
+    let error = lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::OutOfMemory, error.status);
+}
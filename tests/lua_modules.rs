@@ -0,0 +1,85 @@
+extern crate lua_console;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lua_console::lua;
+use lua_console::lua::{LuaError, LuaErrorStatus, LuaLibrary, LuaStateBuilder, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[test]
+fn require_module_source() {
+    let lua_state = lua::LuaState::new();
+    lua_state.add_module_source("greeting", include_str!("modules/greeting.lua")).unwrap();
+
+    let chunk = "local greeting = require 'greeting' return greeting.hello('lua'), greeting.loaded_as, \
+                 require('greeting') == greeting";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::String(LuaString::from("hello lua")),
+        LuaValue::String(LuaString::from("greeting")),
+        LuaValue::Boolean(true),
+    ]), result);
+
+    let result = lua_state.execute_chunk("greeting", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Nil]), result);
+}
+
+
+#[test]
+fn require_module_opener() {
+    let lua_state = lua::LuaState::new();
+    let times_opened = Rc::new(Cell::new(0));
+    let counter = times_opened.clone();
+    lua_state.add_module_opener("answers", move |lua_state| {
+        counter.set(counter.get() + 1);
+        let module = lua_state.create_table();
+        module.set("answer", 42)?;
+        Ok(module)
+    }).unwrap();
+    assert_eq!(0, times_opened.get());
+
+    let result = lua_state.execute_chunk("require('answers').answer + require('answers').answer", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(84)]), result);
+    assert_eq!(1, times_opened.get());
+}
+
+
+#[test]
+fn module_errors() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.add_module_source("broken", "return {").unwrap_err();
+    assert_eq!(LuaErrorStatus::SyntaxError, error.status);
+    assert!(error.message.starts_with("broken:1:"), "{}", error.message);
+
+    lua_state.add_module_source("failing", "error('cannot load')").unwrap();
+    lua_state.add_module_opener("refused", |_| {
+        Err(LuaError::new(LuaErrorStatus::RuntimeError, String::from("refused to open")))
+    }).unwrap();
+
+    let error = lua_state.execute_chunk("require 'failing'", &mut IOReceiver{}).unwrap_err();
+    assert_eq!("failing:1: cannot load", error.message);
+
+    let error = lua_state.execute_chunk("require 'refused'", &mut IOReceiver{}).unwrap_err();
+    assert_eq!("refused to open", error.message);
+
+    let error = lua_state.execute_chunk("require 'missing'", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("no embedded module 'missing'"), "{}", error.message);
+}
+
+
+#[test]
+fn modules_need_package_library() {
+    let lua_state = LuaStateBuilder::new().libraries(&[LuaLibrary::Base]).build().unwrap();
+    let error = lua_state.add_module_source("greeting", include_str!("modules/greeting.lua")).unwrap_err();
+    assert_eq!("cannot add a module without the package library", error.message);
+}
@@ -0,0 +1,122 @@
+extern crate lua_console;
+
+use std::env;
+use std::fs;
+
+use lua_console::lua;
+use lua_console::lua::{LuaRef, LuaStream, LuaString};
+
+
+struct IOReceiver {
+    printed: Vec<LuaString>,
+    written: Vec<(LuaStream, Vec<u8>)>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
+        self.printed.append(&mut values);
+    }
+
+    fn on_write(&mut self, stream: LuaStream, bytes: &[u8]) {
+        self.written.push((stream, bytes.to_vec()));
+    }
+}
+
+
+fn io() -> IOReceiver {
+    IOReceiver{ printed: Vec::new(), written: Vec::new() }
+}
+
+
+#[test]
+fn print_restored_after_execution() {
+    let lua_state = lua::LuaState::new();
+    let original_print = lua_state.get_global::<LuaRef>("print").unwrap();
+
+    lua_state.execute_chunk("saved_print = print", &mut io()).unwrap();
+    assert_eq!(original_print, lua_state.get_global::<LuaRef>("print").unwrap());
+
+    let mut io = io();
+    lua_state.execute_chunk("saved_print('later')", &mut io).unwrap();
+    assert_eq!(vec![LuaString::from("later")], io.printed);
+}
+
+
+#[test]
+fn print_from_resumed_coroutine() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "tick = coroutine.wrap(function() local print = print while true do print('tick') coroutine.yield() end end)";
+    lua_state.execute_chunk(chunk, &mut io()).unwrap();
+
+    let mut io = io();
+    lua_state.execute_chunk("tick() tick()", &mut io).unwrap();
+    assert_eq!(vec![LuaString::from("tick"), LuaString::from("tick")], io.printed);
+}
+
+
+#[test]
+fn replaced_print_kept() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("custom_print = function() end print = custom_print", &mut io()).unwrap();
+
+    let custom_print = lua_state.get_global::<LuaRef>("custom_print").unwrap();
+    assert_eq!(custom_print, lua_state.get_global::<LuaRef>("print").unwrap());
+}
+
+
+#[test]
+fn write_to_standard_streams() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io();
+    let chunk = "io.write('a', 1, ' ', 1.5, ' ', 2.0) io.stdout:write('b\\n'):write('c') io.stderr:write('oops')";
+    lua_state.execute_chunk(chunk, &mut io).unwrap();
+
+    assert_eq!(vec![
+        (LuaStream::Stdout, b"a1 1.5 2".to_vec()),
+        (LuaStream::Stdout, b"b\n".to_vec()),
+        (LuaStream::Stdout, b"c".to_vec()),
+        (LuaStream::Stderr, b"oops".to_vec()),
+    ], io.written);
+}
+
+
+#[test]
+fn write_to_other_files() {
+    let lua_state = lua::LuaState::new();
+    let path = env::temp_dir().join("lua_console_write_to_other_files.txt");
+    lua_state.set_global("path", path.to_str().unwrap()).unwrap();
+
+    let mut io = io();
+    let chunk = "local file = io.open(path, 'w') file:write('to file') file:close() \
+                 io.output(path) io.write('default') io.close() io.output(io.stdout) io.write('back')";
+    lua_state.execute_chunk(chunk, &mut io).unwrap();
+
+    assert_eq!("default", fs::read_to_string(&path).unwrap());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(vec![(LuaStream::Stdout, b"back".to_vec())], io.written);
+}
+
+
+#[test]
+fn write_errors() {
+    let lua_state = lua::LuaState::new();
+    let mut io = io();
+    let error = lua_state.execute_named_chunk("test", "io.write('a', {})", &mut io).unwrap_err();
+    assert_eq!("test:1: bad argument #2 to 'write' (string expected, got table)", error.message);
+
+    let error = lua_state.execute_named_chunk("test", "io.stderr:write(true)", &mut io).unwrap_err();
+    assert_eq!("test:1: bad argument #1 to 'write' (string expected, got boolean)", error.message);
+    assert!(io.written.is_empty());
+}
+
+
+#[test]
+fn write_from_finalizer_while_closing() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "x = setmetatable({}, {__gc = function() io.write('') io.stdout:write('') end})";
+    lua_state.execute_chunk(chunk, &mut io()).unwrap();
+
+    // The finalizer runs while the state is being closed, after its owner is gone.
+    drop(lua_state);
+}
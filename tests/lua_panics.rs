@@ -0,0 +1,135 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaReadFormat, LuaStream, LuaString, LuaUserData, LuaUserDataMethods, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+struct PanickingIOReceiver;
+
+
+impl lua::LuaIO for PanickingIOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+        panic!("printer is broken");
+    }
+
+    fn on_write(&mut self, _stream: LuaStream, _bytes: &[u8]) {
+        panic!("writer is broken");
+    }
+
+    fn on_read(&mut self, _format: LuaReadFormat) -> Option<Vec<u8>> {
+        panic!("reader is broken");
+    }
+}
+
+
+struct Fragile;
+
+
+impl LuaUserData for Fragile {
+    fn type_name() -> &'static str {
+        "Fragile"
+    }
+
+    fn add_methods(methods: &mut LuaUserDataMethods<Fragile>) {
+        methods.add_method("poke", |_: &Fragile, ()| -> Result<(), String> { panic!("poked") });
+    }
+}
+
+
+impl Drop for Fragile {
+    fn drop(&mut self) {
+        panic!("dropped");
+    }
+}
+
+
+#[test]
+fn panic_in_registered_function() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("explode", |()| -> Result<(), String> { panic!("boom") });
+
+    let error = lua_state.execute_chunk("explode()", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Panic, error.status);
+    assert_eq!("panic: boom", error.message);
+    assert_eq!(Some(1), error.line);
+
+    // The state is still usable after the panic.
+    let result = lua_state.execute_chunk("1 + 1", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), result);
+}
+
+
+#[test]
+fn panic_can_be_caught_by_pcall() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("explode", |value: i64| -> Result<(), String> { panic!("bad value {}", value) });
+
+    let chunk = "local ok, err = pcall(explode, 7) return ok, tostring(err)";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Boolean(false), LuaValue::String(LuaString::from("panic: bad value 7"))]), result);
+}
+
+
+#[test]
+fn panic_in_print_receiver() {
+    let lua_state = lua::LuaState::new();
+
+    let error = lua_state.execute_chunk("print('hello')", &mut PanickingIOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Panic, error.status);
+    assert_eq!("panic: printer is broken", error.message);
+
+    let result = lua_state.execute_chunk("print('hello') return 1", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(1)]), result);
+}
+
+
+#[test]
+fn panic_in_write_receiver() {
+    let lua_state = lua::LuaState::new();
+
+    let chunk = "local ok, err = pcall(io.write, 'hello', 42) return ok, tostring(err)";
+    let result = lua_state.execute_chunk(chunk, &mut PanickingIOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::Boolean(false),
+        LuaValue::String(LuaString::from("panic: writer is broken")),
+    ]), result);
+}
+
+
+#[test]
+fn panic_in_read_receiver() {
+    let lua_state = lua::LuaState::new();
+
+    let chunk = "local ok, err = pcall(io.read, 'l', 'n') return ok, tostring(err)";
+    let result = lua_state.execute_chunk(chunk, &mut PanickingIOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::Boolean(false),
+        LuaValue::String(LuaString::from("panic: reader is broken")),
+    ]), result);
+}
+
+#[test]
+fn panic_in_userdata_method_and_drop() {
+    let lua_state = lua::LuaState::new();
+    lua_state.set_global("fragile", lua_state.create_userdata(Fragile)).unwrap();
+
+    let error = lua_state.execute_chunk("fragile:poke()", &mut IOReceiver{}).unwrap_err();
+    assert_eq!(LuaErrorStatus::Panic, error.status);
+    assert_eq!("panic: poked", error.message);
+
+    // A panic while the value is dropped must not unwind through the garbage collector.
+    let result = lua_state.execute_chunk("fragile = nil collectgarbage()", &mut IOReceiver{});
+    assert!(result.is_err());
+
+    let result = lua_state.execute_chunk("1 + 1", &mut IOReceiver{});
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), result);
+}
@@ -0,0 +1,129 @@
+extern crate lua_console;
+
+use std::time::Duration;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaExecutionLimits, LuaRef, LuaString, LuaTable, LuaValue};
+
+
+struct IOReceiver {
+    printed: Vec<LuaString>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
+        self.printed.append(&mut values);
+    }
+}
+
+
+fn io() -> IOReceiver {
+    IOReceiver{ printed: Vec::new() }
+}
+
+
+#[test]
+fn call_function_created_by_chunk() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "local count = 0 return function(step) count = count + step print(count) return count end";
+    let counter = lua_state.execute_chunk_refs(chunk, &mut io()).unwrap().remove(0);
+
+    let mut io = io();
+    assert_eq!(Ok(vec![LuaValue::Integer(2)]), counter.call(2, &mut io));
+    assert_eq!(Ok(vec![LuaValue::Integer(5)]), counter.call(3, &mut io));
+    assert_eq!(vec![LuaString::from("2"), LuaString::from("5")], io.printed);
+}
+
+
+#[test]
+fn push_value_back_into_lua() {
+    let lua_state = lua::LuaState::new();
+    let table = lua_state.execute_chunk_refs("{ answer = 42 }", &mut io()).unwrap().remove(0);
+
+    lua_state.set_global("kept", &table).unwrap();
+    let result = lua_state.execute_chunk("kept.answer", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Integer(42)]), result);
+
+    assert_eq!(Ok(42), table.get::<LuaTable>().unwrap().get::<_, i64>("answer"));
+    assert_eq!(table, lua_state.get_global::<LuaRef>("kept").unwrap());
+    assert_eq!("table", table.value().type_name());
+}
+
+
+#[test]
+fn references_keep_values_alive() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "weak = setmetatable({}, { __mode = 'v' }) weak[1] = {} return weak[1]";
+    let result = lua_state.execute_chunk_refs(chunk, &mut io()).unwrap();
+
+    let result_after_gc = lua_state.execute_chunk("collectgarbage() return weak[1] ~= nil", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Boolean(true)]), result_after_gc);
+
+    drop(result);
+    let result_after_gc = lua_state.execute_chunk("collectgarbage() return weak[1] ~= nil", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Boolean(false)]), result_after_gc);
+}
+
+
+#[test]
+fn plain_results_do_not_keep_values_alive() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "weak = setmetatable({}, { __mode = 'v' }) weak[1] = {} return weak[1]";
+    let result = lua_state.execute_chunk(chunk, &mut io()).unwrap();
+    assert_eq!("table", result[0].type_name());
+
+    let result_after_gc = lua_state.execute_chunk("collectgarbage() return weak[1] ~= nil", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Boolean(false)]), result_after_gc);
+}
+
+
+#[test]
+fn call_errors() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_named_chunk("test", "fail = function(x) error('bad ' .. x) end", &mut io()).unwrap();
+    let function: LuaRef = lua_state.get_global("fail").unwrap();
+
+    let error = function.call("input", &mut io()).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert_eq!("test:1: bad input", error.message);
+    assert!(!error.traceback.is_empty());
+
+    let table = lua_state.execute_chunk_refs("{}", &mut io()).unwrap().remove(0);
+    let error = table.call((), &mut io()).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+}
+
+
+#[test]
+fn nested_call_keeps_limits() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: None,
+        timeout: Some(Duration::from_millis(300)),
+    });
+    lua_state.register_function("call", |function: LuaRef| {
+        function.call((), &mut io()).map(|_| ()).map_err(|error| error.message)
+    });
+
+    // The time limit is still enforced on the chunk once the function it called has returned.
+    let error = lua_state.execute_chunk("call(function() end) while true do end", &mut io()).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+
+    // Nested calls count against the limits of the chunk that made them.
+    let chunk = "local spin = function() while true do end end return pcall(call, spin)";
+    let error = lua_state.execute_chunk(chunk, &mut io()).unwrap_err();
+    assert_eq!(LuaErrorStatus::Timeout, error.status);
+}
+
+
+#[test]
+fn references_from_different_states() {
+    let first_state = lua::LuaState::new();
+    let second_state = lua::LuaState::new();
+    let first: LuaRef = first_state.get_global("print").unwrap();
+    let second: LuaRef = second_state.get_global("print").unwrap();
+
+    assert_eq!(first, first_state.get_global::<LuaRef>("print").unwrap());
+    assert_ne!(first, second);
+}
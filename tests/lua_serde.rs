@@ -0,0 +1,146 @@
+#![cfg(feature = "serde")]
+
+extern crate lua_console;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::HashMap;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaSerde, LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Shape {
+    Point,
+    Circle(f64),
+    Rectangle{ width: f64, height: f64 },
+}
+
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Config {
+    name: String,
+    retries: u32,
+    ratio: f64,
+    tags: Vec<String>,
+    limits: HashMap<String, i64>,
+    shapes: Vec<Shape>,
+    comment: Option<String>,
+}
+
+
+fn config() -> Config {
+    let mut limits = HashMap::new();
+    limits.insert(String::from("memory"), 1024);
+
+    Config{
+        name: String::from("console"),
+        retries: 3,
+        ratio: 0.5,
+        tags: vec![String::from("a"), String::from("b")],
+        limits,
+        shapes: vec![Shape::Point, Shape::Circle(2.0), Shape::Rectangle{ width: 1.0, height: 2.0 }],
+        comment: None,
+    }
+}
+
+
+#[test]
+fn serialize_into_lua() {
+    let lua_state = lua::LuaState::new();
+    lua_state.set_global("config", lua_state.serialize(&config()).unwrap()).unwrap();
+
+    let chunk = "return config.name, config.retries, #config.tags, config.tags[2], config.limits.memory, \
+                 config.shapes[1], config.shapes[2].Circle, config.shapes[3].Rectangle.height, config.comment";
+    let result = lua_state.execute_chunk(chunk, &mut IOReceiver{});
+    assert_eq!(Ok(vec![
+        LuaValue::String(LuaString::from("console")),
+        LuaValue::Integer(3),
+        LuaValue::Integer(2),
+        LuaValue::String(LuaString::from("b")),
+        LuaValue::Integer(1024),
+        LuaValue::String(LuaString::from("Point")),
+        LuaValue::Float(2.0),
+        LuaValue::Float(2.0),
+        LuaValue::Nil,
+    ]), result);
+}
+
+
+#[test]
+fn round_trip() {
+    let lua_state = lua::LuaState::new();
+    lua_state.set_global("config", lua_state.serialize(&config()).unwrap()).unwrap();
+
+    let LuaSerde(round_tripped) = lua_state.get_global::<LuaSerde<Config>>("config").unwrap();
+    assert_eq!(config(), round_tripped);
+}
+
+
+#[test]
+fn deserialize_from_lua() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "config = { name = 'lua', retries = 2.0, ratio = 1, tags = {}, limits = { cpu = 5 }, \
+                 shapes = { { Circle = 1.5 } }, comment = 'hi', ignored = true }";
+    lua_state.execute_chunk(chunk, &mut IOReceiver{}).unwrap();
+
+    let LuaSerde(config) = lua_state.get_global::<LuaSerde<Config>>("config").unwrap();
+    assert_eq!("lua", config.name);
+    assert_eq!(2, config.retries);
+    assert_eq!(1.0, config.ratio);
+    assert!(config.tags.is_empty());
+    assert_eq!(Some(&5), config.limits.get("cpu"));
+    assert_eq!(vec![Shape::Circle(1.5)], config.shapes);
+    assert_eq!(Some(String::from("hi")), config.comment);
+}
+
+
+#[test]
+fn deserialize_errors() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("cyclic = {} cyclic.self = cyclic", &mut IOReceiver{}).unwrap();
+    let error = lua_state.get_global::<LuaSerde<HashMap<String, i64>>>("cyclic").unwrap_err();
+    assert_eq!(LuaErrorStatus::ConversionError, error.status);
+    assert_eq!("cannot deserialize a table that contains itself", error.message);
+
+    lua_state.execute_chunk("numbered = { [1] = 'a', name = 'b' }", &mut IOReceiver{}).unwrap();
+    let error = lua_state.get_global::<LuaSerde<HashMap<String, String>>>("numbered").unwrap_err();
+    assert_eq!("invalid table key: string expected, got number", error.message);
+
+    let error = lua_state.get_global::<LuaSerde<Config>>("numbered").unwrap_err();
+    assert_eq!("invalid table key: string expected, got number", error.message);
+
+    let error = lua_state.get_global::<LuaSerde<Vec<i64>>>("numbered").unwrap_err();
+    assert_eq!("invalid type: string \"a\", expected i64", error.message);
+
+    let error = lua_state.get_global::<LuaSerde<Config>>("type").unwrap_err();
+    assert_eq!("table expected, got function", error.message);
+
+    lua_state.execute_chunk("shape = { Circle = 1, Point = true }", &mut IOReceiver{}).unwrap();
+    let error = lua_state.get_global::<LuaSerde<Shape>>("shape").unwrap_err();
+    assert_eq!("string or single entry table for enum Shape expected, got table", error.message);
+}
+
+
+#[test]
+fn serialize_errors() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.serialize(&u64::MAX).err().unwrap();
+    assert_eq!(LuaErrorStatus::ConversionError, error.status);
+    assert_eq!("integer 18446744073709551615 is too large for a Lua integer", error.message);
+
+    let mut map: HashMap<Option<String>, i64> = HashMap::new();
+    map.insert(None, 1);
+    let error = lua_state.serialize(&map).err().unwrap();
+    assert_eq!("map key serializes to nil", error.message);
+}
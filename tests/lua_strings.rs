@@ -0,0 +1,73 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaString, LuaValue};
+
+
+struct IOReceiver {
+    values: Vec<LuaString>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
+        self.values.append(&mut values);
+    }
+}
+
+
+#[test]
+fn strings_keep_exact_bytes() {
+    let lua_state = lua::LuaState::new();
+    let mut io = IOReceiver{ values: Vec::new() };
+
+    let result = lua_state.execute_chunk("string.char(200), 'a\\0b', '\\xff'", &mut io);
+    assert_eq!(
+        Ok(vec![
+            LuaValue::String(LuaString::from(vec![200u8])),
+            LuaValue::String(LuaString::from(&b"a\0b"[..])),
+            LuaValue::String(LuaString::from(vec![0xffu8])),
+        ]),
+        result
+    );
+
+    lua_state.execute_chunk("print('\\xff', 'x\\0y')", &mut io).unwrap();
+    assert_eq!(vec![LuaString::from(vec![0xffu8]), LuaString::from(&b"x\0y"[..])], io.values);
+}
+
+
+#[test]
+fn lossy_strict_and_escaped_conversions() {
+    let value = LuaString::from(&b"caf\xc3\xa9 \xff!"[..]);
+    assert!(value.to_str().is_err());
+    assert_eq!("caf\u{e9} \u{fffd}!", value.to_string_lossy());
+    assert_eq!("caf\u{e9} \\xff!", value.to_string_escaped());
+    assert_eq!("caf\u{e9} \\xff!", value.to_string());
+
+    let value = LuaString::from("plain");
+    assert_eq!(Ok("plain"), value.to_str());
+    assert_eq!(5, value.len());
+}
+
+
+#[test]
+fn strings_as_function_arguments() {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("byte_count", |value: LuaString| Ok(value.len()));
+    lua_state.register_function("text_length", |value: String| Ok(value.len()));
+
+    let result = lua_state.execute_chunk("byte_count('\\xff\\0\\xfe')", &mut IOReceiver{ values: Vec::new() });
+    assert_eq!(Ok(vec![LuaValue::Integer(3)]), result);
+
+    let error = lua_state.execute_chunk("text_length('\\xff')", &mut IOReceiver{ values: Vec::new() }).unwrap_err();
+    assert!(error.message.contains("string is not valid UTF-8"), "{}", error.message);
+}
+
+
+#[test]
+fn errors_with_invalid_utf8() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.execute_chunk("error('bad \\xff byte\\0end', 0)", &mut IOReceiver{ values: Vec::new() }).unwrap_err();
+    assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+    assert_eq!("bad \\xff byte\0end", error.message);
+}
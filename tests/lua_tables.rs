@@ -0,0 +1,147 @@
+extern crate lua_console;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lua_console::lua;
+use lua_console::lua::{LuaString, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+/// Sets a flag once dropped.
+struct DropFlag(Rc<Cell<bool>>);
+
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+
+#[test]
+fn build_table_in_rust() {
+    let lua_state = lua::LuaState::new();
+
+    let config = lua_state.create_table();
+    config.set("name", "console").unwrap();
+    config.set("retries", 3).unwrap();
+
+    let servers = lua_state.create_table();
+    servers.set(1, "alpha").unwrap();
+    servers.set(2, "beta").unwrap();
+    config.set("servers", &servers).unwrap();
+
+    lua_state.globals().set("config", config).unwrap();
+
+    let result = lua_state.execute_chunk("config.name, config.retries, #config.servers", &mut IOReceiver{});
+    assert_eq!(
+        Ok(vec![
+            LuaValue::String(LuaString::from("console")),
+            LuaValue::Integer(3),
+            LuaValue::Integer(2),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn read_table_created_in_lua() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("t = { 10, 20, 30, answer = 42 }", &mut IOReceiver{}).unwrap();
+
+    let table: lua::LuaTable = lua_state.globals().get("t").unwrap();
+    assert_eq!(Ok(42), table.get::<_, i64>("answer"));
+    assert_eq!(Ok(3), table.len());
+    assert_eq!(3, table.raw_len());
+    assert!(!table.is_empty());
+    assert_eq!(Ok(vec![10, 20, 30]), table.ipairs::<i64>());
+
+    let mut pairs: Vec<(String, i64)> = table.pairs::<LuaValue, i64>()
+        .unwrap()
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    pairs.sort();
+    assert_eq!(
+        vec![
+            (String::from("1"), 10),
+            (String::from("2"), 20),
+            (String::from("3"), 30),
+            (String::from("answer"), 42),
+        ],
+        pairs
+    );
+}
+
+
+#[test]
+fn raw_access_skips_metamethods() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk(
+        "t = setmetatable({}, { __index = function() return 'default' end, __len = function() return 7 end })",
+        &mut IOReceiver{}
+    ).unwrap();
+
+    let table: lua::LuaTable = lua_state.globals().get("t").unwrap();
+    assert_eq!(Ok(String::from("default")), table.get("missing"));
+    assert_eq!(Ok(None), table.raw_get::<_, Option<String>>("missing"));
+    assert_eq!(Ok(7), table.len());
+    assert_eq!(0, table.raw_len());
+    assert!(table.is_empty());
+}
+
+
+#[test]
+fn metamethod_errors_are_returned() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk(
+        "t = setmetatable({}, { __newindex = function() error('read only') end })",
+        &mut IOReceiver{}
+    ).unwrap();
+
+    let table: lua::LuaTable = lua_state.globals().get("t").unwrap();
+    let error = table.set("x", 1).unwrap_err();
+    assert_eq!(lua::LuaErrorStatus::RuntimeError, error.status);
+    assert!(error.message.contains("read only"));
+
+    assert!(table.raw_set("x", 1).is_ok());
+    assert_eq!(lua::LuaErrorStatus::RuntimeError, table.raw_set(None::<i64>, 1).unwrap_err().status);
+    assert_eq!(lua::LuaErrorStatus::ConversionError, table.get::<_, bool>("x").unwrap_err().status);
+}
+
+
+#[test]
+fn handles_owned_by_lua_do_not_keep_state_open() {
+    let lua_state = lua::LuaState::new();
+    let dropped = Rc::new(Cell::new(false));
+    let globals = lua_state.globals();
+    let flag = DropFlag(dropped.clone());
+    lua_state.register_function("keep", move |()| {
+        let _ = (&globals, &flag);
+        Ok(())
+    });
+
+    // Closing the state drops the function, along with the handle it holds.
+    drop(lua_state);
+    assert!(dropped.get());
+}
+
+
+#[test]
+#[should_panic(expected = "Lua value used after its state was closed")]
+fn handles_cannot_be_used_after_state_is_dropped() {
+    let lua_state = lua::LuaState::new();
+    let table = lua_state.create_table();
+    drop(lua_state);
+    let _ = table.get::<_, i64>(1);
+}
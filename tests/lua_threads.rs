@@ -0,0 +1,105 @@
+extern crate lua_console;
+
+use lua_console::lua;
+use lua_console::lua::{LuaErrorStatus, LuaExecutionLimits, LuaResume, LuaString, LuaThread, LuaValue};
+
+
+struct IOReceiver {
+    printed: Vec<LuaString>,
+}
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, mut values: Vec<LuaString>) {
+        self.printed.append(&mut values);
+    }
+}
+
+
+fn io() -> IOReceiver {
+    IOReceiver{ printed: Vec::new() }
+}
+
+
+#[test]
+fn resume_until_finished() {
+    let lua_state = lua::LuaState::new();
+    let chunk = "local a = coroutine.yield(1, 2) print(a) local b = coroutine.yield(a * 10) return a + b";
+    let thread = lua_state.create_thread("test", chunk).unwrap();
+    assert!(thread.is_resumable());
+
+    let mut io = io();
+    assert_eq!(LuaResume::Yielded(vec![LuaValue::Integer(1), LuaValue::Integer(2)]), thread.resume((), &mut io));
+    assert_eq!(LuaResume::Yielded(vec![LuaValue::Integer(30)]), thread.resume(3, &mut io));
+    assert_eq!(vec![LuaString::from("3")], io.printed);
+    assert!(thread.is_resumable());
+
+    assert_eq!(LuaResume::Finished(vec![LuaValue::Integer(7)]), thread.resume(4, &mut io));
+    assert!(!thread.is_resumable());
+
+    match thread.resume((), &mut io) {
+        LuaResume::Error(error) => assert_eq!("cannot resume dead coroutine", error.message),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+
+#[test]
+fn expression_chunks_return_their_value() {
+    let lua_state = lua::LuaState::new();
+    let thread = lua_state.create_thread("test", "1 + 1").unwrap();
+    assert_eq!(LuaResume::Finished(vec![LuaValue::Integer(2)]), thread.resume((), &mut io()));
+}
+
+
+#[test]
+fn errors_in_threads() {
+    let lua_state = lua::LuaState::new();
+    let error = lua_state.create_thread("test", "return +").unwrap_err();
+    assert_eq!(LuaErrorStatus::SyntaxError, error.status);
+
+    let thread = lua_state.create_thread("test", "coroutine.yield()\nerror('boom')").unwrap();
+    assert_eq!(LuaResume::Yielded(vec![]), thread.resume((), &mut io()));
+
+    match thread.resume((), &mut io()) {
+        LuaResume::Error(error) => {
+            assert_eq!(LuaErrorStatus::RuntimeError, error.status);
+            assert_eq!("test:2: boom", error.message);
+            assert_eq!(Some(2), error.line);
+            assert!(!error.traceback.is_empty());
+        },
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(!thread.is_resumable());
+}
+
+
+#[test]
+fn resume_coroutine_created_in_lua() {
+    let lua_state = lua::LuaState::new();
+    lua_state.execute_chunk("co = coroutine.create(function(a) while true do a = coroutine.yield(a * 2) end end)", &mut io()).unwrap();
+
+    let thread: LuaThread = lua_state.get_global("co").unwrap();
+    assert_eq!(LuaResume::Yielded(vec![LuaValue::Integer(10)]), thread.resume(5, &mut io()));
+
+    let result = lua_state.execute_chunk("select(2, coroutine.resume(co, 21))", &mut io());
+    assert_eq!(Ok(vec![LuaValue::Integer(42)]), result);
+}
+
+
+#[test]
+fn limits_apply_to_threads() {
+    let mut lua_state = lua::LuaState::new();
+    lua_state.set_execution_limits(LuaExecutionLimits{
+        max_instructions: Some(100_000),
+        timeout: None,
+    });
+
+    let thread = lua_state.create_thread("test", "coroutine.yield() while true do end").unwrap();
+    assert_eq!(LuaResume::Yielded(vec![]), thread.resume((), &mut io()));
+
+    match thread.resume((), &mut io()) {
+        LuaResume::Error(error) => assert_eq!(LuaErrorStatus::Timeout, error.status),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
@@ -0,0 +1,174 @@
+extern crate lua_console;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lua_console::lua;
+use lua_console::lua::{LuaMetaMethod, LuaString, LuaUserData, LuaUserDataMethods, LuaUserDataRef, LuaValue};
+
+
+struct IOReceiver;
+
+
+impl lua::LuaIO for IOReceiver {
+    fn on_print(&mut self, _values: Vec<LuaString>) {
+    }
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+struct Vector {
+    x: f64,
+    y: f64,
+}
+
+
+impl LuaUserData for Vector {
+    fn type_name() -> &'static str {
+        "Vector"
+    }
+
+    fn add_methods(methods: &mut LuaUserDataMethods<Vector>) {
+        methods.add_field_getter("x", |vector: &Vector| Ok(vector.x));
+        methods.add_field_getter("y", |vector: &Vector| Ok(vector.y));
+        methods.add_field_setter("x", |vector: &mut Vector, x: f64| {
+            vector.x = x;
+            Ok(())
+        });
+
+        methods.add_method("length", |vector: &Vector, ()| Ok((vector.x * vector.x + vector.y * vector.y).sqrt()));
+        methods.add_method_mut("scale", |vector: &mut Vector, factor: f64| {
+            vector.x *= factor;
+            vector.y *= factor;
+            Ok(())
+        });
+
+        methods.add_meta_function(LuaMetaMethod::Add, |(a, b): (LuaUserDataRef<Vector>, LuaUserDataRef<Vector>)| {
+            let (a, b) = (a.borrow(), b.borrow());
+            Ok(Vector{ x: a.x + b.x, y: a.y + b.y })
+        });
+        methods.add_meta_function(LuaMetaMethod::Eq, |(a, b): (LuaUserDataRef<Vector>, LuaUserDataRef<Vector>)| {
+            Ok(*a.borrow() == *b.borrow())
+        });
+        methods.add_meta_function(LuaMetaMethod::Lt, |(a, b): (LuaUserDataRef<Vector>, LuaUserDataRef<Vector>)| {
+            let (a, b) = (a.borrow(), b.borrow());
+            Ok(a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y)
+        });
+        methods.add_meta_method(LuaMetaMethod::ToString, |vector: &Vector, ()| {
+            Ok(format!("({}, {})", vector.x, vector.y))
+        });
+    }
+}
+
+
+struct DropTracker {
+    dropped: Rc<Cell<bool>>,
+}
+
+
+impl LuaUserData for DropTracker {
+}
+
+
+impl Drop for DropTracker {
+    fn drop(&mut self) {
+        self.dropped.set(true);
+    }
+}
+
+
+fn vector_state() -> lua::LuaState {
+    let lua_state = lua::LuaState::new();
+    lua_state.register_function("vector", |(x, y): (f64, f64)| Ok(Vector{ x, y }));
+    lua_state
+}
+
+
+#[test]
+fn fields_and_methods() {
+    let lua_state = vector_state();
+    let result = lua_state.execute_chunk(
+        "local v = vector(3, 4); v.x = 6; v:scale(0.5); return v.x, v.y, v:length(), tostring(v)",
+        &mut IOReceiver{}
+    );
+
+    assert_eq!(
+        Ok(vec![
+            LuaValue::Float(3.0),
+            LuaValue::Float(2.0),
+            LuaValue::Float(13.0f64.sqrt()),
+            LuaValue::String(LuaString::from("(3, 2)")),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn metamethods() {
+    let lua_state = vector_state();
+    let result = lua_state.execute_chunk(
+        "local v = vector(1, 2) + vector(3, 4); return v.x, v.y, v == vector(4, 6), vector(1, 1) < v",
+        &mut IOReceiver{}
+    );
+
+    assert_eq!(
+        Ok(vec![
+            LuaValue::Float(4.0),
+            LuaValue::Float(6.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+        ]),
+        result
+    );
+}
+
+
+#[test]
+fn shared_by_reference() {
+    let lua_state = vector_state();
+    let vector = lua_state.create_userdata(Vector{ x: 1.0, y: 1.0 });
+    lua_state.set_global("v", &vector).unwrap();
+
+    lua_state.execute_chunk("v:scale(10)", &mut IOReceiver{}).unwrap();
+    assert_eq!(Vector{ x: 10.0, y: 10.0 }, *vector.borrow());
+
+    vector.borrow_mut().y = -5.0;
+    assert_eq!(Ok(vec![LuaValue::Float(-5.0)]), lua_state.execute_chunk("v.y", &mut IOReceiver{}));
+
+    let from_lua: LuaUserDataRef<Vector> = lua_state.get_global("v").unwrap();
+    assert_eq!(10.0, from_lua.borrow().x);
+}
+
+
+#[test]
+fn invalid_use_raises_lua_errors() {
+    let lua_state = vector_state();
+    lua_state.execute_chunk("v = vector(1, 2)", &mut IOReceiver{}).unwrap();
+
+    let error = lua_state.execute_chunk("v.z = 5", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("cannot set unknown field 'z' of Vector"));
+
+    let error = lua_state.execute_chunk("v.length(5)", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("bad argument #1 to 'length' (Vector expected, got number)"));
+
+    let error = lua_state.execute_chunk("v:scale('big')", &mut IOReceiver{}).unwrap_err();
+    assert!(error.message.contains("bad argument #2 to 'scale' (number expected, got string)"));
+
+    assert_eq!(Ok(vec![LuaValue::Boolean(false)]), lua_state.execute_chunk("getmetatable(v)", &mut IOReceiver{}));
+}
+
+
+#[test]
+fn value_dropped_when_collected() {
+    let lua_state = lua::LuaState::new();
+    let dropped = Rc::new(Cell::new(false));
+
+    let tracker = lua_state.create_userdata(DropTracker{ dropped: dropped.clone() });
+    lua_state.set_global("tracker", tracker).unwrap();
+    lua_state.execute_chunk("collectgarbage()", &mut IOReceiver{}).unwrap();
+    assert!(!dropped.get());
+
+    lua_state.execute_chunk("tracker = nil; collectgarbage()", &mut IOReceiver{}).unwrap();
+    assert!(dropped.get());
+}
@@ -0,0 +1,9 @@
+local greeting = {}
+
+function greeting.hello(name)
+    return 'hello ' .. name
+end
+
+greeting.loaded_as = ...
+
+return greeting
@@ -0,0 +1,73 @@
+extern crate lua_console;
+
+use lua_console::lua::{LuaFunctionKind, LuaStackFrame};
+use lua_console::profiler::{Profile, Profiler};
+
+
+fn frame(what: LuaFunctionKind, function_name: Option<&str>, source: &str, line_defined: Option<u32>) -> LuaStackFrame {
+    LuaStackFrame{
+        function_name: function_name.map(String::from),
+        source: String::from(source),
+        current_line: line_defined,
+        line_defined,
+        what,
+    }
+}
+
+
+fn folded_lines(profile: &Profile) -> Vec<String> {
+    let mut folded = Vec::new();
+    profile.write_folded(&mut folded).unwrap();
+    String::from_utf8(folded).unwrap().lines().map(String::from).collect()
+}
+
+
+#[test]
+fn fold_known_samples() {
+    let main = frame(LuaFunctionKind::Main, None, "stdin:1", None);
+    let add = frame(LuaFunctionKind::Lua, Some("add"), "utils.lua", Some(12));
+    let anonymous = frame(LuaFunctionKind::Lua, None, "stdin:1", Some(3));
+    let format = frame(LuaFunctionKind::C, Some("format"), "[C]", None);
+    let odd = frame(LuaFunctionKind::Lua, Some("a;b"), "stdin:1", Some(5));
+
+    let mut profile = Profiler::new().finish();
+    profile.record(&[add.clone(), main.clone()]);
+    profile.record(&[format.clone(), add.clone(), main.clone()]);
+    profile.record(&[add.clone(), main.clone()]);
+    profile.record(&[anonymous.clone(), main.clone()]);
+    profile.record(&[odd, main.clone()]);
+    profile.record(&[]);
+
+    assert_eq!(5, profile.num_samples());
+    assert_eq!(vec![
+        "main chunk (stdin:1);a:b (stdin:1:5) 1",
+        "main chunk (stdin:1);add (utils.lua:12) 2",
+        "main chunk (stdin:1);add (utils.lua:12);format [C] 1",
+        "main chunk (stdin:1);function (stdin:1:3) 1",
+    ], folded_lines(&profile));
+}
+
+
+#[test]
+fn hottest_functions_count_recursion_once() {
+    let main = frame(LuaFunctionKind::Main, None, "stdin:1", None);
+    let fib = frame(LuaFunctionKind::Lua, Some("fib"), "stdin:1", Some(1));
+    let print = frame(LuaFunctionKind::C, Some("print"), "[C]", None);
+
+    let mut profile = Profiler::new().finish();
+    profile.record(&[fib.clone(), fib.clone(), fib.clone(), main.clone()]);
+    profile.record(&[fib.clone(), fib.clone(), main.clone()]);
+    profile.record(&[fib.clone(), main.clone()]);
+    profile.record(&[print.clone(), main.clone()]);
+
+    let hottest: Vec<(String, usize, usize)> = profile.hottest_functions(10).into_iter()
+        .map(|function| (function.function, function.self_samples, function.total_samples))
+        .collect();
+    assert_eq!(vec![
+        (String::from("fib (stdin:1:1)"), 3, 3),
+        (String::from("print [C]"), 1, 1),
+        (String::from("main chunk (stdin:1)"), 0, 4),
+    ], hottest);
+
+    assert_eq!(1, profile.hottest_functions(1).len());
+}